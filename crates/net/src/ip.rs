@@ -26,16 +26,129 @@ pub struct Ipv6Hdr {
     pub dst_addr: [u8; 16],
 }
 
-#[repr(u8)]
-#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
-#[derive(Debug, Copy, Clone)]
-pub enum IpProto {
-    HopOpt = 0,
-    Ipv4 = 4,
-    Tcp = 6,
-    Udp = 17,
-    Ipv6 = 41,
-    Ipv6Route = 43,
-    Ipv6Frag = 44,
-    Ipv6Opts = 60,
+/// An IANA assigned internet protocol number. Unlike an enum every value is
+/// valid, so it can be read straight out of packet memory.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpProto(pub u8);
+
+impl IpProto {
+    pub const HOPOPT: Self = Self(0);
+    pub const ICMP: Self = Self(1);
+    pub const IGMP: Self = Self(2);
+    pub const IPV4: Self = Self(4);
+    pub const TCP: Self = Self(6);
+    pub const UDP: Self = Self(17);
+    pub const IPV6: Self = Self(41);
+    pub const IPV6_ROUTE: Self = Self(43);
+    pub const IPV6_FRAG: Self = Self(44);
+    pub const GRE: Self = Self(47);
+    pub const ESP: Self = Self(50);
+    pub const AH: Self = Self(51);
+    pub const ICMPV6: Self = Self(58);
+    pub const IPV6_NO_NEXT: Self = Self(59);
+    pub const IPV6_OPTS: Self = Self(60);
+    pub const OSPF: Self = Self(89);
+    pub const PIM: Self = Self(103);
+    pub const VRRP: Self = Self(112);
+    pub const L2TP: Self = Self(115);
+    pub const SCTP: Self = Self(132);
+    pub const UDPLITE: Self = Self(136);
+    pub const MPLS_IN_IP: Self = Self(137);
+    pub const ETHERNET: Self = Self(143);
+
+    const NAMES: [(Self, &'static str); 23] = [
+        (Self::HOPOPT, "HOPOPT"),
+        (Self::ICMP, "ICMP"),
+        (Self::IGMP, "IGMP"),
+        (Self::IPV4, "IPv4"),
+        (Self::TCP, "TCP"),
+        (Self::UDP, "UDP"),
+        (Self::IPV6, "IPv6"),
+        (Self::IPV6_ROUTE, "IPv6-Route"),
+        (Self::IPV6_FRAG, "IPv6-Frag"),
+        (Self::GRE, "GRE"),
+        (Self::ESP, "ESP"),
+        (Self::AH, "AH"),
+        (Self::ICMPV6, "IPv6-ICMP"),
+        (Self::IPV6_NO_NEXT, "IPv6-NoNxt"),
+        (Self::IPV6_OPTS, "IPv6-Opts"),
+        (Self::OSPF, "OSPFIGP"),
+        (Self::PIM, "PIM"),
+        (Self::VRRP, "VRRP"),
+        (Self::L2TP, "L2TP"),
+        (Self::SCTP, "SCTP"),
+        (Self::UDPLITE, "UDPLite"),
+        (Self::MPLS_IN_IP, "MPLS-in-IP"),
+        (Self::ETHERNET, "Ethernet"),
+    ];
+
+    /// The IANA keyword of the protocol, `None` for numbers not in the table.
+    pub fn name(self) -> Option<&'static str> {
+        Self::NAMES
+            .iter()
+            .find(|(proto, _)| *proto == self)
+            .map(|(_, name)| *name)
+    }
+
+    /// Looks up a protocol by its IANA keyword, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(_, keyword)| keyword.eq_ignore_ascii_case(name))
+            .map(|(proto, _)| *proto)
+    }
+}
+
+impl From<u8> for IpProto {
+    fn from(proto: u8) -> Self {
+        Self(proto)
+    }
+}
+
+impl From<IpProto> for u8 {
+    fn from(proto: IpProto) -> Self {
+        proto.0
+    }
+}
+
+impl core::fmt::Display for IpProto {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Serialized as `{ "number": 6, "name": "TCP" }`, `name` is `null` for
+/// unassigned numbers and ignored when deserializing.
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for IpProto {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        use ::serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("IpProto", 2)?;
+        state.serialize_field("number", &self.0)?;
+        state.serialize_field("name", &self.name())?;
+        state.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for IpProto {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        #[derive(::serde::Deserialize)]
+        struct Repr {
+            number: u8,
+        }
+
+        Repr::deserialize(deserializer).map(|repr| Self(repr.number))
+    }
 }
@@ -1,6 +1,8 @@
 #![no_std]
 
 pub mod eth;
+pub mod icmp;
 pub mod ip;
+pub mod parse;
 pub mod tcp;
 pub mod udp;
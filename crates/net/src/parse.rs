@@ -0,0 +1,313 @@
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{
+    eth::{ETHER_TYPE_IPV4, ETHER_TYPE_IPV6, EthHdr},
+    icmp::{IcmpHdr, Icmpv6Hdr},
+    ip::{IpProto, Ipv4Hdr, Ipv6Hdr},
+    tcp::TcpHdr,
+    udp::UdpHdr,
+};
+
+pub const IPV6_MAX_EXTENSION_HEADER_COUNT: usize = 8;
+
+const IPV4_MORE_FRAGMENTS: u16 = 0x2000;
+const IPV4_FRAG_OFFSET_MASK: u16 = 0x1FFF;
+const IPV6_FRAG_HDR_LEN: usize = 8;
+
+/// Bounds checked access to the bytes of an Ethernet frame, implemented for
+/// byte slices on the host and for socket buffers in the eBPF program.
+pub trait PacketBuf {
+    fn len(&self) -> usize;
+
+    fn load<T>(&self, offset: usize) -> Option<T>
+    where
+        T: Copy;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl PacketBuf for [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn load<T>(&self, offset: usize) -> Option<T>
+    where
+        T: Copy,
+    {
+        let bytes = self.get(offset..offset.checked_add(size_of::<T>())?)?;
+        Some(unsafe { bytes.as_ptr().cast::<T>().read_unaligned() })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The frame ends before a header that has to be read.
+    Truncated,
+    /// Neither IPv4 nor IPv6.
+    UnknownEtherType,
+    /// The version of the IP header does not match the EtherType. Packets of
+    /// any IP protocol are parsed, there is no unsupported protocol.
+    IpVersionMismatch,
+    /// More than [`IPV6_MAX_EXTENSION_HEADER_COUNT`] IPv6 extension headers.
+    ExtensionHeaderOverflow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fragment {
+    /// Not fragmented.
+    Whole,
+    /// First fragment, carries the transport header.
+    First,
+    /// Neither first nor last fragment, no transport header.
+    Middle,
+    /// Last fragment, no transport header.
+    Last,
+}
+
+impl Fragment {
+    fn new(more_fragments: bool, offset: u16) -> Self {
+        match (more_fragments, offset) {
+            (false, 0) => Fragment::Whole,
+            (true, 0) => Fragment::First,
+            (true, _) => Fragment::Middle,
+            (false, _) => Fragment::Last,
+        }
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        *self != Fragment::Whole
+    }
+
+    pub fn is_last(&self) -> bool {
+        *self == Fragment::Last
+    }
+
+    /// Only unfragmented packets and first fragments start with the transport
+    /// header.
+    pub fn has_transport_header(&self) -> bool {
+        matches!(self, Fragment::Whole | Fragment::First)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParsedPacket {
+    pub src_addr: IpAddr,
+    pub dst_addr: IpAddr,
+    pub proto: IpProto,
+    /// The ICMP type for ICMP and ICMPv6, zero for other protocols without
+    /// ports and fragments without a transport header.
+    pub src_port: u16,
+    /// The ICMP code for ICMP and ICMPv6, zero for other protocols without
+    /// ports and fragments without a transport header.
+    pub dst_port: u16,
+    pub fragment: Fragment,
+    /// Offset of the transport header from the start of the frame.
+    pub l4_offset: usize,
+    /// Offset of the transport payload from the start of the frame, the
+    /// headers every segment of a GSO packet carries.
+    pub payload_offset: usize,
+}
+
+/// Parses the Ethernet and IP headers of a packet, the ports of TCP and UDP
+/// and the type and code of ICMP and ICMPv6.
+pub fn parse<P>(packet: &P) -> Result<ParsedPacket, ParseError>
+where
+    P: PacketBuf + ?Sized,
+{
+    let eth_header = packet.load::<EthHdr>(0).ok_or(ParseError::Truncated)?;
+
+    parse_at(
+        packet,
+        u16::from_be(eth_header.ether_type),
+        size_of::<EthHdr>(),
+    )
+}
+
+/// Like [`parse`] for packets starting at the IP header, as seen by cgroup
+/// socket buffer programs. Offsets are relative to the IP header.
+pub fn parse_ip<P>(packet: &P, ether_type: u16) -> Result<ParsedPacket, ParseError>
+where
+    P: PacketBuf + ?Sized,
+{
+    parse_at(packet, ether_type, 0)
+}
+
+fn parse_at<P>(packet: &P, ether_type: u16, offset: usize) -> Result<ParsedPacket, ParseError>
+where
+    P: PacketBuf + ?Sized,
+{
+    match ether_type {
+        ETHER_TYPE_IPV4 => parse_ipv4(packet, offset),
+        ETHER_TYPE_IPV6 => parse_ipv6(packet, offset),
+        _ => Err(ParseError::UnknownEtherType),
+    }
+}
+
+fn parse_ipv4<P>(packet: &P, offset: usize) -> Result<ParsedPacket, ParseError>
+where
+    P: PacketBuf + ?Sized,
+{
+    let ip_header = load::<Ipv4Hdr, _>(packet, offset)?;
+    if ip_header.vihl >> 4 != 4 {
+        return Err(ParseError::IpVersionMismatch);
+    }
+
+    let header_len = (ip_header.vihl & 0x0F) as usize * 4;
+    if header_len < size_of::<Ipv4Hdr>() {
+        return Err(ParseError::Truncated);
+    }
+
+    let frags = u16::from_be_bytes(ip_header.frags);
+    let fragment = Fragment::new(
+        frags & IPV4_MORE_FRAGMENTS != 0,
+        frags & IPV4_FRAG_OFFSET_MASK,
+    );
+
+    let l4_offset = offset + header_len;
+    let (src_port, dst_port, l4_header_len) =
+        transport(packet, ip_header.proto, fragment, l4_offset)?;
+
+    Ok(ParsedPacket {
+        src_addr: IpAddr::V4(Ipv4Addr::from(ip_header.src_addr)),
+        dst_addr: IpAddr::V4(Ipv4Addr::from(ip_header.dst_addr)),
+        proto: ip_header.proto,
+        src_port,
+        dst_port,
+        fragment,
+        l4_offset,
+        payload_offset: l4_offset + l4_header_len,
+    })
+}
+
+fn parse_ipv6<P>(packet: &P, offset: usize) -> Result<ParsedPacket, ParseError>
+where
+    P: PacketBuf + ?Sized,
+{
+    let ip_header = load::<Ipv6Hdr, _>(packet, offset)?;
+    if ip_header.vcf[0] >> 4 != 6 {
+        return Err(ParseError::IpVersionMismatch);
+    }
+    let mut next_header = ip_header.next_hdr;
+    let mut l4_offset = offset + size_of::<Ipv6Hdr>();
+    let mut fragment = Fragment::Whole;
+    let mut walked = false;
+
+    // Every extension header starts with the protocol number of the header
+    // following it. The last round only finds the transport header.
+    for _ in 0..=IPV6_MAX_EXTENSION_HEADER_COUNT {
+        match next_header {
+            IpProto::HOPOPT | IpProto::IPV6_ROUTE | IpProto::IPV6_OPTS => {
+                let len = load::<u8, _>(packet, l4_offset + 1)?;
+                next_header = load::<IpProto, _>(packet, l4_offset)?;
+                l4_offset += (len as usize + 1) * 8;
+            }
+            IpProto::AH => {
+                let len = load::<u8, _>(packet, l4_offset + 1)?;
+                next_header = load::<IpProto, _>(packet, l4_offset)?;
+                l4_offset += (len as usize + 2) * 4;
+            }
+            IpProto::IPV6_FRAG => {
+                let frag_field = u16::from_be(load::<u16, _>(packet, l4_offset + 2)?);
+                fragment = Fragment::new(frag_field & 0b1 != 0, frag_field >> 3);
+                next_header = load::<IpProto, _>(packet, l4_offset)?;
+                l4_offset += IPV6_FRAG_HDR_LEN;
+
+                // later fragments carry only payload, even if the next
+                // header is another extension header
+                if !fragment.has_transport_header() {
+                    walked = true;
+                    break;
+                }
+            }
+            _ => {
+                walked = true;
+                break;
+            }
+        }
+    }
+
+    if !walked {
+        return Err(ParseError::ExtensionHeaderOverflow);
+    }
+
+    let (src_port, dst_port, l4_header_len) = transport(packet, next_header, fragment, l4_offset)?;
+
+    Ok(ParsedPacket {
+        src_addr: IpAddr::V6(Ipv6Addr::from(ip_header.src_addr)),
+        dst_addr: IpAddr::V6(Ipv6Addr::from(ip_header.dst_addr)),
+        proto: next_header,
+        src_port,
+        dst_port,
+        fragment,
+        l4_offset,
+        payload_offset: l4_offset + l4_header_len,
+    })
+}
+
+/// Returns the ports (or ICMP type and code) and the length of the transport
+/// header, zero if it is not parsed.
+fn transport<P>(
+    packet: &P,
+    proto: IpProto,
+    fragment: Fragment,
+    offset: usize,
+) -> Result<(u16, u16, usize), ParseError>
+where
+    P: PacketBuf + ?Sized,
+{
+    if !fragment.has_transport_header() {
+        return Ok((0, 0, 0));
+    }
+
+    match proto {
+        IpProto::TCP => {
+            let tcp_header = load::<TcpHdr, _>(packet, offset)?;
+            let header_len = (tcp_header.data_offset_flags[0] >> 4) as usize * 4;
+            if header_len < size_of::<TcpHdr>() {
+                return Err(ParseError::Truncated);
+            }
+
+            Ok((
+                u16::from_be_bytes(tcp_header.source),
+                u16::from_be_bytes(tcp_header.dest),
+                header_len,
+            ))
+        }
+        IpProto::UDP => {
+            let udp_header = load::<UdpHdr, _>(packet, offset)?;
+            Ok((
+                u16::from_be_bytes(udp_header.src),
+                u16::from_be_bytes(udp_header.dst),
+                size_of::<UdpHdr>(),
+            ))
+        }
+        IpProto::ICMP => {
+            let icmp_header = load::<IcmpHdr, _>(packet, offset)?;
+            Ok((
+                icmp_header.icmp_type as u16,
+                icmp_header.code as u16,
+                size_of::<IcmpHdr>(),
+            ))
+        }
+        IpProto::ICMPV6 => {
+            let icmp_header = load::<Icmpv6Hdr, _>(packet, offset)?;
+            Ok((
+                icmp_header.icmp_type as u16,
+                icmp_header.code as u16,
+                size_of::<Icmpv6Hdr>(),
+            ))
+        }
+        _ => Ok((0, 0, 0)),
+    }
+}
+
+fn load<T, P>(packet: &P, offset: usize) -> Result<T, ParseError>
+where
+    T: Copy,
+    P: PacketBuf + ?Sized,
+{
+    packet.load(offset).ok_or(ParseError::Truncated)
+}
@@ -0,0 +1,180 @@
+//! Ethernet frames as they appear on the wire, hand assembled with the
+//! documentation prefixes 192.0.2.0/24 and 2001:db8::/32 as local addresses.
+
+#![allow(dead_code)]
+
+/// 192.0.2.1:50000 -> 1.1.1.1:443, TCP SYN.
+pub const IPV4_TCP: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, // eth
+    0x45, 0x00, 0x00, 0x28, 0x12, 0x34, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, // ipv4
+    0xc0, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, //
+    0xc3, 0x50, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // tcp
+    0x50, 0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, //
+];
+
+/// 8.8.8.8:53 -> 192.0.2.1:54321, UDP with 4 bytes of IPv4 options.
+pub const IPV4_OPTIONS_UDP: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, // eth
+    0x46, 0x00, 0x00, 0x24, 0x00, 0x01, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, // ipv4
+    0x08, 0x08, 0x08, 0x08, 0xc0, 0x00, 0x02, 0x01, //
+    0x01, 0x01, 0x01, 0x00, // options
+    0x00, 0x35, 0xd4, 0x31, 0x00, 0x0c, 0x00, 0x00, // udp
+    0xde, 0xad, 0xbe, 0xef, //
+];
+
+/// 192.0.2.1:8080 -> 1.1.1.1:53, first fragment of a UDP datagram.
+pub const IPV4_FIRST_FRAGMENT: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, // eth
+    0x45, 0x00, 0x05, 0xdc, 0xab, 0xcd, 0x20, 0x00, 0x40, 0x11, 0x00, 0x00, // ipv4, MF
+    0xc0, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, //
+    0x1f, 0x90, 0x00, 0x35, 0x0f, 0xa0, 0x00, 0x00, // udp
+];
+
+/// Middle fragment of the datagram above, starts with payload.
+pub const IPV4_MIDDLE_FRAGMENT: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, // eth
+    0x45, 0x00, 0x05, 0xdc, 0xab, 0xcd, 0x20, 0xb9, 0x40, 0x11, 0x00, 0x00, // ipv4, MF, 185
+    0xc0, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, //
+    0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, // payload
+];
+
+/// Last fragment of the datagram above, starts with payload.
+pub const IPV4_LAST_FRAGMENT: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, // eth
+    0x45, 0x00, 0x00, 0x1c, 0xab, 0xcd, 0x01, 0x72, 0x40, 0x11, 0x00, 0x00, // ipv4, 370
+    0xc0, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, //
+    0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, // payload
+];
+
+/// 192.0.2.1 -> 1.1.1.1, ICMP echo request.
+pub const IPV4_ICMP: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, // eth
+    0x45, 0x00, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x40, 0x01, 0x00, 0x00, // ipv4
+    0xc0, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, //
+    0x08, 0x00, 0xf7, 0xfe, 0x00, 0x01, 0x00, 0x00, // icmp
+];
+
+/// 192.0.2.1 -> 1.1.1.1, GRE carrying IPv4.
+pub const IPV4_GRE: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, // eth
+    0x45, 0x00, 0x00, 0x18, 0x00, 0x01, 0x00, 0x00, 0x40, 0x2f, 0x00, 0x00, // ipv4
+    0xc0, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, //
+    0x00, 0x00, 0x08, 0x00, // gre
+];
+
+/// 192.0.2.1:50000 -> 1.1.1.1:443, cut off in the middle of the TCP header.
+pub const IPV4_TRUNCATED_TCP: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08, 0x00, // eth
+    0x45, 0x00, 0x00, 0x28, 0x12, 0x34, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, // ipv4
+    0xc0, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, //
+    0xc3, 0x50, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, // tcp
+];
+
+/// [2001:db8::1]:50000 -> [2606:4700:4700::1111]:443, TCP SYN.
+pub const IPV6_TCP: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x86, 0xdd, // eth
+    0x60, 0x00, 0x00, 0x00, 0x00, 0x14, 0x06, 0x40, // ipv6
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0xc3, 0x50, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // tcp
+    0x50, 0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, //
+];
+
+/// [2606:4700:4700::1111]:53 -> [2001:db8::1]:5353, UDP behind hop-by-hop
+/// and destination options headers.
+pub const IPV6_EXT_HEADERS_UDP: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x86, 0xdd, // eth
+    0x60, 0x00, 0x00, 0x00, 0x00, 0x1c, 0x00, 0x40, // ipv6, hop-by-hop
+    0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x3c, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, // hop-by-hop, dst opts
+    0x11, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, // dst opts, udp
+    0x00, 0x35, 0x14, 0xe9, 0x00, 0x0c, 0x00, 0x00, // udp
+    0xde, 0xad, 0xbe, 0xef, //
+];
+
+/// Middle fragment of a datagram whose fragmentable part starts with a
+/// destination options header, the payload does not parse as one.
+pub const IPV6_MIDDLE_FRAGMENT_OPTS: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x86, 0xdd, // eth
+    0x60, 0x00, 0x00, 0x00, 0x00, 0x10, 0x2c, 0x40, // ipv6, fragment
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0x3c, 0x00, 0x03, 0x21, 0x00, 0x00, 0x00, 0x2b, // fragment, destination options, 100, M
+    0x3c, 0xff, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, // payload
+];
+
+/// [2001:db8::1]:8080 -> [2606:4700:4700::1111]:53, first fragment of a UDP
+/// datagram.
+pub const IPV6_FIRST_FRAGMENT: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x86, 0xdd, // eth
+    0x60, 0x00, 0x00, 0x00, 0x05, 0xb0, 0x2c, 0x40, // ipv6, fragment
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0x11, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x2a, // fragment, udp, M
+    0x1f, 0x90, 0x00, 0x35, 0x0f, 0xa0, 0x00, 0x00, // udp
+];
+
+/// Last fragment of the datagram above, starts with payload.
+pub const IPV6_LAST_FRAGMENT: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x86, 0xdd, // eth
+    0x60, 0x00, 0x00, 0x00, 0x00, 0x10, 0x2c, 0x40, // ipv6, fragment
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0x11, 0x00, 0x05, 0xa8, 0x00, 0x00, 0x00, 0x2a, // fragment, udp, 181
+    0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, 0x41, // payload
+];
+
+/// 2606:4700:4700::1111 -> 2001:db8::1, ICMPv6 packet too big with an MTU
+/// of 1280.
+pub const IPV6_PACKET_TOO_BIG: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x86, 0xdd, // eth
+    0x60, 0x00, 0x00, 0x00, 0x00, 0x10, 0x3a, 0x40, // ipv6
+    0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, // icmpv6
+    0x60, 0x00, 0x00, 0x00, 0x05, 0xb4, 0x11, 0x40, // offending packet
+];
+
+/// IPv6 UDP packet with eight chained hop-by-hop headers, the most walked.
+pub const IPV6_MAX_EXT_HEADERS_UDP: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x86, 0xdd, // eth
+    0x60, 0x00, 0x00, 0x00, 0x00, 0x48, 0x00, 0x40, // ipv6, hop-by-hop
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x11, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x35, 0x14, 0xe9, 0x00, 0x08, 0x00, 0x00, // udp
+];
+
+/// IPv6 packet with nine chained hop-by-hop headers.
+pub const IPV6_EXT_HEADER_OVERFLOW: &[u8] = &[
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x86, 0xdd, // eth
+    0x60, 0x00, 0x00, 0x00, 0x00, 0x50, 0x00, 0x40, // ipv6, hop-by-hop
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+    0x26, 0x06, 0x47, 0x00, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x11,
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x11, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, //
+    0x00, 0x35, 0x14, 0xe9, 0x00, 0x08, 0x00, 0x00, // udp
+];
+
+/// ARP request.
+pub const ARP: &[u8] = &[
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, // eth
+    0x00, 0x01, 0x08, 0x00, 0x06, 0x04, 0x00, 0x01, // arp
+    0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0xc0, 0x00, 0x02, 0x01, //
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x02, 0x02, //
+];
@@ -0,0 +1,21 @@
+use net::ip::IpProto;
+
+#[test]
+fn names_assigned_protocols() {
+    assert_eq!(IpProto::TCP.name(), Some("TCP"));
+    assert_eq!(IpProto(58).name(), Some("IPv6-ICMP"));
+    assert_eq!(IpProto(253).name(), None);
+}
+
+#[test]
+fn looks_up_names_ignoring_case() {
+    assert_eq!(IpProto::from_name("sctp"), Some(IpProto::SCTP));
+    assert_eq!(IpProto::from_name("IPv6-Frag"), Some(IpProto::IPV6_FRAG));
+    assert_eq!(IpProto::from_name("QUIC"), None);
+}
+
+#[test]
+fn displays_name_or_number() {
+    assert_eq!(IpProto::GRE.to_string(), "GRE");
+    assert_eq!(IpProto(253).to_string(), "253");
+}
@@ -0,0 +1,193 @@
+mod fixtures;
+
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use net::{
+    eth::{ETHER_TYPE_IPV4, ETHER_TYPE_IPV6},
+    icmp,
+    ip::IpProto,
+    parse::{Fragment, ParseError, parse, parse_ip},
+};
+
+const LOCAL_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+const LOCAL_V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+const CLOUDFLARE_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+const CLOUDFLARE_V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111));
+
+#[test]
+fn ipv4_tcp() {
+    let packet = parse(fixtures::IPV4_TCP).unwrap();
+
+    assert_eq!(packet.src_addr, LOCAL_V4);
+    assert_eq!(packet.dst_addr, CLOUDFLARE_V4);
+    assert_eq!(packet.proto, IpProto::TCP);
+    assert_eq!((packet.src_port, packet.dst_port), (50000, 443));
+    assert_eq!(packet.fragment, Fragment::Whole);
+    assert_eq!(packet.l4_offset, 34);
+    assert_eq!(packet.payload_offset, 54);
+}
+
+#[test]
+fn ipv4_options_are_skipped() {
+    let packet = parse(fixtures::IPV4_OPTIONS_UDP).unwrap();
+
+    assert_eq!(packet.src_addr, IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)));
+    assert_eq!(packet.dst_addr, LOCAL_V4);
+    assert_eq!(packet.proto, IpProto::UDP);
+    assert_eq!((packet.src_port, packet.dst_port), (53, 54321));
+    assert_eq!(packet.l4_offset, 38);
+    assert_eq!(packet.payload_offset, 46);
+}
+
+#[test]
+fn ipv4_fragments() {
+    let first = parse(fixtures::IPV4_FIRST_FRAGMENT).unwrap();
+    assert_eq!(first.fragment, Fragment::First);
+    assert_eq!((first.src_port, first.dst_port), (8080, 53));
+
+    let middle = parse(fixtures::IPV4_MIDDLE_FRAGMENT).unwrap();
+    assert_eq!(middle.fragment, Fragment::Middle);
+    assert_eq!((middle.src_port, middle.dst_port), (0, 0));
+    assert_eq!(middle.payload_offset, middle.l4_offset);
+
+    let last = parse(fixtures::IPV4_LAST_FRAGMENT).unwrap();
+    assert_eq!(last.fragment, Fragment::Last);
+    assert!(last.fragment.is_last());
+    assert_eq!((last.src_port, last.dst_port), (0, 0));
+}
+
+#[test]
+fn ipv6_tcp() {
+    let packet = parse(fixtures::IPV6_TCP).unwrap();
+
+    assert_eq!(packet.src_addr, LOCAL_V6);
+    assert_eq!(packet.dst_addr, CLOUDFLARE_V6);
+    assert_eq!(packet.proto, IpProto::TCP);
+    assert_eq!((packet.src_port, packet.dst_port), (50000, 443));
+    assert_eq!(packet.fragment, Fragment::Whole);
+    assert_eq!(packet.l4_offset, 54);
+    assert_eq!(packet.payload_offset, 74);
+}
+
+#[test]
+fn ipv6_extension_headers_are_walked() {
+    let packet = parse(fixtures::IPV6_EXT_HEADERS_UDP).unwrap();
+
+    assert_eq!(packet.src_addr, CLOUDFLARE_V6);
+    assert_eq!(packet.dst_addr, LOCAL_V6);
+    assert_eq!(packet.proto, IpProto::UDP);
+    assert_eq!((packet.src_port, packet.dst_port), (53, 5353));
+    assert_eq!(packet.l4_offset, 70);
+}
+
+#[test]
+fn ipv6_fragments() {
+    let first = parse(fixtures::IPV6_FIRST_FRAGMENT).unwrap();
+    assert_eq!(first.fragment, Fragment::First);
+    assert_eq!(first.proto, IpProto::UDP);
+    assert_eq!((first.src_port, first.dst_port), (8080, 53));
+    assert_eq!(first.l4_offset, 62);
+
+    let last = parse(fixtures::IPV6_LAST_FRAGMENT).unwrap();
+    assert_eq!(last.fragment, Fragment::Last);
+    assert_eq!((last.src_port, last.dst_port), (0, 0));
+}
+
+#[test]
+fn ipv6_middle_fragment_stops_at_fragment_header() {
+    let middle = parse(fixtures::IPV6_MIDDLE_FRAGMENT_OPTS).unwrap();
+
+    assert_eq!(middle.fragment, Fragment::Middle);
+    assert_eq!(middle.proto, IpProto::IPV6_OPTS);
+    assert_eq!((middle.src_port, middle.dst_port), (0, 0));
+    assert_eq!(middle.l4_offset, 14 + 40 + 8);
+    assert_eq!(middle.payload_offset, middle.l4_offset);
+}
+
+#[test]
+fn ipv6_extension_header_limit() {
+    let packet = parse(fixtures::IPV6_MAX_EXT_HEADERS_UDP).unwrap();
+
+    assert_eq!(packet.proto, IpProto::UDP);
+    assert_eq!((packet.src_port, packet.dst_port), (53, 5353));
+    assert_eq!(packet.l4_offset, 14 + 40 + 8 * 8);
+}
+
+#[test]
+fn ipv6_extension_header_overflow() {
+    assert_eq!(
+        parse(fixtures::IPV6_EXT_HEADER_OVERFLOW),
+        Err(ParseError::ExtensionHeaderOverflow)
+    );
+}
+
+#[test]
+fn packets_without_ethernet_header() {
+    let packet = parse_ip(&fixtures::IPV4_TCP[14..], ETHER_TYPE_IPV4).unwrap();
+    assert_eq!(packet.src_addr, LOCAL_V4);
+    assert_eq!((packet.src_port, packet.dst_port), (50000, 443));
+    assert_eq!(packet.l4_offset, 20);
+
+    let packet = parse_ip(&fixtures::IPV6_EXT_HEADERS_UDP[14..], ETHER_TYPE_IPV6).unwrap();
+    assert_eq!(packet.dst_addr, LOCAL_V6);
+    assert_eq!((packet.src_port, packet.dst_port), (53, 5353));
+    assert_eq!(packet.l4_offset, 56);
+}
+
+#[test]
+fn unknown_ether_type() {
+    assert_eq!(parse(fixtures::ARP), Err(ParseError::UnknownEtherType));
+}
+
+#[test]
+fn ip_version_mismatch() {
+    let mut packet = fixtures::IPV4_TCP.to_vec();
+    packet[14] = 0x65;
+    assert_eq!(parse(&packet[..]), Err(ParseError::IpVersionMismatch));
+
+    let mut packet = fixtures::IPV6_TCP.to_vec();
+    packet[14] = 0x45;
+    assert_eq!(parse(&packet[..]), Err(ParseError::IpVersionMismatch));
+}
+
+#[test]
+fn icmp_type_and_code_replace_ports() {
+    let echo = parse(fixtures::IPV4_ICMP).unwrap();
+    assert_eq!(echo.proto, IpProto::ICMP);
+    assert_eq!(
+        (echo.src_port, echo.dst_port),
+        (icmp::ICMP_ECHO_REQUEST as u16, 0)
+    );
+    assert_eq!(echo.l4_offset, 34);
+
+    let too_big = parse(fixtures::IPV6_PACKET_TOO_BIG).unwrap();
+    assert_eq!(too_big.proto, IpProto::ICMPV6);
+    assert_eq!(
+        (too_big.src_port, too_big.dst_port),
+        (icmp::ICMPV6_PACKET_TOO_BIG as u16, 0)
+    );
+}
+
+#[test]
+fn protocols_without_ports() {
+    let packet = parse(fixtures::IPV4_GRE).unwrap();
+
+    assert_eq!(packet.proto, IpProto::GRE);
+    assert_eq!((packet.src_port, packet.dst_port), (0, 0));
+}
+
+#[test]
+fn truncated_packets() {
+    assert_eq!(
+        parse(fixtures::IPV4_TRUNCATED_TCP),
+        Err(ParseError::Truncated)
+    );
+
+    for len in 0..fixtures::IPV6_TCP.len() {
+        assert_eq!(
+            parse(&fixtures::IPV6_TCP[..len]),
+            Err(ParseError::Truncated),
+            "{len} bytes"
+        );
+    }
+}
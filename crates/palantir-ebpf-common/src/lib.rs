@@ -1,7 +1,7 @@
 #![no_std]
 
-use core::net::IpAddr;
-use net::ip::IpProto;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use net::{ip::IpProto, parse::ParseError};
 
 #[cfg(feature = "std")]
 extern crate std;
@@ -9,29 +9,416 @@ extern crate std;
 #[cfg(feature = "std")]
 use std::time::SystemTime;
 
+/// A packet, or with `packets > 1` the traffic of one flow since it was last
+/// reported.
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct RawEvent {
-    pub pid: u32,
+    /// The process owning the local socket, if it is known.
+    pub process: Option<ProcessInfo>,
+    pub ifindex: u32,
     pub src_addr: IpAddr,
     pub dst_addr: IpAddr,
+    /// ICMP type for ICMP and ICMPv6.
     pub src_port: u16,
+    /// ICMP code for ICMP and ICMPv6.
     pub dst_port: u16,
     pub ts_offset_ns: u64,
     pub proto: IpProto,
     pub fragment: bool,
     pub last_fragment: bool,
     pub direction: Direction,
-    pub bytes: u16,
+    /// Packets on the wire, a GSO packet counts once per segment.
+    pub packets: u64,
+    /// IP packet bytes including the IP header.
+    pub bytes: u64,
+    /// Ethernet frame bytes as counted by the interface statistics, with the
+    /// headers of every GSO segment.
+    pub wire_bytes: u64,
+    /// Only one in this many packets was counted, see [`Sampling`]. The
+    /// counters above are already scaled up by it.
+    pub sample_rate: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 #[repr(C)]
 pub enum Direction {
     Ingress,
     Egress,
 }
 
+/// The process a socket belongs to, captured in process context by the cgroup
+/// socket hooks. `pid` is the id of the thread, `tgid` the id of the process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub tgid: u32,
+    pub uid: u32,
+    pub _pad: u32,
+    pub cgroup_id: u64,
+    pub comm: [u8; 16],
+}
+
+impl ProcessInfo {
+    /// `comm` up to the first NUL byte.
+    pub fn comm(&self) -> &[u8] {
+        let len = self
+            .comm
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.comm.len());
+        &self.comm[..len]
+    }
+}
+
+/// Key of the `OWNERS` map, a socket seen from the local host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct SocketKey {
+    pub local_addr: [u8; 16],
+    pub remote_addr: [u8; 16],
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub proto: IpProto,
+    pub family: u8,
+    pub _pad: [u8; 2],
+}
+
+impl SocketKey {
+    pub fn new(
+        local_addr: IpAddr,
+        remote_addr: IpAddr,
+        local_port: u16,
+        remote_port: u16,
+        proto: IpProto,
+    ) -> Self {
+        let (local_addr, family) = encode_addr(local_addr);
+        let (remote_addr, _) = encode_addr(remote_addr);
+
+        Self {
+            local_addr,
+            remote_addr,
+            local_port,
+            remote_port,
+            proto,
+            family,
+            _pad: [0; 2],
+        }
+    }
+}
+
+impl From<&RawEvent> for SocketKey {
+    fn from(event: &RawEvent) -> Self {
+        let (local_port, remote_port) = match event.direction {
+            Direction::Ingress => (event.dst_port, event.src_port),
+            Direction::Egress => (event.src_port, event.dst_port),
+        };
+
+        Self::new(
+            event.local_addr(),
+            event.peer_addr(),
+            local_port,
+            remote_port,
+            event.proto,
+        )
+    }
+}
+
+/// Key of the `LISTENERS` map, a bound local port of either address family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct PortKey {
+    pub port: u16,
+    pub proto: IpProto,
+    pub _pad: u8,
+}
+
+/// Index into the per-CPU `COUNTERS` array of the classifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Counter {
+    /// New flows announced on the `EVENTS` ring buffer.
+    EventsEmitted,
+    /// New flows not announced because `EVENTS` was full.
+    RingBufferFull,
+    /// Packets ignored by the capture filter.
+    Filtered,
+    /// Packets skipped by sampling, the counted ones stand in for them.
+    SampledOut,
+    Truncated,
+    UnknownEtherType,
+    IpVersionMismatch,
+    ExtensionHeaderOverflow,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 8] = [
+        Counter::EventsEmitted,
+        Counter::RingBufferFull,
+        Counter::Filtered,
+        Counter::SampledOut,
+        Counter::Truncated,
+        Counter::UnknownEtherType,
+        Counter::IpVersionMismatch,
+        Counter::ExtensionHeaderOverflow,
+    ];
+}
+
+impl From<ParseError> for Counter {
+    fn from(err: ParseError) -> Self {
+        match err {
+            ParseError::Truncated => Counter::Truncated,
+            ParseError::UnknownEtherType => Counter::UnknownEtherType,
+            ParseError::IpVersionMismatch => Counter::IpVersionMismatch,
+            ParseError::ExtensionHeaderOverflow => Counter::ExtensionHeaderOverflow,
+        }
+    }
+}
+
+/// Capacity of the `ADDR_RULES` trie.
+pub const MAX_ADDR_RULES: u32 = 1024;
+/// Capacity of the `PORT_RULES` map.
+pub const MAX_PORT_RULES: u32 = 1024;
+
+/// Value of the `ADDR_RULES`, `PORT_RULES` and `PROTO_RULES` maps.
+pub const RULE_INCLUDE: u8 = 1;
+pub const RULE_EXCLUDE: u8 = 2;
+
+/// Value of the `FILTER` array, how the classifiers apply the rule maps. All
+/// zeros captures everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct CaptureFilter {
+    /// Only peers whose longest matching address rule includes them are
+    /// captured, otherwise only excluded peers are ignored.
+    pub include_addrs: u8,
+    /// Only TCP and UDP packets with an included port are captured.
+    pub include_ports: u8,
+    /// Only packets of an included protocol are captured.
+    pub include_protos: u8,
+    /// Bit `1 << direction` is set for directions that are ignored.
+    pub exclude_directions: u8,
+}
+
+impl CaptureFilter {
+    pub fn captures(&self, direction: Direction) -> bool {
+        self.exclude_directions & (1 << direction as u8) == 0
+    }
+}
+
+/// Data of the `ADDR_RULES` trie keys. The family comes first so rules of
+/// both families share one trie, a network of `n` bits is a prefix of
+/// `8 + n` bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct AddrRuleKey {
+    pub family: u8,
+    pub addr: [u8; 16],
+}
+
+impl AddrRuleKey {
+    /// Prefix length of a single address, the longest there is.
+    pub const MAX_PREFIX_LEN: u32 = 8 + 128;
+
+    pub fn new(addr: IpAddr) -> Self {
+        let (addr, family) = encode_addr(addr);
+
+        Self { family, addr }
+    }
+
+    /// Prefix length of the trie key for a network of `prefix_len` bits.
+    pub fn prefix_len(prefix_len: u8) -> u32 {
+        8 + prefix_len as u32
+    }
+}
+
+/// Value of the `SAMPLING` array, a rate of 0 or 1 counts every packet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Sampling {
+    pub rate: u32,
+    /// [`SAMPLE_RANDOM`] or [`SAMPLE_FLOW`].
+    pub mode: u32,
+}
+
+/// Counts every packet with a probability of `1 / rate`.
+pub const SAMPLE_RANDOM: u32 = 0;
+/// Counts every packet of one in `rate` connections, by [`RawEvent::flow_hash`].
+pub const SAMPLE_FLOW: u32 = 1;
+
+/// Key of the `FLOWS` map. Addresses are stored as 16 bytes with `family`
+/// telling IPv4 and IPv6 apart, and there is no implicit padding, so equal
+/// flows always hash to the same bytes in the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FlowKey {
+    pub src_addr: [u8; 16],
+    pub dst_addr: [u8; 16],
+    pub ifindex: u32,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub proto: IpProto,
+    pub direction: u8,
+    pub family: u8,
+    pub _pad: u8,
+}
+
+/// Value of the `FLOWS` map, one per CPU.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct FlowStats {
+    pub packets: u64,
+    pub bytes: u64,
+    pub wire_bytes: u64,
+    pub first_seen_ns: u64,
+    pub last_seen_ns: u64,
+    /// Sample rate of the last packet.
+    pub sample_rate: u32,
+    pub _pad: u32,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for AddrRuleKey {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for CaptureFilter {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for FlowKey {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for FlowStats {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for ProcessInfo {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for Sampling {}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for SocketKey {}
+
+impl From<&RawEvent> for FlowKey {
+    fn from(event: &RawEvent) -> Self {
+        let (src_addr, family) = encode_addr(event.src_addr);
+        let (dst_addr, _) = encode_addr(event.dst_addr);
+
+        Self {
+            src_addr,
+            dst_addr,
+            ifindex: event.ifindex,
+            src_port: event.src_port,
+            dst_port: event.dst_port,
+            proto: event.proto,
+            direction: event.direction as u8,
+            family,
+            _pad: 0,
+        }
+    }
+}
+
+impl FlowKey {
+    /// An event carrying the traffic in `stats` for this flow.
+    pub fn event(&self, stats: &FlowStats) -> RawEvent {
+        RawEvent {
+            process: None,
+            ifindex: self.ifindex,
+            src_addr: decode_addr(self.src_addr, self.family),
+            dst_addr: decode_addr(self.dst_addr, self.family),
+            src_port: self.src_port,
+            dst_port: self.dst_port,
+            ts_offset_ns: stats.last_seen_ns,
+            proto: self.proto,
+            fragment: false,
+            last_fragment: false,
+            direction: match self.direction {
+                0 => Direction::Ingress,
+                _ => Direction::Egress,
+            },
+            packets: stats.packets,
+            bytes: stats.bytes,
+            wire_bytes: stats.wire_bytes,
+            sample_rate: stats.sample_rate,
+        }
+    }
+}
+
+impl From<&RawEvent> for FlowStats {
+    fn from(event: &RawEvent) -> Self {
+        Self {
+            packets: event.packets,
+            bytes: event.bytes,
+            wire_bytes: event.wire_bytes,
+            first_seen_ns: event.ts_offset_ns,
+            last_seen_ns: event.ts_offset_ns,
+            sample_rate: event.sample_rate,
+            _pad: 0,
+        }
+    }
+}
+
+impl FlowStats {
+    /// Accounts an event. The slots of other CPUs start out zeroed when a
+    /// flow is inserted, so the first event on a CPU sets `first_seen_ns`.
+    pub fn record(&mut self, event: &RawEvent) {
+        if self.packets == 0 {
+            self.first_seen_ns = event.ts_offset_ns;
+        }
+
+        self.packets += event.packets;
+        self.bytes += event.bytes;
+        self.wire_bytes += event.wire_bytes;
+        self.last_seen_ns = event.ts_offset_ns;
+        self.sample_rate = event.sample_rate;
+    }
+
+    /// Combines the per-CPU values of a flow.
+    pub fn merge(self, other: Self) -> Self {
+        if self.packets == 0 {
+            return other;
+        }
+        if other.packets == 0 {
+            return self;
+        }
+
+        let latest = match self.last_seen_ns >= other.last_seen_ns {
+            true => self,
+            false => other,
+        };
+
+        Self {
+            packets: self.packets + other.packets,
+            bytes: self.bytes + other.bytes,
+            wire_bytes: self.wire_bytes + other.wire_bytes,
+            first_seen_ns: self.first_seen_ns.min(other.first_seen_ns),
+            last_seen_ns: latest.last_seen_ns,
+            sample_rate: latest.sample_rate,
+            _pad: 0,
+        }
+    }
+}
+
+fn encode_addr(addr: IpAddr) -> ([u8; 16], u8) {
+    match addr {
+        IpAddr::V4(addr) => {
+            let mut bytes = [0u8; 16];
+            bytes[..4].copy_from_slice(&addr.octets());
+            (bytes, 4)
+        }
+        IpAddr::V6(addr) => (addr.octets(), 6),
+    }
+}
+
+fn decode_addr(bytes: [u8; 16], family: u8) -> IpAddr {
+    match family {
+        4 => IpAddr::V4(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])),
+        _ => IpAddr::V6(Ipv6Addr::from(bytes)),
+    }
+}
+
 impl RawEvent {
     pub fn peer_addr(&self) -> IpAddr {
         match self.direction {
@@ -47,6 +434,69 @@ impl RawEvent {
         }
     }
 
+    pub fn is_icmp(&self) -> bool {
+        matches!(self.proto, IpProto::ICMP | IpProto::ICMPV6)
+    }
+
+    /// Source and destination port, `None` for ICMP and ICMPv6 which carry
+    /// type and code in the port fields.
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        match self.is_icmp() {
+            true => None,
+            false => Some((self.src_port, self.dst_port)),
+        }
+    }
+
+    /// ICMP type and code, `None` for other protocols.
+    pub fn icmp(&self) -> Option<(u8, u8)> {
+        match self.is_icmp() {
+            true => Some((self.src_port as u8, self.dst_port as u8)),
+            false => None,
+        }
+    }
+
+    /// Hash of the connection, the same for both directions so sampling by
+    /// flow keeps or drops a connection as a whole.
+    pub fn flow_hash(&self) -> u32 {
+        let (peer_port, local_port) = match self.direction {
+            Direction::Ingress => (self.src_port, self.dst_port),
+            Direction::Egress => (self.dst_port, self.src_port),
+        };
+        let (peer_addr, _) = encode_addr(self.peer_addr());
+        let (local_addr, _) = encode_addr(self.local_addr());
+
+        // FNV-1a over 32 bit words, few enough rounds for the verifier
+        let mut hash: u32 = 0x811c_9dc5;
+        let mut mix = |word: u32| hash = (hash ^ word).wrapping_mul(0x0100_0193);
+        for addr in [peer_addr, local_addr] {
+            for i in 0..4 {
+                mix(u32::from_be_bytes([
+                    addr[i * 4],
+                    addr[i * 4 + 1],
+                    addr[i * 4 + 2],
+                    addr[i * 4 + 3],
+                ]));
+            }
+        }
+        mix((peer_port as u32) << 16 | local_port as u32);
+        mix(self.proto.0 as u32);
+
+        hash
+    }
+
+    /// Scales the counters of a packet sampled at one in `rate` up to an
+    /// estimate of the whole traffic. Flows add up the scaled counters, so
+    /// they stay correct when packets of a flow are sampled at different
+    /// rates.
+    pub fn sample(&mut self, rate: u32) {
+        let rate = rate.max(1);
+
+        self.packets *= rate as u64;
+        self.bytes *= rate as u64;
+        self.wire_bytes *= rate as u64;
+        self.sample_rate = rate;
+    }
+
     #[cfg(feature = "std")]
     pub fn timestamp(&self, boot_time: SystemTime) -> SystemTime {
         use core::time::Duration;
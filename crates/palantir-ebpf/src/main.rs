@@ -1,198 +1,437 @@
 #![no_std]
 #![no_main]
 
-use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::ffi::c_void;
 
 use aya_ebpf::{
-    bindings::{BPF_RB_FORCE_WAKEUP, TC_ACT_OK},
-    helpers::{bpf_get_current_pid_tgid, generated::bpf_ktime_get_ns},
-    macros::{classifier, map},
-    maps::RingBuf,
-    programs::TcContext,
+    bindings::{BPF_F_NO_PREALLOC, BPF_NOEXIST, TC_ACT_OK, xdp_action::XDP_PASS},
+    helpers::{
+        bpf_get_current_comm, bpf_get_current_pid_tgid, bpf_get_current_uid_gid,
+        generated::{
+            bpf_get_current_cgroup_id, bpf_get_prandom_u32, bpf_get_socket_cookie, bpf_ktime_get_ns,
+        },
+    },
+    macros::{cgroup_skb, cgroup_sock, classifier, map, xdp},
+    maps::{
+        Array, HashMap, LruHashMap, LruPerCpuHashMap, PerCpuArray, RingBuf,
+        lpm_trie::{Key, LpmTrie},
+    },
+    programs::{SkBuffContext, SockContext, TcContext, XdpContext, sk_buff::SkBuff},
 };
-
-use aya_log_ebpf::warn;
 use net::{
     eth::{ETHER_TYPE_IPV4, ETHER_TYPE_IPV6, EthHdr},
-    ip::{IpProto, Ipv4Hdr, Ipv6Hdr},
-    tcp::TcpHdr,
-    udp::UdpHdr,
+    ip::IpProto,
+    parse::{PacketBuf, parse, parse_ip},
+};
+use palantir_ebpf_common::{
+    AddrRuleKey, CaptureFilter, Counter, Direction, FlowKey, FlowStats, MAX_ADDR_RULES,
+    MAX_PORT_RULES, PortKey, ProcessInfo, RULE_EXCLUDE, RULE_INCLUDE, RawEvent, SAMPLE_FLOW,
+    Sampling, SocketKey,
 };
-use palantir_ebpf_common::{Direction, RawEvent};
 
-const IPV6_MAX_EXTENSION_HEADEAR_COUNT: usize = 8;
+const MAX_FLOWS: u32 = 65536;
+const MAX_SOCKETS: u32 = 65536;
+const MAX_LISTENERS: u32 = 4096;
+const MAX_INTERFACES: u32 = 256;
 
+/// New flow notifications, the traffic itself is accumulated in `FLOWS`.
 #[map]
 static EVENTS: RingBuf = RingBuf::with_byte_size(4096 * 20, 0);
 
+/// Events, drops and parse failures of the classifiers indexed by
+/// [`Counter`], read by userspace. Logging them instead would flood the log
+/// when it matters most.
+#[map]
+static COUNTERS: PerCpuArray<u64> = PerCpuArray::with_max_entries(Counter::ALL.len() as u32, 0);
+
+/// How the classifiers apply the rule maps below, written by userspace after
+/// the rules.
+#[map]
+static FILTER: Array<CaptureFilter> = Array::with_max_entries(1, 0);
+
+/// [`RULE_INCLUDE`] or [`RULE_EXCLUDE`] by network of the remote peer.
+#[map]
+static ADDR_RULES: LpmTrie<AddrRuleKey, u8> =
+    LpmTrie::with_max_entries(MAX_ADDR_RULES, BPF_F_NO_PREALLOC);
+
+/// [`RULE_INCLUDE`] or [`RULE_EXCLUDE`] by source or destination port.
+#[map]
+static PORT_RULES: HashMap<u16, u8> = HashMap::with_max_entries(MAX_PORT_RULES, 0);
+
+/// [`RULE_INCLUDE`] by IP protocol number.
+#[map]
+static PROTO_RULES: Array<u8> = Array::with_max_entries(256, 0);
+
+/// Rate and mode of sampling, written by userspace.
+#[map]
+static SAMPLING: Array<Sampling> = Array::with_max_entries(1, 0);
+
+/// Interfaces without a link layer header by ifindex, such as WireGuard and
+/// tun devices, written by userspace. Their packets start at the IP header.
+#[map]
+static L3_INTERFACES: HashMap<u32, u8> = HashMap::with_max_entries(MAX_INTERFACES, 0);
+
+/// Packet and byte counters per flow, harvested by userspace.
+#[map]
+static FLOWS: LruPerCpuHashMap<FlowKey, FlowStats> =
+    LruPerCpuHashMap::with_max_entries(MAX_FLOWS, 0);
+
+/// Owner of every socket created while the programs are attached, by socket
+/// cookie.
+#[map]
+static SOCKETS: LruHashMap<u64, ProcessInfo> = LruHashMap::with_max_entries(MAX_SOCKETS, 0);
+
+/// Owner of bound ports. Sockets accepted from a listener are created in
+/// softirq context, they are attributed to whoever bound the port.
+#[map]
+static LISTENERS: LruHashMap<PortKey, ProcessInfo> = LruHashMap::with_max_entries(MAX_LISTENERS, 0);
+
+/// Owner of the connections seen by the cgroup socket buffer programs, looked
+/// up for flows by the classifiers and userspace.
+#[map]
+static OWNERS: LruHashMap<SocketKey, ProcessInfo> = LruHashMap::with_max_entries(MAX_FLOWS, 0);
+
+#[cgroup_sock(sock_create)]
+pub fn sock_create(ctx: SockContext) -> i32 {
+    let cookie = unsafe { bpf_get_socket_cookie(ctx.sock as *mut c_void) };
+    _ = SOCKETS.insert(&cookie, &current_process(), 0);
+
+    1
+}
+
+#[cgroup_sock(post_bind4)]
+pub fn post_bind4(ctx: SockContext) -> i32 {
+    record_listener(&ctx);
+
+    1
+}
+
+#[cgroup_sock(post_bind6)]
+pub fn post_bind6(ctx: SockContext) -> i32 {
+    record_listener(&ctx);
+
+    1
+}
+
+#[cgroup_skb(ingress)]
+pub fn cgroup_skb_ingress(ctx: SkBuffContext) -> i32 {
+    _ = track_owner(&ctx, Direction::Ingress);
+
+    1
+}
+
+#[cgroup_skb(egress)]
+pub fn cgroup_skb_egress(ctx: SkBuffContext) -> i32 {
+    _ = track_owner(&ctx, Direction::Egress);
+
+    1
+}
+
 #[classifier]
 pub fn tc_ingress(ctx: TcContext) -> i32 {
-    match try_handle_packet(&ctx, Direction::Ingress) {
-        Ok(ret) => ret,
-        Err(_) => TC_ACT_OK,
-    }
+    _ = try_handle_skb(&ctx, Direction::Ingress);
+
+    TC_ACT_OK
 }
 
 #[classifier]
 pub fn tc_egress(ctx: TcContext) -> i32 {
-    match try_handle_packet(&ctx, Direction::Egress) {
-        Ok(ret) => ret,
-        Err(_) => TC_ACT_OK,
+    _ = try_handle_skb(&ctx, Direction::Egress);
+
+    TC_ACT_OK
+}
+
+/// Replaces `tc_ingress` if configured. Runs in the driver before an skb is
+/// allocated, so it also sees packets the stack drops later.
+#[xdp]
+pub fn xdp_ingress(ctx: XdpContext) -> u32 {
+    _ = try_handle_xdp(&ctx);
+
+    XDP_PASS
+}
+
+/// Lets the shared parser read from the socket buffer.
+struct SkBuf<'a>(&'a SkBuff);
+
+impl PacketBuf for SkBuf<'_> {
+    fn len(&self) -> usize {
+        self.0.len() as usize
+    }
+
+    fn load<T>(&self, offset: usize) -> Option<T>
+    where
+        T: Copy,
+    {
+        self.0.load::<T>(offset).ok()
     }
 }
 
-fn try_handle_packet(ctx: &TcContext, direction: Direction) -> Result<i32, ()> {
-    let pid = bpf_get_current_pid_tgid() as u32;
-    let ts_offset_ns = unsafe { bpf_ktime_get_ns() };
+/// Lets the shared parser read from the frame of an XDP program.
+struct XdpBuf<'a>(&'a XdpContext);
 
-    let eth_header = ctx.load::<EthHdr>(0).or(Err(()))?;
-
-    let event = match u16::from_be(eth_header.ether_type) {
-        ETHER_TYPE_IPV4 => {
-            let ip_header = ctx.load::<Ipv4Hdr>(size_of::<EthHdr>()).or(Err(()))?;
-            let src_addr = IpAddr::V4(Ipv4Addr::from_bits(u32::from_be_bytes(ip_header.src_addr)));
-            let dst_addr = IpAddr::V4(Ipv4Addr::from_bits(u32::from_be_bytes(ip_header.dst_addr)));
-            let proto = ip_header.proto;
-            let (src_port, dst_port) = match proto {
-                IpProto::Tcp => {
-                    let tcp_header = ctx
-                        .load::<TcpHdr>(size_of::<EthHdr>() + size_of::<Ipv4Hdr>())
-                        .or(Err(()))?;
-                    (
-                        u16::from_be_bytes(tcp_header.source),
-                        u16::from_be_bytes(tcp_header.dest),
-                    )
-                }
-                IpProto::Udp => {
-                    let udp_header = ctx
-                        .load::<UdpHdr>(size_of::<EthHdr>() + size_of::<Ipv4Hdr>())
-                        .or(Err(()))?;
-                    (
-                        u16::from_be_bytes(udp_header.src),
-                        u16::from_be_bytes(udp_header.dst),
-                    )
-                }
-                _ => return Err(()),
-            };
-            let frags = u16::from_be_bytes(ip_header.frags);
-            let frag_flags = (frags >> 13) as u8;
-            let frag_offset = frags & 0x1FFF;
-
-            let fragment = (frag_flags & 0b001) != 0 && frag_offset == 0;
-            let last_fragment = (frag_flags & 0b001) == 0 && frag_offset == 0;
-            let bytes = u16::from_be_bytes(ip_header.tot_len);
-
-            RawEvent {
-                pid,
-                ts_offset_ns,
-                src_addr,
-                dst_addr,
-                proto,
-                src_port,
-                dst_port,
-                fragment,
-                last_fragment,
-                direction,
-                bytes,
-            }
+impl PacketBuf for XdpBuf<'_> {
+    fn len(&self) -> usize {
+        self.0.data_end() - self.0.data()
+    }
+
+    fn load<T>(&self, offset: usize) -> Option<T>
+    where
+        T: Copy,
+    {
+        let start = self.0.data() + offset;
+        if start + size_of::<T>() > self.0.data_end() {
+            return None;
         }
-        ETHER_TYPE_IPV6 => {
-            let ip_header = ctx.load::<Ipv6Hdr>(size_of::<EthHdr>()).or(Err(()))?;
-
-            let src_addr = IpAddr::V6(Ipv6Addr::from_octets(ip_header.src_addr));
-            let dst_addr = IpAddr::V6(Ipv6Addr::from_octets(ip_header.dst_addr));
-            let mut fragment = false;
-            let mut last_fragment = false;
-
-            let mut offset = size_of::<EthHdr>() + size_of::<Ipv6Hdr>();
-            let mut next_header = ip_header.next_hdr;
-
-            for _ in 0..IPV6_MAX_EXTENSION_HEADEAR_COUNT {
-                if offset + 1 >= ctx.data_end() - ctx.data() {
-                    return Err(());
-                }
-
-                match next_header {
-                    IpProto::HopOpt | IpProto::Ipv6Route | IpProto::Ipv6Opts => {
-                        let extension_header_length = ctx.load::<u8>(offset + 1).or(Err(()))?;
-                        offset += (extension_header_length as usize + 1) * 8;
-                    }
-                    IpProto::Ipv6Frag => {
-                        fragment = true;
-
-                        let frag_field = u16::from_be(ctx.load::<u16>(offset + 2).or(Err(()))?);
-                        let frag_offset = frag_field >> 3;
-
-                        last_fragment = (frag_field & 0b001) != 0 && frag_offset != 0;
-
-                        const IPV6_FRAG_HDR_LEN: usize = 8;
-
-                        offset += IPV6_FRAG_HDR_LEN;
-                    }
-                    IpProto::Tcp | IpProto::Udp => {
-                        break;
-                    }
-                    _ => return Err(()),
-                }
-
-                next_header = ctx.load::<IpProto>(offset).or(Err(()))?;
-            }
-
-            let proto = next_header;
-
-            let (src_port, dst_port) = match proto {
-                IpProto::Tcp => {
-                    let tcp_header = ctx
-                        .load::<TcpHdr>(size_of::<EthHdr>() + size_of::<Ipv6Addr>())
-                        .or(Err(()))?;
-                    (
-                        u16::from_be_bytes(tcp_header.source),
-                        u16::from_be_bytes(tcp_header.dest),
-                    )
-                }
-                IpProto::Udp => {
-                    let udp_header = ctx
-                        .load::<UdpHdr>(size_of::<EthHdr>() + size_of::<Ipv6Addr>())
-                        .or(Err(()))?;
-                    (
-                        u16::from_be_bytes(udp_header.src),
-                        u16::from_be_bytes(udp_header.dst),
-                    )
-                }
-                _ => return Err(()),
+
+        Some(unsafe { (start as *const T).read_unaligned() })
+    }
+}
+
+/// The cgroup socket hooks run in the context of the process owning the
+/// socket, unlike the classifiers.
+fn current_process() -> ProcessInfo {
+    let pid_tgid = bpf_get_current_pid_tgid();
+
+    ProcessInfo {
+        pid: pid_tgid as u32,
+        tgid: (pid_tgid >> 32) as u32,
+        uid: bpf_get_current_uid_gid() as u32,
+        _pad: 0,
+        cgroup_id: unsafe { bpf_get_current_cgroup_id() },
+        comm: bpf_get_current_comm().unwrap_or_default(),
+    }
+}
+
+fn record_listener(ctx: &SockContext) {
+    let sock = unsafe { &*ctx.sock };
+    let key = PortKey {
+        port: sock.src_port as u16,
+        proto: IpProto(sock.protocol as u8),
+        _pad: 0,
+    };
+
+    _ = LISTENERS.insert(&key, &current_process(), 0);
+}
+
+fn track_owner(ctx: &SkBuffContext, direction: Direction) -> Result<(), ()> {
+    let ether_type = u16::from_be(unsafe { (*ctx.skb.skb).protocol } as u16);
+    let packet = parse_ip(&SkBuf(&ctx.skb), ether_type).or(Err(()))?;
+
+    let key = match direction {
+        Direction::Ingress => SocketKey::new(
+            packet.dst_addr,
+            packet.src_addr,
+            packet.dst_port,
+            packet.src_port,
+            packet.proto,
+        ),
+        Direction::Egress => SocketKey::new(
+            packet.src_addr,
+            packet.dst_addr,
+            packet.src_port,
+            packet.dst_port,
+            packet.proto,
+        ),
+    };
+
+    if unsafe { OWNERS.get(&key) }.is_some() {
+        return Ok(());
+    }
+
+    let cookie = unsafe { bpf_get_socket_cookie(ctx.skb.skb as *mut c_void) };
+    let owner = match unsafe { SOCKETS.get(&cookie) } {
+        Some(owner) => *owner,
+        None => {
+            let port = PortKey {
+                port: key.local_port,
+                proto: key.proto,
+                _pad: 0,
             };
-            let bytes = u16::from_be_bytes(ip_header.payload_len);
-
-            RawEvent {
-                pid,
-                ts_offset_ns,
-                src_addr,
-                dst_addr,
-                proto,
-                src_port,
-                dst_port,
-                fragment,
-                last_fragment,
-                direction,
-                bytes,
-            }
+            *unsafe { LISTENERS.get(&port) }.ok_or(())?
         }
-        _ => {
-            return Err(());
+    };
+
+    OWNERS.insert(&key, &owner, 0).or(Err(()))
+}
+
+fn try_handle_skb(ctx: &TcContext, direction: Direction) -> Result<(), ()> {
+    let skb = unsafe { &*ctx.skb.skb };
+    let ether_type =
+        unsafe { L3_INTERFACES.get(&skb.ifindex) }.map(|_| u16::from_be(skb.protocol as u16));
+
+    // GSO and GRO packets are one skb for several segments on the wire
+    let gso_segs = skb.gso_segs.max(1) as u64;
+
+    try_handle_packet(
+        &SkBuf(&ctx.skb),
+        skb.ifindex,
+        ether_type,
+        gso_segs,
+        direction,
+    )
+}
+
+fn try_handle_xdp(ctx: &XdpContext) -> Result<(), ()> {
+    let ifindex = unsafe { (*ctx.ctx).ingress_ifindex };
+    let buf = XdpBuf(ctx);
+
+    // there is no protocol field without an skb, the IP version tells
+    let ether_type = unsafe { L3_INTERFACES.get(&ifindex) }.map(|_| {
+        match buf.load::<u8>(0).map(|version| version >> 4) {
+            Some(6) => ETHER_TYPE_IPV6,
+            _ => ETHER_TYPE_IPV4,
         }
+    });
+
+    // frames arrive one by one, before GRO merges them
+    try_handle_packet(&buf, ifindex, ether_type, 1, Direction::Ingress)
+}
+
+/// Accounts a frame of `gso_segs` segments to its flow, announcing new flows
+/// on `EVENTS`. Frames of interfaces without a link layer start at the IP
+/// header of `ether_type`.
+fn try_handle_packet<B>(
+    buf: &B,
+    ifindex: u32,
+    ether_type: Option<u16>,
+    gso_segs: u64,
+    direction: Direction,
+) -> Result<(), ()>
+where
+    B: PacketBuf,
+{
+    let ts_offset_ns = unsafe { bpf_ktime_get_ns() };
+
+    let (packet, link_header_len) = match ether_type {
+        Some(ether_type) => (parse_ip(buf, ether_type), 0),
+        None => (parse(buf), size_of::<EthHdr>() as u64),
+    };
+    let packet = packet.map_err(|err| count(Counter::from(err)))?;
+
+    // each segment repeats the headers up to the transport payload
+    let wire_bytes = buf.len() as u64 + (gso_segs - 1) * packet.payload_offset as u64;
+    let bytes = wire_bytes.saturating_sub(gso_segs * link_header_len);
+
+    let mut event = RawEvent {
+        process: None,
+        ifindex,
+        ts_offset_ns,
+        src_addr: packet.src_addr,
+        dst_addr: packet.dst_addr,
+        proto: packet.proto,
+        src_port: packet.src_port,
+        dst_port: packet.dst_port,
+        fragment: packet.fragment.is_fragment(),
+        last_fragment: packet.fragment.is_last(),
+        direction,
+        packets: gso_segs,
+        bytes,
+        wire_bytes,
+        sample_rate: 1,
     };
 
+    if !captures(&event) {
+        count(Counter::Filtered);
+        return Ok(());
+    }
+
+    // skipped packets are not accounted, the others count for them
+    let Some(sample_rate) = sample(&event) else {
+        count(Counter::SampledOut);
+        return Ok(());
+    };
+    event.sample(sample_rate);
+
+    let key = FlowKey::from(&event);
+
+    if let Some(stats) = FLOWS.get_ptr_mut(&key) {
+        unsafe { (*stats).record(&event) };
+        return Ok(());
+    }
+
+    // egress packets have passed the cgroup hooks already, ingress packets
+    // of established connections as well
+    event.process = unsafe { OWNERS.get(&SocketKey::from(&event)) }.copied();
+
+    let stats = FlowStats::from(&event);
+    if FLOWS.insert(&key, &stats, BPF_NOEXIST.into()).is_err() {
+        // another CPU inserted the flow in the meantime
+        if let Some(stats) = FLOWS.get_ptr_mut(&key) {
+            unsafe { (*stats).record(&event) };
+        }
+        return Ok(());
+    }
+
     match EVENTS.reserve::<RawEvent>(0) {
         Some(mut entry) => {
             entry.write(event);
-            entry.submit(BPF_RB_FORCE_WAKEUP.into());
-        }
-        None => {
-            warn!(ctx, "EVENTS is full: skipping");
+            entry.submit(0);
+            count(Counter::EventsEmitted);
         }
+        // the flow is in `FLOWS` already, userspace finds it when harvesting
+        None => count(Counter::RingBufferFull),
+    };
+
+    Ok(())
+}
+
+/// Whether the packet passes the rules of the capture filter. The longest
+/// matching network of the peer decides, ports are checked on both ends.
+fn captures(event: &RawEvent) -> bool {
+    let Some(filter) = FILTER.get(0) else {
+        return true;
+    };
+
+    if !filter.captures(event.direction) {
+        return false;
+    }
+
+    if filter.include_protos != 0
+        && PROTO_RULES.get(event.proto.0 as u32).copied() != Some(RULE_INCLUDE)
+    {
+        return false;
+    }
+
+    let addr = Key::new(
+        AddrRuleKey::MAX_PREFIX_LEN,
+        AddrRuleKey::new(event.peer_addr()),
+    );
+    match ADDR_RULES.get(&addr).copied() {
+        Some(RULE_EXCLUDE) => return false,
+        None if filter.include_addrs != 0 => return false,
+        _ => {}
+    }
+
+    let ports = event.ports().map(|(src_port, dst_port)| {
+        [src_port, dst_port].map(|port| unsafe { PORT_RULES.get(&port) }.copied())
+    });
+    match ports {
+        Some(rules) if rules.contains(&Some(RULE_EXCLUDE)) => false,
+        Some(rules) if filter.include_ports != 0 => rules.contains(&Some(RULE_INCLUDE)),
+        None if filter.include_ports != 0 => false,
+        _ => true,
+    }
+}
+
+/// The rate the packet is counted with, `None` if it is skipped.
+fn sample(event: &RawEvent) -> Option<u32> {
+    let sampling = match SAMPLING.get(0) {
+        Some(sampling) if sampling.rate > 1 => *sampling,
+        _ => return Some(1),
+    };
+
+    let value = match sampling.mode {
+        SAMPLE_FLOW => event.flow_hash(),
+        _ => unsafe { bpf_get_prandom_u32() },
     };
 
-    Ok(TC_ACT_OK)
+    value.is_multiple_of(sampling.rate).then_some(sampling.rate)
+}
+
+fn count(counter: Counter) {
+    if let Some(value) = COUNTERS.get_ptr_mut(counter as u32) {
+        unsafe { *value += 1 };
+    }
 }
 
 #[cfg(not(test))]
@@ -0,0 +1,190 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, hash_map::Entry},
+    mem,
+    net::IpAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use palantir_ebpf_common::Direction;
+use tokio::sync::{Mutex, broadcast};
+
+use crate::event::{Event, Packet, PacketBatch};
+
+/// Coalesces packet events per peer until they are drained, so subscribers
+/// of busy hosts get a few events per interval instead of one per packet.
+#[derive(Debug, Clone, Default)]
+pub struct PacketBatcher {
+    batches: HashMap<(IpAddr, IpAddr), PacketBatch>,
+}
+
+impl PacketBatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    pub fn add(&mut self, packet: &Packet) {
+        let (local_addr, peer_addr) = match packet.direction {
+            Direction::Ingress => (packet.dst_addr, packet.src_addr),
+            Direction::Egress => (packet.src_addr, packet.dst_addr),
+        };
+
+        let batch = match self.batches.entry((local_addr, peer_addr)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(PacketBatch {
+                local_addr,
+                peer_addr,
+                country_code: packet.country_code.clone(),
+                events: 0,
+                packets: 0,
+                ingress_bytes: 0,
+                egress_bytes: 0,
+                protocols: BTreeMap::new(),
+                ports: BTreeSet::new(),
+                first_timestamp: packet.timestamp,
+                last_timestamp: packet.timestamp,
+                interfaces: BTreeMap::new(),
+                workloads: BTreeMap::new(),
+            }),
+        };
+
+        batch.events += 1;
+        batch.packets += packet.packets;
+        *batch.protocols.entry(packet.proto.to_string()).or_default() += packet.packets;
+        if let (Some(src_port), Some(dst_port)) = (packet.src_port, packet.dst_port) {
+            batch.ports.insert(src_port.min(dst_port));
+        }
+        batch.first_timestamp = batch.first_timestamp.min(packet.timestamp);
+        batch.last_timestamp = batch.last_timestamp.max(packet.timestamp);
+
+        let (ingress_bytes, egress_bytes) = match packet.direction {
+            Direction::Ingress => (packet.wire_bytes, 0),
+            Direction::Egress => (0, packet.wire_bytes),
+        };
+
+        batch.ingress_bytes += ingress_bytes;
+        batch.egress_bytes += egress_bytes;
+
+        if let Some(interface) = &packet.interface {
+            let traffic = batch.interfaces.entry(interface.clone()).or_default();
+            traffic.ingress_bytes += ingress_bytes;
+            traffic.egress_bytes += egress_bytes;
+        }
+
+        if let Some(workload) = &packet.workload {
+            let traffic = batch.workloads.entry(workload.to_string()).or_default();
+            traffic.ingress_bytes += ingress_bytes;
+            traffic.egress_bytes += egress_bytes;
+        }
+    }
+
+    /// Takes the batches collected since the last call.
+    pub fn drain(&mut self) -> Vec<PacketBatch> {
+        mem::take(&mut self.batches).into_values().collect()
+    }
+}
+
+/// Periodically sends the batches collected by `batcher` to `tx`.
+pub async fn flush_batches(
+    batcher: Arc<Mutex<PacketBatcher>>,
+    tx: broadcast::Sender<Event>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        for batch in batcher.lock().await.drain() {
+            _ = tx.send(Event::PacketBatch(batch));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, SystemTime},
+    };
+
+    use net::ip::IpProto;
+    use palantir_ebpf_common::Direction;
+
+    use super::PacketBatcher;
+    use crate::event::Packet;
+
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn packet(remote: IpAddr, direction: Direction, proto: IpProto, wire_bytes: u64) -> Packet {
+        let (src_addr, dst_addr, src_port, dst_port) = match direction {
+            Direction::Ingress => (remote, LOCAL, 443, 50000),
+            Direction::Egress => (LOCAL, remote, 50000, 443),
+        };
+
+        Packet {
+            proto,
+            src_addr,
+            dst_addr,
+            src_port: Some(src_port),
+            dst_port: Some(dst_port),
+            direction,
+            country_code: "AU".to_string(),
+            packets: 2,
+            bytes: wire_bytes - 14,
+            wire_bytes,
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(wire_bytes),
+            interface: Some("eth0".to_string()),
+            icmp: None,
+            process: None,
+            workload: None,
+            capture_sample_rate: 1,
+            sample_rate: 1,
+        }
+    }
+
+    #[test]
+    fn coalesces_per_peer() {
+        let first = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 2));
+        let mut batcher = PacketBatcher::new();
+
+        batcher.add(&packet(first, Direction::Ingress, IpProto::TCP, 1000));
+        batcher.add(&packet(first, Direction::Egress, IpProto::UDP, 100));
+        batcher.add(&packet(first, Direction::Egress, IpProto::TCP, 200));
+        batcher.add(&packet(second, Direction::Egress, IpProto::TCP, 300));
+        assert_eq!(batcher.len(), 2);
+
+        let mut batches = batcher.drain();
+        batches.sort_by_key(|batch| batch.peer_addr);
+        assert!(batcher.is_empty());
+
+        let batch = &batches[0];
+        assert_eq!((batch.local_addr, batch.peer_addr), (LOCAL, first));
+        assert_eq!(batch.events, 3);
+        assert_eq!(batch.packets, 6);
+        assert_eq!(batch.ingress_bytes, 1000);
+        assert_eq!(batch.egress_bytes, 300);
+        assert_eq!(batch.protocols["TCP"], 4);
+        assert_eq!(batch.protocols["UDP"], 2);
+        assert_eq!(batch.ports.iter().collect::<Vec<_>>(), [&443]);
+        assert_eq!(batch.interfaces["eth0"].egress_bytes, 300);
+        assert_eq!(
+            batch
+                .last_timestamp
+                .duration_since(batch.first_timestamp)
+                .unwrap(),
+            Duration::from_secs(900)
+        );
+
+        assert_eq!(batches[1].events, 1);
+    }
+}
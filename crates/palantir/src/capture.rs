@@ -0,0 +1,553 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, SystemTime},
+};
+
+use net::{eth::EthHdr, ip::IpProto};
+use palantir_ebpf_common::{Direction, ProcessInfo, RawEvent};
+
+use crate::source::Interface;
+
+/// Capture files start with a header followed by a flat sequence of
+/// fixed-size, little-endian records. `boot_time` anchors the `ts_offset_ns`
+/// of every record to wall clock time, the interface table maps the
+/// `ifindex` of records to names.
+///
+/// ```text
+/// header:    magic[8] version:u16 iface_count:u16 boot_secs:u64 boot_nanos:u32
+///            iface[iface_count]
+/// iface:     index:u32 name_len:u8 name[name_len]            (since v2)
+/// record:    ts_offset_ns:u64 pid:u32 src_addr[17] dst_addr[17] src_port:u16
+///            dst_port:u16 proto:u8 flags:u8 direction:u8
+///            bytes:u16                                       (until v2)
+///            bytes:u64 packets:u64                           (since v3)
+///            ifindex:u32                                     (since v2)
+///            wire_bytes:u64                                  (since v4)
+///            has_process:u8 pid:u32 tgid:u32 uid:u32
+///            cgroup_id:u64 comm[16]                          (since v5)
+///            sample_rate:u32                                 (since v6)
+/// ```
+///
+/// Before v4 `bytes` excluded the IPv6 header and `wire_bytes` is estimated
+/// by adding an Ethernet header per packet. The `pid` after the timestamp is
+/// unused since v5, it was the thread the TC classifier happened to run in.
+/// Counters are recorded scaled up by `sample_rate`, before v6 every packet
+/// was counted.
+pub const MAGIC: [u8; 8] = *b"PLNTRCAP";
+pub const VERSION: u16 = 6;
+
+const MIN_VERSION: u16 = 1;
+const HEADER_LEN: usize = 8 + 2 + 2 + 8 + 4;
+const ADDR_LEN: usize = 17;
+
+const FLAG_FRAGMENT: u8 = 0b01;
+const FLAG_LAST_FRAGMENT: u8 = 0b10;
+
+fn record_len(version: u16) -> usize {
+    let mut len = 8 + 4 + ADDR_LEN * 2 + 2 + 2 + 1 + 1 + 1;
+    if version >= 3 {
+        len += 8 + 8;
+    } else {
+        len += 2;
+    }
+    if version >= 2 {
+        len += 4;
+    }
+    if version >= 4 {
+        len += 8;
+    }
+    if version >= 5 {
+        len += 1 + 4 + 4 + 4 + 8 + 16;
+    }
+    if version >= 6 {
+        len += 4;
+    }
+    len
+}
+
+pub struct CaptureWriter<W>
+where
+    W: Write,
+{
+    writer: W,
+}
+
+impl<W> CaptureWriter<W>
+where
+    W: Write,
+{
+    pub fn new(mut writer: W, boot_time: SystemTime, interfaces: &[Interface]) -> io::Result<Self> {
+        let boot_time = boot_time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+
+        let iface_count = u16::try_from(interfaces.len())
+            .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+
+        let mut header = [0u8; HEADER_LEN];
+        header[0..8].copy_from_slice(&MAGIC);
+        header[8..10].copy_from_slice(&VERSION.to_le_bytes());
+        header[10..12].copy_from_slice(&iface_count.to_le_bytes());
+        header[12..20].copy_from_slice(&boot_time.as_secs().to_le_bytes());
+        header[20..24].copy_from_slice(&boot_time.subsec_nanos().to_le_bytes());
+        writer.write_all(&header)?;
+
+        for interface in interfaces {
+            let name_len = u8::try_from(interface.name.len())
+                .map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+
+            writer.write_all(&interface.index.to_le_bytes())?;
+            writer.write_all(&[name_len])?;
+            writer.write_all(interface.name.as_bytes())?;
+        }
+
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, event: &RawEvent) -> io::Result<()> {
+        self.writer.write_all(&encode(event))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+pub struct CaptureReader<R>
+where
+    R: Read,
+{
+    reader: R,
+    version: u16,
+    boot_time: SystemTime,
+    interfaces: Vec<Interface>,
+}
+
+impl<R> CaptureReader<R>
+where
+    R: Read,
+{
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; HEADER_LEN];
+        reader.read_exact(&mut header)?;
+
+        if header[0..8] != MAGIC {
+            return Err(invalid_data("not a palantir capture file"));
+        }
+
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(invalid_data(format!(
+                "unsupported capture version {version}, expected {MIN_VERSION} to {VERSION}"
+            )));
+        }
+
+        let secs = u64::from_le_bytes(header[12..20].try_into().unwrap());
+        let nanos = u32::from_le_bytes(header[20..24].try_into().unwrap());
+        let boot_time = SystemTime::UNIX_EPOCH + Duration::new(secs, nanos);
+
+        let mut interfaces = Vec::new();
+        if version >= 2 {
+            let iface_count = u16::from_le_bytes([header[10], header[11]]);
+
+            for _ in 0..iface_count {
+                let mut entry = [0u8; 5];
+                reader.read_exact(&mut entry)?;
+
+                let mut name = vec![0u8; entry[4] as usize];
+                reader.read_exact(&mut name)?;
+
+                interfaces.push(Interface {
+                    index: u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                    name: String::from_utf8(name).map_err(invalid_data)?,
+                });
+            }
+        }
+
+        Ok(Self {
+            reader,
+            version,
+            boot_time,
+            interfaces,
+        })
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn boot_time(&self) -> SystemTime {
+        self.boot_time
+    }
+
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    /// Returns `Ok(None)` once the end of the file is reached on a record
+    /// boundary; a truncated trailing record is reported as an error.
+    pub fn read(&mut self) -> io::Result<Option<RawEvent>> {
+        let mut record = vec![0u8; record_len(self.version)];
+        let mut filled = 0;
+
+        while filled < record.len() {
+            match self.reader.read(&mut record[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        decode(self.version, &record).map(Some)
+    }
+}
+
+fn encode(event: &RawEvent) -> Vec<u8> {
+    let mut record = Vec::with_capacity(record_len(VERSION));
+
+    let mut flags = 0;
+    if event.fragment {
+        flags |= FLAG_FRAGMENT;
+    }
+    if event.last_fragment {
+        flags |= FLAG_LAST_FRAGMENT;
+    }
+
+    record.extend_from_slice(&event.ts_offset_ns.to_le_bytes());
+    record.extend_from_slice(&0u32.to_le_bytes());
+    record.extend_from_slice(&encode_addr(event.src_addr));
+    record.extend_from_slice(&encode_addr(event.dst_addr));
+    record.extend_from_slice(&event.src_port.to_le_bytes());
+    record.extend_from_slice(&event.dst_port.to_le_bytes());
+    record.extend_from_slice(&[event.proto.0, flags, event.direction as u8]);
+    record.extend_from_slice(&event.bytes.to_le_bytes());
+    record.extend_from_slice(&event.packets.to_le_bytes());
+    record.extend_from_slice(&event.ifindex.to_le_bytes());
+    record.extend_from_slice(&event.wire_bytes.to_le_bytes());
+
+    let process = event.process.unwrap_or_default();
+    record.push(event.process.is_some() as u8);
+    record.extend_from_slice(&process.pid.to_le_bytes());
+    record.extend_from_slice(&process.tgid.to_le_bytes());
+    record.extend_from_slice(&process.uid.to_le_bytes());
+    record.extend_from_slice(&process.cgroup_id.to_le_bytes());
+    record.extend_from_slice(&process.comm);
+    record.extend_from_slice(&event.sample_rate.to_le_bytes());
+
+    record
+}
+
+fn decode(version: u16, record: &[u8]) -> io::Result<RawEvent> {
+    let mut offset = 0;
+
+    let mut take = move |len: usize| {
+        let bytes = &record[offset..offset + len];
+        offset += len;
+        bytes
+    };
+
+    let ts_offset_ns = u64::from_le_bytes(take(8).try_into().unwrap());
+    let _pid = take(4);
+    let src_addr = decode_addr(take(ADDR_LEN))?;
+    let dst_addr = decode_addr(take(ADDR_LEN))?;
+    let src_port = u16::from_le_bytes(take(2).try_into().unwrap());
+    let dst_port = u16::from_le_bytes(take(2).try_into().unwrap());
+    let [proto, flags, direction] = take(3).try_into().unwrap();
+    let (bytes, packets) = match version >= 3 {
+        true => (
+            u64::from_le_bytes(take(8).try_into().unwrap()),
+            u64::from_le_bytes(take(8).try_into().unwrap()),
+        ),
+        false => (u16::from_le_bytes(take(2).try_into().unwrap()) as u64, 1),
+    };
+    let ifindex = match version >= 2 {
+        true => u32::from_le_bytes(take(4).try_into().unwrap()),
+        false => 0,
+    };
+    let wire_bytes = match version >= 4 {
+        true => u64::from_le_bytes(take(8).try_into().unwrap()),
+        false => bytes + packets * size_of::<EthHdr>() as u64,
+    };
+    let process = match version >= 5 {
+        true => {
+            let has_process = take(1)[0] != 0;
+            let process = ProcessInfo {
+                pid: u32::from_le_bytes(take(4).try_into().unwrap()),
+                tgid: u32::from_le_bytes(take(4).try_into().unwrap()),
+                uid: u32::from_le_bytes(take(4).try_into().unwrap()),
+                _pad: 0,
+                cgroup_id: u64::from_le_bytes(take(8).try_into().unwrap()),
+                comm: take(16).try_into().unwrap(),
+            };
+            has_process.then_some(process)
+        }
+        false => None,
+    };
+    let sample_rate = match version >= 6 {
+        true => u32::from_le_bytes(take(4).try_into().unwrap()),
+        false => 1,
+    };
+
+    let direction = match direction {
+        0 => Direction::Ingress,
+        1 => Direction::Egress,
+        _ => return Err(invalid_data(format!("unknown direction {direction}"))),
+    };
+
+    Ok(RawEvent {
+        process,
+        ifindex,
+        src_addr,
+        dst_addr,
+        src_port,
+        dst_port,
+        ts_offset_ns,
+        proto: IpProto(proto),
+        fragment: flags & FLAG_FRAGMENT != 0,
+        last_fragment: flags & FLAG_LAST_FRAGMENT != 0,
+        direction,
+        packets,
+        bytes,
+        wire_bytes,
+        sample_rate,
+    })
+}
+
+fn encode_addr(addr: IpAddr) -> [u8; ADDR_LEN] {
+    let mut bytes = [0u8; ADDR_LEN];
+
+    match addr {
+        IpAddr::V4(addr) => {
+            bytes[0] = 4;
+            bytes[1..5].copy_from_slice(&addr.octets());
+        }
+        IpAddr::V6(addr) => {
+            bytes[0] = 6;
+            bytes[1..17].copy_from_slice(&addr.octets());
+        }
+    }
+
+    bytes
+}
+
+fn decode_addr(bytes: &[u8]) -> io::Result<IpAddr> {
+    match bytes[0] {
+        4 => Ok(IpAddr::V4(Ipv4Addr::from(
+            <[u8; 4]>::try_from(&bytes[1..5]).unwrap(),
+        ))),
+        6 => Ok(IpAddr::V6(Ipv6Addr::from(
+            <[u8; 16]>::try_from(&bytes[1..17]).unwrap(),
+        ))),
+        family => Err(invalid_data(format!("unknown address family {family}"))),
+    }
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::ErrorKind,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        time::{Duration, SystemTime},
+    };
+
+    use net::ip::IpProto;
+    use palantir_ebpf_common::{Direction, ProcessInfo, RawEvent};
+
+    use super::{CaptureReader, CaptureWriter, MAGIC, MIN_VERSION, VERSION, encode_addr};
+    use crate::{fixtures, source::Interface};
+
+    const SRC: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+    const DST: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+
+    fn boot_time() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 500)
+    }
+
+    fn interfaces() -> Vec<Interface> {
+        vec![Interface {
+            index: 2,
+            name: "eth0".to_string(),
+        }]
+    }
+
+    fn event() -> RawEvent {
+        let mut process = ProcessInfo {
+            pid: 101,
+            tgid: 100,
+            uid: 1000,
+            cgroup_id: 42,
+            ..ProcessInfo::default()
+        };
+        process.comm[..5].copy_from_slice(b"nginx");
+
+        RawEvent {
+            process: Some(process),
+            ifindex: 2,
+            ts_offset_ns: 1_000,
+            fragment: true,
+            packets: 3,
+            bytes: 3000,
+            sample_rate: 10,
+            ..fixtures::event(SRC, DST, Direction::Egress, 3042)
+        }
+    }
+
+    fn header(version: u16, interfaces: &[Interface]) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&version.to_le_bytes());
+        header.extend_from_slice(&(interfaces.len() as u16).to_le_bytes());
+        header.extend_from_slice(&1_700_000_000u64.to_le_bytes());
+        header.extend_from_slice(&500u32.to_le_bytes());
+        for interface in interfaces {
+            header.extend_from_slice(&interface.index.to_le_bytes());
+            header.push(interface.name.len() as u8);
+            header.extend_from_slice(interface.name.as_bytes());
+        }
+        header
+    }
+
+    /// A record laid out by hand as `version` wrote it.
+    fn record(version: u16) -> Vec<u8> {
+        let mut record = 1_000u64.to_le_bytes().to_vec();
+        record.extend_from_slice(&7u32.to_le_bytes());
+        record.extend_from_slice(&encode_addr(SRC));
+        record.extend_from_slice(&encode_addr(SRC));
+        record.extend_from_slice(&443u16.to_le_bytes());
+        record.extend_from_slice(&50000u16.to_le_bytes());
+        record.extend_from_slice(&[IpProto::UDP.0, 0b11, 0]);
+        match version >= 3 {
+            true => {
+                record.extend_from_slice(&3000u64.to_le_bytes());
+                record.extend_from_slice(&3u64.to_le_bytes());
+            }
+            false => record.extend_from_slice(&1000u16.to_le_bytes()),
+        }
+        if version >= 2 {
+            record.extend_from_slice(&2u32.to_le_bytes());
+        }
+        if version >= 4 {
+            record.extend_from_slice(&3100u64.to_le_bytes());
+        }
+        if version >= 5 {
+            record.push(1);
+            record.extend_from_slice(&[0; 4 + 4 + 4 + 8]);
+            record.extend_from_slice(b"curl\0\0\0\0\0\0\0\0\0\0\0\0");
+        }
+        if version >= 6 {
+            record.extend_from_slice(&5u32.to_le_bytes());
+        }
+        record
+    }
+
+    #[test]
+    fn round_trips_events() {
+        let mut file = Vec::new();
+        let mut writer = CaptureWriter::new(&mut file, boot_time(), &interfaces()).unwrap();
+        let unsampled = RawEvent {
+            process: None,
+            src_addr: DST,
+            dst_addr: SRC,
+            sample_rate: 1,
+            ..event()
+        };
+        writer.write(&event()).unwrap();
+        writer.write(&unsampled).unwrap();
+        writer.flush().unwrap();
+
+        let mut reader = CaptureReader::new(file.as_slice()).unwrap();
+        assert_eq!(reader.version(), VERSION);
+        assert_eq!(reader.boot_time(), boot_time());
+        assert_eq!(reader.interfaces(), interfaces());
+
+        for expected in [event(), unsampled] {
+            let decoded = reader.read().unwrap().unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{expected:?}"));
+        }
+        assert!(reader.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn decodes_every_version() {
+        for version in MIN_VERSION..=VERSION {
+            let interfaces = match version >= 2 {
+                true => interfaces(),
+                false => Vec::new(),
+            };
+            let mut file = header(version, &interfaces);
+            file.extend_from_slice(&record(version));
+
+            let mut reader = CaptureReader::new(file.as_slice()).unwrap();
+            assert_eq!(reader.interfaces(), interfaces, "v{version}");
+
+            let event = reader.read().unwrap().unwrap();
+            assert_eq!(event.ts_offset_ns, 1_000, "v{version}");
+            assert_eq!(event.src_addr, SRC, "v{version}");
+            assert_eq!(event.proto, IpProto::UDP, "v{version}");
+            assert!(event.fragment && event.last_fragment, "v{version}");
+            assert_eq!(event.direction, Direction::Ingress, "v{version}");
+
+            let (bytes, packets) = match version >= 3 {
+                true => (3000, 3),
+                false => (1000, 1),
+            };
+            assert_eq!((event.bytes, event.packets), (bytes, packets), "v{version}");
+            assert_eq!(event.ifindex, (version >= 2) as u32 * 2, "v{version}");
+
+            let wire_bytes = match version >= 4 {
+                true => 3100,
+                false => bytes + packets * 14,
+            };
+            assert_eq!(event.wire_bytes, wire_bytes, "v{version}");
+            assert_eq!(
+                event.process.map(|process| process.comm[..4] == *b"curl"),
+                (version >= 5).then_some(true),
+                "v{version}"
+            );
+            assert_eq!(
+                event.sample_rate,
+                if version >= 6 { 5 } else { 1 },
+                "v{version}"
+            );
+
+            assert!(reader.read().unwrap().is_none(), "v{version}");
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let mut file = header(VERSION, &interfaces());
+        file.extend_from_slice(&record(VERSION));
+        file.pop();
+
+        let mut reader = CaptureReader::new(file.as_slice()).unwrap();
+        let err = reader.read().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn rejects_unsupported_files() {
+        for version in [MIN_VERSION - 1, VERSION + 1] {
+            let file = header(version, &[]);
+            let err = CaptureReader::new(file.as_slice()).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "v{version}");
+        }
+
+        let mut file = header(VERSION, &[]);
+        file[0] = b'X';
+        let err = CaptureReader::new(file.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err = CaptureReader::new(&MAGIC[..]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,770 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt, fs, io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use net::ip::IpProto;
+use palantir_ebpf_common::{
+    Direction, MAX_ADDR_RULES, MAX_PORT_RULES, RawEvent, SAMPLE_FLOW, SAMPLE_RANDOM, Sampling,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cidr::Cidr,
+    event::Peer,
+    history::HistoryStore,
+    resolver::{IpInfo, LocationDetails},
+    server::{CHANNEL_CAPACITY, TOP_PEERS},
+    workload::{DockerProvider, FileProvider, WorkloadResolver},
+};
+
+/// Contents of the TOML configuration file. Every field is optional in the
+/// file itself; required values are checked by [`Config::validate`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: SocketAddr,
+    /// Interface names or glob patterns such as `bond*`.
+    pub interfaces: Vec<String>,
+    pub server: ServerConfig,
+    pub geoip: GeoIpConfig,
+    pub filter: FilterConfig,
+    pub sampling: SamplingConfig,
+    pub capture: CaptureConfig,
+    pub retention: RetentionConfig,
+    pub workloads: WorkloadConfig,
+    pub history: HistoryConfig,
+    pub events: EventsConfig,
+    pub metrics: MetricsConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: Option<IpAddr>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub country_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoIpConfig {
+    /// GeoLite2/GeoIP2 City database, the embedded copy is used if unset.
+    pub city: Option<PathBuf>,
+    /// GeoLite2/GeoIP2 ASN database, peers have no autonomous system if unset.
+    pub asn: Option<PathBuf>,
+}
+
+/// Traffic to capture, applied by the eBPF classifiers and again by the
+/// pipeline for other sources. Served and replaced at runtime by `/filter`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Networks of remote peers to capture, every peer if empty. The longest
+    /// matching network of both lists decides, exclusion wins a tie.
+    pub include_addrs: Vec<Cidr>,
+    pub exclude_addrs: Vec<Cidr>,
+    /// Ports to capture on either end, every port if empty. ICMP has none.
+    pub include_ports: Vec<u16>,
+    pub exclude_ports: Vec<u16>,
+    /// IANA keywords like `tcp` or protocol numbers, every protocol if empty.
+    #[serde(with = "protocols")]
+    pub protocols: Vec<IpProto>,
+    /// Only traffic in this direction is captured, both if unset.
+    pub direction: Option<Direction>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplingConfig {
+    /// Only one in this many packets is counted by the eBPF classifiers, the
+    /// counters are scaled up by the rate as they are counted.
+    pub rate: u32,
+    pub mode: SamplingMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplingMode {
+    /// Every packet is counted with a probability of one in `rate`.
+    #[default]
+    Random,
+    /// Every packet of one in `rate` connections is counted, which keeps the
+    /// traffic of a connection together at the cost of more variance.
+    Flow,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Program capturing ingress traffic, egress is always captured by a TC
+    /// classifier.
+    pub ingress: IngressHook,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IngressHook {
+    /// A TC classifier, after the stack allocated the socket buffer.
+    #[default]
+    Tc,
+    /// An XDP program in native mode, run by the driver. Interfaces whose
+    /// driver lacks XDP support fall back to the TC classifier.
+    Xdp,
+    /// An XDP program in generic mode, supported by every driver but hardly
+    /// cheaper than TC.
+    XdpGeneric,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Seconds after their last message peers are dropped from the table.
+    pub peer_ttl: Option<u64>,
+    /// Remote peers kept in the table, the least recently active are dropped.
+    pub max_peers: usize,
+    /// Addresses whose location is cached.
+    pub geo_cache_size: usize,
+    /// Peer, country and port time series kept for `/rates`, the least
+    /// recently active are dropped.
+    pub max_series: usize,
+    /// Processes kept for `/processes`, the least recently active are dropped.
+    pub max_processes: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkloadConfig {
+    /// Resolve the cgroups of processes to containers and services.
+    pub enabled: bool,
+    pub cgroup_root: PathBuf,
+    /// State directory of the Docker daemon, container names are read from it.
+    pub docker_root: Option<PathBuf>,
+    /// Static workload mapping, asked before Docker, see [`FileProvider`].
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Directory of the history segments, nothing is persisted if unset.
+    pub path: Option<PathBuf>,
+    /// Seconds between checkpoints, the resolution of the stored traffic.
+    pub interval: u64,
+    /// Days of history kept, everything is kept if unset.
+    pub retention_days: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventsConfig {
+    /// Events buffered for every subscriber, slower subscribers are told how
+    /// many they missed and get the peer table again.
+    pub channel_capacity: usize,
+    /// Milliseconds packet events are coalesced per peer for, every packet
+    /// is sent on its own if unset.
+    pub batch_interval_ms: Option<u64>,
+    /// Only one in this many packet events is sent when not batching.
+    pub sample_rate: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Busiest peers exported with their address as a label on `/metrics`,
+    /// bounding the number of series.
+    pub top_peers: usize,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Missing(&'static str),
+    Invalid(&'static str, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) => {
+                write!(f, "failed to parse {}: {}", path.display(), err)
+            }
+            ConfigError::Missing(field) => write!(f, "{field} is not defined"),
+            ConfigError::Invalid(field, reason) => write!(f, "{field} is invalid: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            interfaces: Vec::new(),
+            server: ServerConfig::default(),
+            geoip: GeoIpConfig::default(),
+            filter: FilterConfig::default(),
+            sampling: SamplingConfig::default(),
+            capture: CaptureConfig::default(),
+            retention: RetentionConfig::default(),
+            workloads: WorkloadConfig::default(),
+            history: HistoryConfig::default(),
+            events: EventsConfig::default(),
+            metrics: MetricsConfig::default(),
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            top_peers: TOP_PEERS,
+        }
+    }
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            rate: 1,
+            mode: SamplingMode::Random,
+        }
+    }
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            channel_capacity: CHANNEL_CAPACITY,
+            batch_interval_ms: None,
+            sample_rate: 1,
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval: 60,
+            retention_days: None,
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            peer_ttl: None,
+            max_peers: 10_000,
+            geo_cache_size: 65_536,
+            max_series: 20_000,
+            max_processes: 4096,
+        }
+    }
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cgroup_root: PathBuf::from("/sys/fs/cgroup"),
+            docker_root: Some(PathBuf::from("/var/lib/docker")),
+            file: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    /// Checks that all values required to run are present and in range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.local_peer()?;
+
+        if let Some(0) = self.retention.peer_ttl {
+            return Err(ConfigError::Invalid(
+                "retention.peer_ttl",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.retention.max_peers == 0 {
+            return Err(ConfigError::Invalid(
+                "retention.max_peers",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.retention.geo_cache_size == 0 {
+            return Err(ConfigError::Invalid(
+                "retention.geo_cache_size",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.retention.max_series == 0 {
+            return Err(ConfigError::Invalid(
+                "retention.max_series",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.retention.max_processes == 0 {
+            return Err(ConfigError::Invalid(
+                "retention.max_processes",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.events.channel_capacity == 0 {
+            return Err(ConfigError::Invalid(
+                "events.channel_capacity",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if let Some(0) = self.events.batch_interval_ms {
+            return Err(ConfigError::Invalid(
+                "events.batch_interval_ms",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.events.sample_rate == 0 {
+            return Err(ConfigError::Invalid(
+                "events.sample_rate",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        self.filter.validate()?;
+
+        if self.sampling.rate == 0 {
+            return Err(ConfigError::Invalid(
+                "sampling.rate",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.history.interval == 0 {
+            return Err(ConfigError::Invalid(
+                "history.interval",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if let Some(0) = self.history.retention_days {
+            return Err(ConfigError::Invalid(
+                "history.retention_days",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn local_peer(&self) -> Result<Peer, ConfigError> {
+        let addr = self
+            .server
+            .addr
+            .ok_or(ConfigError::Missing("server.addr"))?;
+        let lat = self.server.lat.ok_or(ConfigError::Missing("server.lat"))?;
+        let lon = self.server.lon.ok_or(ConfigError::Missing("server.lon"))?;
+        let country_code = self
+            .server
+            .country_code
+            .clone()
+            .ok_or(ConfigError::Missing("server.country_code"))?;
+
+        if !(-90.0..=90.0).contains(&lat) {
+            return Err(ConfigError::Invalid(
+                "server.lat",
+                format!("{lat} is not within -90 and 90"),
+            ));
+        }
+
+        if !(-180.0..=180.0).contains(&lon) {
+            return Err(ConfigError::Invalid(
+                "server.lon",
+                format!("{lon} is not within -180 and 180"),
+            ));
+        }
+
+        if country_code.len() != 2 || !country_code.bytes().all(|b| b.is_ascii_uppercase()) {
+            return Err(ConfigError::Invalid(
+                "server.country_code",
+                format!("{country_code:?} is not an ISO 3166-1 alpha-2 code"),
+            ));
+        }
+
+        Ok(Peer {
+            addr,
+            info: IpInfo {
+                lat,
+                lon,
+                country_code,
+                details: LocationDetails::Manual,
+                asn: None,
+            },
+            ingress_bytes: 0,
+            egress_bytes: 0,
+            last_message: None,
+            interfaces: BTreeMap::new(),
+            workloads: BTreeMap::new(),
+        })
+    }
+
+    pub fn city_db(&self) -> Result<Cow<'static, [u8]>, ConfigError> {
+        match &self.geoip.city {
+            Some(path) => fs::read(path)
+                .map(Cow::Owned)
+                .map_err(|err| ConfigError::Read(path.clone(), err)),
+            None => Ok(Cow::Borrowed(include_bytes!(
+                "../../../assets/GeoLite2-City.mmdb"
+            ))),
+        }
+    }
+
+    pub fn asn_db(&self) -> Result<Option<Cow<'static, [u8]>>, ConfigError> {
+        self.geoip
+            .asn
+            .as_ref()
+            .map(|path| {
+                fs::read(path)
+                    .map(Cow::Owned)
+                    .map_err(|err| ConfigError::Read(path.clone(), err))
+            })
+            .transpose()
+    }
+}
+
+impl FilterConfig {
+    /// Checks that the rules fit into the maps of the eBPF classifiers.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let addr_rules = self.include_addrs.len() + self.exclude_addrs.len();
+        if addr_rules > MAX_ADDR_RULES as usize {
+            return Err(ConfigError::Invalid(
+                "filter",
+                format!("{addr_rules} address rules exceed the limit of {MAX_ADDR_RULES}"),
+            ));
+        }
+
+        let port_rules = self.include_ports.len() + self.exclude_ports.len();
+        if port_rules > MAX_PORT_RULES as usize {
+            return Err(ConfigError::Invalid(
+                "filter",
+                format!("{port_rules} port rules exceed the limit of {MAX_PORT_RULES}"),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the event is outside the captured traffic, with the same
+    /// rules as the eBPF classifiers.
+    pub fn excludes(&self, raw_event: &RawEvent) -> bool {
+        if self
+            .direction
+            .is_some_and(|direction| direction != raw_event.direction)
+        {
+            return true;
+        }
+
+        if !self.protocols.is_empty() && !self.protocols.contains(&raw_event.proto) {
+            return true;
+        }
+
+        let peer_addr = raw_event.peer_addr();
+        let longest_match = |cidrs: &[Cidr]| {
+            cidrs
+                .iter()
+                .filter(|cidr| cidr.contains(&peer_addr))
+                .map(Cidr::prefix_len)
+                .max()
+        };
+        match (
+            longest_match(&self.include_addrs),
+            longest_match(&self.exclude_addrs),
+        ) {
+            (Some(include), Some(exclude)) if exclude >= include => return true,
+            (None, Some(_)) => return true,
+            (None, None) if !self.include_addrs.is_empty() => return true,
+            _ => {}
+        }
+
+        match raw_event.ports() {
+            Some((src_port, dst_port)) => {
+                let has = |ports: &[u16]| ports.contains(&src_port) || ports.contains(&dst_port);
+
+                has(&self.exclude_ports)
+                    || (!self.include_ports.is_empty() && !has(&self.include_ports))
+            }
+            None => !self.include_ports.is_empty(),
+        }
+    }
+}
+
+impl SamplingConfig {
+    /// The value of the `SAMPLING` map of the eBPF classifiers.
+    pub fn sampling(&self) -> Sampling {
+        Sampling {
+            rate: self.rate,
+            mode: match self.mode {
+                SamplingMode::Random => SAMPLE_RANDOM,
+                SamplingMode::Flow => SAMPLE_FLOW,
+            },
+        }
+    }
+}
+
+/// Protocols as IANA keywords, numbers are accepted for unnamed ones.
+mod protocols {
+    use net::ip::IpProto;
+    use serde::{Deserialize, Deserializer, Serializer, de};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Number(u8),
+        Name(String),
+    }
+
+    pub fn serialize<S>(protos: &[IpProto], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(protos.iter().map(IpProto::to_string))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<IpProto>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<Repr>::deserialize(deserializer)?
+            .into_iter()
+            .map(|repr| match repr {
+                Repr::Number(number) => Ok(IpProto(number)),
+                Repr::Name(name) => IpProto::from_name(&name)
+                    .or_else(|| name.parse().ok().map(IpProto))
+                    .ok_or_else(|| de::Error::custom(format!("unknown protocol {name:?}"))),
+            })
+            .collect()
+    }
+}
+
+impl WorkloadConfig {
+    /// Builds the resolver with the configured providers, `None` if disabled.
+    pub fn resolver(&self) -> Result<Option<WorkloadResolver>, ConfigError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+
+        let mut resolver = WorkloadResolver::new(&self.cgroup_root);
+
+        if let Some(path) = &self.file {
+            let provider = FileProvider::from_file(path)
+                .map_err(|err| ConfigError::Read(path.clone(), err))?;
+            resolver = resolver.with_provider(provider);
+        }
+
+        if let Some(root) = &self.docker_root {
+            resolver = resolver.with_provider(DockerProvider::new(root));
+        }
+
+        Ok(Some(resolver))
+    }
+}
+
+impl HistoryConfig {
+    /// Opens the store if a path is configured.
+    pub fn store(&self) -> Result<Option<HistoryStore>, ConfigError> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        let retention = self
+            .retention_days
+            .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+
+        HistoryStore::open(path, retention)
+            .map(Some)
+            .map_err(|err| ConfigError::Read(path.clone(), err))
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+}
+
+impl EventsConfig {
+    pub fn batch_interval(&self) -> Option<Duration> {
+        self.batch_interval_ms.map(Duration::from_millis)
+    }
+}
+
+impl RetentionConfig {
+    pub fn peer_ttl(&self) -> Option<Duration> {
+        self.peer_ttl.map(Duration::from_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use net::ip::IpProto;
+    use palantir_ebpf_common::{Direction, RawEvent};
+
+    use super::{Config, ConfigError, FilterConfig};
+    use crate::fixtures;
+
+    const SERVER: &str = r#"
+        [server]
+        addr = "192.0.2.1"
+        lat = 48.2
+        lon = 16.4
+        country_code = "AT"
+    "#;
+
+    fn validate(extra: &str) -> Result<(), ConfigError> {
+        toml::from_str::<Config>(&format!("{SERVER}\n{extra}"))
+            .unwrap()
+            .validate()
+    }
+
+    fn event(peer_addr: [u8; 4], proto: IpProto, port: u16) -> RawEvent {
+        RawEvent {
+            src_port: port,
+            dst_port: 40000,
+            proto,
+            ..fixtures::event(
+                IpAddr::from(peer_addr),
+                fixtures::LOCAL,
+                Direction::Ingress,
+                114,
+            )
+        }
+    }
+
+    #[test]
+    fn longest_prefix_decides() {
+        let filter: FilterConfig = toml::from_str(
+            r#"
+            include_addrs = ["10.0.0.0/8", "10.1.1.0/24"]
+            exclude_addrs = ["10.1.0.0/16", "10.2.0.0/16", "10.2.0.0/16"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(!filter.excludes(&event([10, 0, 0, 1], IpProto::TCP, 443)));
+        assert!(filter.excludes(&event([10, 1, 0, 1], IpProto::TCP, 443)));
+        assert!(!filter.excludes(&event([10, 1, 1, 1], IpProto::TCP, 443)));
+        assert!(filter.excludes(&event([10, 2, 0, 1], IpProto::TCP, 443)));
+        assert!(filter.excludes(&event([192, 0, 2, 2], IpProto::TCP, 443)));
+    }
+
+    #[test]
+    fn ports_protocols_and_direction() {
+        let filter: FilterConfig = toml::from_str(
+            r#"
+            include_ports = [53, 443]
+            exclude_ports = [40000]
+            protocols = ["udp", "icmp", 6]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            filter.protocols,
+            [IpProto::UDP, IpProto::ICMP, IpProto::TCP]
+        );
+        assert!(filter.excludes(&event([1, 1, 1, 1], IpProto::UDP, 53)));
+
+        let filter = FilterConfig {
+            exclude_ports: Vec::new(),
+            ..filter
+        };
+        assert!(!filter.excludes(&event([1, 1, 1, 1], IpProto::UDP, 53)));
+        assert!(filter.excludes(&event([1, 1, 1, 1], IpProto::UDP, 123)));
+        assert!(filter.excludes(&event([1, 1, 1, 1], IpProto::ICMP, 8)));
+        assert!(filter.excludes(&event([1, 1, 1, 1], IpProto(47), 53)));
+
+        let filter = FilterConfig {
+            direction: Some(Direction::Egress),
+            ..FilterConfig::default()
+        };
+        assert!(filter.excludes(&event([1, 1, 1, 1], IpProto::UDP, 53)));
+    }
+
+    #[test]
+    fn accepts_defaults() {
+        validate("").unwrap();
+    }
+
+    #[test]
+    fn requires_the_server() {
+        let config = toml::from_str::<Config>("").unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Missing("server.addr"))
+        ));
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        for (extra, field) in [
+            ("[retention]\npeer_ttl = 0", "retention.peer_ttl"),
+            ("[retention]\nmax_peers = 0", "retention.max_peers"),
+            (
+                "[retention]\ngeo_cache_size = 0",
+                "retention.geo_cache_size",
+            ),
+            ("[retention]\nmax_series = 0", "retention.max_series"),
+            ("[retention]\nmax_processes = 0", "retention.max_processes"),
+            ("[events]\nchannel_capacity = 0", "events.channel_capacity"),
+            (
+                "[events]\nbatch_interval_ms = 0",
+                "events.batch_interval_ms",
+            ),
+            ("[events]\nsample_rate = 0", "events.sample_rate"),
+            ("[sampling]\nrate = 0", "sampling.rate"),
+            ("[history]\ninterval = 0", "history.interval"),
+            ("[history]\nretention_days = 0", "history.retention_days"),
+        ] {
+            match validate(extra) {
+                Err(ConfigError::Invalid(invalid, _)) => assert_eq!(invalid, field),
+                other => panic!("{field} was not rejected: {other:?}"),
+            }
+        }
+
+        for (from, to, field) in [
+            ("48.2", "91.0", "server.lat"),
+            ("16.4", "-180.5", "server.lon"),
+            ("\"AT\"", "\"at\"", "server.country_code"),
+        ] {
+            let config = toml::from_str::<Config>(&SERVER.replace(from, to)).unwrap();
+            match config.validate() {
+                Err(ConfigError::Invalid(invalid, _)) => assert_eq!(invalid, field),
+                other => panic!("{field} was not rejected: {other:?}"),
+            }
+        }
+
+        let filter = format!("[filter]\nexclude_ports = {:?}", vec![1u16; 1025]);
+        assert!(matches!(
+            validate(&filter),
+            Err(ConfigError::Invalid("filter", _))
+        ));
+    }
+}
@@ -1,9 +1,14 @@
-use std::{net::IpAddr, time::SystemTime};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+    time::SystemTime,
+};
 
 use net::ip::IpProto;
-use serde::Serialize;
+use palantir_ebpf_common::{Direction, ProcessInfo};
+use serde::{Deserialize, Serialize};
 
-use crate::resolver::IpInfo;
+use crate::{resolver::IpInfo, workload::Workload};
 
 #[derive(Debug, Clone, Serialize)]
 pub enum Event {
@@ -11,15 +16,98 @@ pub enum Event {
     Peer(Peer),
     #[serde(rename = "packet")]
     Packet(Packet),
+    /// Sent instead of packet events when batching is enabled.
+    #[serde(rename = "packet_batch")]
+    PacketBatch(PacketBatch),
+    #[serde(rename = "peer_removed")]
+    PeerRemoved(PeerRemoved),
+    /// Only sent to a subscriber that fell behind, the peer table is sent
+    /// again right after it.
+    #[serde(rename = "lagged")]
+    Lagged(Lagged),
+    /// Sent when the eBPF programs missed packets since the previous one,
+    /// and after the peer table if they ever did.
+    #[serde(rename = "capture")]
+    Capture(CaptureStats),
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Peer {
     pub addr: IpAddr,
     pub info: IpInfo,
+    /// On-wire bytes, see [`crate::PeerTable`].
     pub ingress_bytes: u64,
     pub egress_bytes: u64,
     pub last_message: Option<SystemTime>,
+    /// Byte counters per interface name, for events with a known interface.
+    pub interfaces: BTreeMap<String, Traffic>,
+    /// Byte counters per workload, for events with a known workload.
+    pub workloads: BTreeMap<String, Traffic>,
+}
+
+/// A peer dropped from the table, clients should forget it as well.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerRemoved {
+    pub addr: IpAddr,
+    pub reason: RemovalReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalReason {
+    /// Idle for longer than the TTL.
+    Idle,
+    /// The least recently active peer of a full table.
+    Evicted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Lagged {
+    /// Events the subscriber missed.
+    pub skipped: u64,
+}
+
+/// Counters of the eBPF classifiers since they were loaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CaptureStats {
+    /// New flows announced on the ring buffer.
+    pub events_emitted: u64,
+    /// New flows not announced because the ring buffer was full, their
+    /// traffic is still counted once the flows are harvested.
+    pub ring_buffer_dropped: u64,
+    /// Packets ignored by the capture filter on purpose.
+    pub filtered: u64,
+    /// Packets skipped by sampling on purpose, the counters of the sampled
+    /// ones are scaled up for them.
+    pub sampled_out: u64,
+    /// Packets that could not be parsed, their traffic is missing.
+    pub parse_failures: ParseFailures,
+}
+
+/// Packets the classifiers failed to parse, by [`net::parse::ParseError`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ParseFailures {
+    pub truncated: u64,
+    pub unknown_ether_type: u64,
+    pub ip_version_mismatch: u64,
+    pub extension_header_overflow: u64,
+}
+
+impl CaptureStats {
+    /// Flows announced late and packets lost.
+    pub fn missed(&self) -> u64 {
+        self.ring_buffer_dropped
+            + self.parse_failures.truncated
+            + self.parse_failures.unknown_ether_type
+            + self.parse_failures.ip_version_mismatch
+            + self.parse_failures.extension_header_overflow
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Traffic {
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -27,6 +115,96 @@ pub struct Packet {
     pub proto: IpProto,
     pub src_addr: IpAddr,
     pub dst_addr: IpAddr,
-    pub bytes: u16,
+    /// `None` for ICMP and ICMPv6 packets.
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    pub direction: Direction,
+    /// Country of the remote peer.
+    pub country_code: String,
+    /// More than one for sources aggregating flows, like the eBPF source.
+    pub packets: u64,
+    /// IP packet bytes including the IP header.
+    pub bytes: u64,
+    /// Ethernet frame bytes, what the peer counters are based on.
+    pub wire_bytes: u64,
     pub timestamp: SystemTime,
+    pub interface: Option<String>,
+    /// Set for ICMP and ICMPv6 packets.
+    pub icmp: Option<Icmp>,
+    /// The process owning the local socket, if it is known.
+    pub process: Option<Process>,
+    /// The container or service of the process, if it is known.
+    pub workload: Option<Workload>,
+    /// One in this many packets was counted by the capture, `packets` and
+    /// the byte counters are scaled up by it already.
+    pub capture_sample_rate: u32,
+    /// One in this many packet events is sent to subscribers, totals summed
+    /// up from the stream have to be scaled by it. Always 1 when batching.
+    pub sample_rate: u32,
+}
+
+/// The packet events between the local host and one peer within a batching
+/// interval. Counters are from the perspective of the local host, like
+/// [`Packet::direction`].
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketBatch {
+    pub local_addr: IpAddr,
+    pub peer_addr: IpAddr,
+    /// Country of the peer.
+    pub country_code: String,
+    /// Packet events coalesced into this batch.
+    pub events: u64,
+    pub packets: u64,
+    /// On-wire bytes.
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+    /// Packets per protocol name.
+    pub protocols: BTreeMap<String, u64>,
+    /// The lower port of every packet with ports, usually the service port.
+    pub ports: BTreeSet<u16>,
+    pub first_timestamp: SystemTime,
+    pub last_timestamp: SystemTime,
+    pub interfaces: BTreeMap<String, Traffic>,
+    pub workloads: BTreeMap<String, Traffic>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Process {
+    /// Process id, the thread id is `tid`.
+    pub pid: u32,
+    pub tid: u32,
+    pub uid: u32,
+    pub cgroup_id: u64,
+    pub comm: String,
+}
+
+impl From<&ProcessInfo> for Process {
+    fn from(info: &ProcessInfo) -> Self {
+        Self {
+            pid: info.tgid,
+            tid: info.pid,
+            uid: info.uid,
+            cgroup_id: info.cgroup_id,
+            comm: String::from_utf8_lossy(info.comm()).into_owned(),
+        }
+    }
+}
+
+/// Traffic of one process, see [`crate::ProcessTable`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessTraffic {
+    pub process: Process,
+    /// On-wire bytes from the perspective of the process, unlike [`Peer`].
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+    pub last_message: Option<SystemTime>,
+    /// Byte counters per country code of the remote peers.
+    pub countries: BTreeMap<String, Traffic>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Icmp {
+    #[serde(rename = "type")]
+    pub icmp_type: u8,
+    pub code: u8,
 }
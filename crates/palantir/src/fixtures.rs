@@ -0,0 +1,68 @@
+//! Peers and events shared by the unit tests, adjusted with struct update
+//! syntax where a test needs something else.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr},
+};
+
+use net::ip::IpProto;
+use palantir_ebpf_common::{Direction, RawEvent};
+
+use crate::{
+    event::Peer,
+    resolver::{IpInfo, LocationDetails},
+};
+
+pub const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+/// A manually placed location in `country_code` without an AS.
+pub fn info(country_code: &str) -> IpInfo {
+    IpInfo {
+        lat: 0.0,
+        lon: 0.0,
+        country_code: country_code.to_string(),
+        details: LocationDetails::Manual,
+        asn: None,
+    }
+}
+
+/// A peer in `country_code` that has not sent or received anything yet.
+pub fn peer(addr: IpAddr, country_code: &str) -> Peer {
+    Peer {
+        addr,
+        info: info(country_code),
+        ingress_bytes: 0,
+        egress_bytes: 0,
+        last_message: None,
+        interfaces: BTreeMap::new(),
+        workloads: BTreeMap::new(),
+    }
+}
+
+/// A single unsampled TCP packet from port 443 to port 50000 without a
+/// process or interface, `wire_bytes` including the Ethernet header.
+pub fn event(
+    src_addr: IpAddr,
+    dst_addr: IpAddr,
+    direction: Direction,
+    wire_bytes: u64,
+) -> RawEvent {
+    RawEvent {
+        process: None,
+        ifindex: 0,
+        src_addr,
+        dst_addr,
+        src_port: 443,
+        dst_port: 50000,
+        ts_offset_ns: 0,
+        proto: IpProto::TCP,
+        fragment: false,
+        last_fragment: false,
+        direction,
+        packets: 1,
+        bytes: wire_bytes.saturating_sub(14),
+        wire_bytes,
+        sample_rate: 1,
+    }
+}
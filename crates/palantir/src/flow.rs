@@ -0,0 +1,255 @@
+use std::collections::{HashMap, hash_map::Entry};
+
+use palantir_ebpf_common::{FlowKey, FlowStats, RawEvent};
+
+/// Remembers how much of every flow in the kernel's `FLOWS` map has already
+/// been reported, so that harvesting the map only yields the traffic since the
+/// previous harvest.
+///
+/// Traffic of a flow evicted from the LRU map between two harvests is lost;
+/// a flow that was evicted and inserted again is detected by its counters
+/// going backwards and reported from scratch.
+#[derive(Debug, Default)]
+pub struct FlowTable {
+    reported: HashMap<FlowKey, FlowStats>,
+}
+
+impl FlowTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accounts a new flow notification, returns `false` for flows that are
+    /// already known, because a harvest got to them first.
+    pub fn notify(&mut self, event: &RawEvent) -> bool {
+        match self.reported.entry(FlowKey::from(event)) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(FlowStats::from(event));
+                true
+            }
+        }
+    }
+
+    /// Diffs a snapshot of the map, with the per-CPU values already merged,
+    /// against what was reported so far. Flows missing from the snapshot are
+    /// forgotten.
+    pub fn harvest<I>(&mut self, snapshot: I) -> Vec<RawEvent>
+    where
+        I: IntoIterator<Item = (FlowKey, FlowStats)>,
+    {
+        let mut events = Vec::new();
+        let mut reported = HashMap::with_capacity(self.reported.len());
+
+        for (key, total) in snapshot {
+            let delta = match self.reported.get(&key) {
+                Some(previous)
+                    if total.packets >= previous.packets
+                        && total.bytes >= previous.bytes
+                        && total.wire_bytes >= previous.wire_bytes =>
+                {
+                    FlowStats {
+                        packets: total.packets - previous.packets,
+                        bytes: total.bytes - previous.bytes,
+                        wire_bytes: total.wire_bytes - previous.wire_bytes,
+                        first_seen_ns: previous.last_seen_ns,
+                        last_seen_ns: total.last_seen_ns,
+                        sample_rate: total.sample_rate,
+                        _pad: 0,
+                    }
+                }
+                _ => total,
+            };
+
+            if delta.packets > 0 {
+                events.push(key.event(&delta));
+            }
+
+            reported.insert(key, total);
+        }
+
+        self.reported = reported;
+
+        events
+    }
+
+    pub fn len(&self) -> usize {
+        self.reported.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reported.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use palantir_ebpf_common::{Direction, FlowKey, FlowStats, RawEvent};
+
+    use super::FlowTable;
+    use crate::fixtures;
+
+    fn event(src_addr: IpAddr, bytes: u64) -> RawEvent {
+        let dst_addr = match src_addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+        };
+
+        RawEvent {
+            ifindex: 2,
+            ts_offset_ns: 1_000,
+            ..fixtures::event(src_addr, dst_addr, Direction::Ingress, bytes + 14)
+        }
+    }
+
+    fn stats(packets: u64, bytes: u64, last_seen_ns: u64) -> FlowStats {
+        FlowStats {
+            packets,
+            bytes,
+            wire_bytes: bytes + packets * 14,
+            first_seen_ns: 1_000,
+            last_seen_ns,
+            ..FlowStats::default()
+        }
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for addr in [
+            IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+            IpAddr::V6(Ipv6Addr::new(0x2606, 0x4700, 0x4700, 0, 0, 0, 0, 0x1111)),
+        ] {
+            let event = event(addr, 60);
+            let key = FlowKey::from(&event);
+            let decoded = key.event(&stats(1, 60, 1_000));
+
+            assert_eq!(decoded.src_addr, event.src_addr);
+            assert_eq!(decoded.dst_addr, event.dst_addr);
+            assert_eq!(FlowKey::from(&decoded), key);
+        }
+    }
+
+    #[test]
+    fn notifies_each_flow_once() {
+        let mut flows = FlowTable::new();
+        let event = event(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 60);
+
+        assert!(flows.notify(&event));
+        assert!(!flows.notify(&event));
+        assert_eq!(flows.len(), 1);
+    }
+
+    #[test]
+    fn harvest_reports_traffic_since_notification() {
+        let mut flows = FlowTable::new();
+        let event = event(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 60);
+        let key = FlowKey::from(&event);
+
+        flows.notify(&event);
+
+        let events = flows.harvest([(key, stats(3, 1560, 3_000))]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].packets, 2);
+        assert_eq!(events[0].bytes, 1500);
+        assert_eq!(events[0].wire_bytes, 1528);
+        assert_eq!(events[0].ts_offset_ns, 3_000);
+
+        assert!(flows.harvest([(key, stats(3, 1560, 3_000))]).is_empty());
+    }
+
+    #[test]
+    fn harvest_reports_unnotified_flows_in_full() {
+        let mut flows = FlowTable::new();
+        let key = FlowKey::from(&event(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 60));
+
+        let events = flows.harvest([(key, stats(2, 120, 2_000))]);
+        assert_eq!(events[0].packets, 2);
+        assert_eq!(events[0].bytes, 120);
+
+        // the late notification must not be counted again
+        assert!(!flows.notify(&event(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 60)));
+    }
+
+    #[test]
+    fn harvest_forgets_evicted_flows() {
+        let mut flows = FlowTable::new();
+        let key = FlowKey::from(&event(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 60));
+
+        flows.harvest([(key, stats(10, 1000, 2_000))]);
+        flows.harvest([]);
+        assert!(flows.is_empty());
+
+        // evicted and inserted again between two harvests
+        flows.harvest([(key, stats(10, 1000, 2_000))]);
+        let events = flows.harvest([(key, stats(2, 200, 5_000))]);
+        assert_eq!(events[0].packets, 2);
+        assert_eq!(events[0].bytes, 200);
+    }
+
+    #[test]
+    fn merges_per_cpu_values() {
+        let merged = [
+            FlowStats::default(),
+            FlowStats {
+                packets: 1,
+                bytes: 60,
+                wire_bytes: 74,
+                first_seen_ns: 2_000,
+                last_seen_ns: 2_000,
+                ..FlowStats::default()
+            },
+            stats(2, 120, 3_000),
+        ]
+        .into_iter()
+        .fold(FlowStats::default(), FlowStats::merge);
+
+        assert_eq!(merged.packets, 3);
+        assert_eq!(merged.bytes, 180);
+        assert_eq!(merged.wire_bytes, 222);
+        assert_eq!(merged.first_seen_ns, 1_000);
+        assert_eq!(merged.last_seen_ns, 3_000);
+    }
+
+    #[test]
+    fn samples_connections_in_both_directions() {
+        let ingress = event(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 60);
+        let egress = RawEvent {
+            src_addr: ingress.dst_addr,
+            dst_addr: ingress.src_addr,
+            src_port: ingress.dst_port,
+            dst_port: ingress.src_port,
+            direction: Direction::Egress,
+            ..ingress
+        };
+        let other = RawEvent {
+            src_port: 80,
+            ..ingress
+        };
+
+        assert_eq!(ingress.flow_hash(), egress.flow_hash());
+        assert_ne!(ingress.flow_hash(), other.flow_hash());
+    }
+
+    #[test]
+    fn scales_sampled_traffic() {
+        let mut flows = FlowTable::new();
+        let unsampled = event(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 60);
+        let mut sampled = unsampled;
+        sampled.sample(100);
+        let key = FlowKey::from(&unsampled);
+
+        // counted before and after sampling was enabled
+        let mut stats = FlowStats::from(&unsampled);
+        stats.record(&sampled);
+        stats.record(&sampled);
+        assert_eq!(stats.sample_rate, 100);
+
+        let events = flows.harvest([(key, stats)]);
+        assert_eq!(events[0].packets, 201);
+        assert_eq!(events[0].bytes, 12_060);
+        assert_eq!(events[0].wire_bytes, 14_874);
+        assert_eq!(events[0].sample_rate, 100);
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{event::Peer, resolver::IpInfo, server::AppState};
+
+const SEGMENT_EXTENSION: &str = "jsonl";
+
+/// Persists peers and their traffic in an append-only log of JSON lines, one
+/// segment file per UTC day named after the day, e.g. `2025-01-31.jsonl`.
+///
+/// Every [`HistoryStore::checkpoint`] appends a `traffic` record for each
+/// peer with traffic since the previous checkpoint, followed by a `peer`
+/// record with its lifetime counters. The latest `peer` records restore the
+/// peer table on startup, the `traffic` records answer who was talked to
+/// when. Retention drops whole segments.
+pub struct HistoryStore {
+    dir: PathBuf,
+    retention: Option<Duration>,
+    segment: Option<(NaiveDate, BufWriter<File>)>,
+    reported: HashMap<IpAddr, (u64, u64)>,
+    checkpointed: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Peer(Peer),
+    Traffic(TrafficRecord),
+}
+
+/// Bytes exchanged with a peer between two checkpoints, in Unix seconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TrafficRecord {
+    start: u64,
+    end: u64,
+    addr: IpAddr,
+    ingress_bytes: u64,
+    egress_bytes: u64,
+}
+
+/// Traffic with one peer within a queried window, from the perspective of
+/// the peer like [`Peer`].
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerHistory {
+    pub addr: IpAddr,
+    /// Location of the peer, if a `peer` record was found in the window.
+    pub info: Option<IpInfo>,
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// A bound of a queried window that is out of range for the clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidWindow {
+    pub param: &'static str,
+    /// Unix seconds.
+    pub secs: u64,
+}
+
+impl fmt::Display for InvalidWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {}: {} is out of range", self.param, self.secs)
+    }
+}
+
+impl std::error::Error for InvalidWindow {}
+
+impl HistoryStore {
+    pub fn open(dir: impl Into<PathBuf>, retention: Option<Duration>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            retention,
+            segment: None,
+            reported: HashMap::new(),
+            checkpointed: None,
+        })
+    }
+
+    /// The latest state of every peer in the retained segments, least
+    /// recently active first. Later checkpoints only store what changed since.
+    pub fn load_peers(&mut self) -> io::Result<Vec<Peer>> {
+        let mut peers = HashMap::new();
+
+        for (_, path) in self.segments()? {
+            for record in read_segment(&path)? {
+                if let Record::Peer(peer) = record {
+                    peers.insert(peer.addr, peer);
+                }
+            }
+        }
+
+        self.reported = peers
+            .values()
+            .map(|peer| (peer.addr, (peer.ingress_bytes, peer.egress_bytes)))
+            .collect();
+
+        let mut peers: Vec<_> = peers.into_values().collect();
+        peers.sort_by_key(|peer| peer.last_message);
+
+        Ok(peers)
+    }
+
+    /// Appends the traffic of `peers` since the previous checkpoint. Peers
+    /// missing from `peers` are forgotten, counters going backwards mean the
+    /// peer was evicted and seen again. Returns the number of peers written.
+    pub fn checkpoint<'a, I>(&mut self, now: SystemTime, peers: I) -> io::Result<usize>
+    where
+        I: IntoIterator<Item = &'a Peer>,
+    {
+        let start = self.checkpointed.replace(now).unwrap_or(now);
+        let mut reported = HashMap::with_capacity(self.reported.len());
+        let mut lines = Vec::new();
+
+        for peer in peers {
+            let counters = (peer.ingress_bytes, peer.egress_bytes);
+            let (ingress_bytes, egress_bytes) = match self.reported.get(&peer.addr) {
+                Some(&(ingress, egress)) if counters.0 >= ingress && counters.1 >= egress => {
+                    (counters.0 - ingress, counters.1 - egress)
+                }
+                _ => counters,
+            };
+
+            reported.insert(peer.addr, counters);
+
+            if ingress_bytes == 0 && egress_bytes == 0 {
+                continue;
+            }
+
+            let traffic = Record::Traffic(TrafficRecord {
+                start: unix_secs(start),
+                end: unix_secs(now),
+                addr: peer.addr,
+                ingress_bytes,
+                egress_bytes,
+            });
+
+            lines.push(serde_json::to_string(&traffic).map_err(io::Error::other)?);
+            lines.push(
+                serde_json::to_string(&Record::Peer(peer.clone())).map_err(io::Error::other)?,
+            );
+        }
+
+        self.reported = reported;
+
+        let writer = self.writer(now)?;
+        for line in &lines {
+            writeln!(writer, "{line}")?;
+        }
+        writer.flush()?;
+
+        Ok(lines.len() / 2)
+    }
+
+    /// Traffic per peer between `from` and `to`, busiest peers first.
+    /// Checkpoints overlapping the window count in full.
+    pub fn query(&self, from: SystemTime, to: SystemTime) -> io::Result<Vec<PeerHistory>> {
+        let (from, to) = (unix_secs(from), unix_secs(to));
+        let mut history: HashMap<IpAddr, PeerHistory> = HashMap::new();
+        let mut infos = HashMap::new();
+
+        for (day, path) in self.segments()? {
+            if day < day_of(from) || day > day_of(to) {
+                continue;
+            }
+
+            for record in read_segment(&path)? {
+                let traffic = match record {
+                    Record::Traffic(traffic) if traffic.end > from && traffic.start < to => traffic,
+                    Record::Traffic(_) => continue,
+                    Record::Peer(peer) => {
+                        infos.insert(peer.addr, peer.info);
+                        continue;
+                    }
+                };
+
+                let start = SystemTime::UNIX_EPOCH + Duration::from_secs(traffic.start);
+                let end = SystemTime::UNIX_EPOCH + Duration::from_secs(traffic.end);
+                let entry = history.entry(traffic.addr).or_insert(PeerHistory {
+                    addr: traffic.addr,
+                    info: None,
+                    ingress_bytes: 0,
+                    egress_bytes: 0,
+                    first_seen: start,
+                    last_seen: end,
+                });
+
+                entry.ingress_bytes += traffic.ingress_bytes;
+                entry.egress_bytes += traffic.egress_bytes;
+                entry.first_seen = entry.first_seen.min(start);
+                entry.last_seen = entry.last_seen.max(end);
+            }
+        }
+
+        let mut history: Vec<_> = history
+            .into_values()
+            .map(|mut entry| {
+                entry.info = infos.remove(&entry.addr);
+                entry
+            })
+            .collect();
+        history.sort_by_key(|entry| std::cmp::Reverse(entry.ingress_bytes + entry.egress_bytes));
+
+        Ok(history)
+    }
+
+    /// Deletes the segments of days entirely older than the retention.
+    pub fn prune(&self, now: SystemTime) -> io::Result<()> {
+        let Some(retention) = self.retention else {
+            return Ok(());
+        };
+        let cutoff = day_of(unix_secs(now).saturating_sub(retention.as_secs()));
+
+        for (day, path) in self.segments()? {
+            if day < cutoff {
+                info!("removing history segment {}", path.display());
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The segment of the day of `now`, rolling over to a new file and
+    /// pruning old ones when the day changed.
+    fn writer(&mut self, now: SystemTime) -> io::Result<&mut BufWriter<File>> {
+        let day = day_of(unix_secs(now));
+
+        if self
+            .segment
+            .as_ref()
+            .is_none_or(|(current, _)| *current != day)
+        {
+            let path = self.dir.join(format!("{day}.{SEGMENT_EXTENSION}"));
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            self.segment = Some((day, BufWriter::new(file)));
+            self.prune(now)?;
+        }
+
+        Ok(&mut self.segment.as_mut().unwrap().1)
+    }
+
+    /// Segment files ordered by day.
+    fn segments(&self) -> io::Result<Vec<(NaiveDate, PathBuf)>> {
+        let mut segments: Vec<_> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != SEGMENT_EXTENSION {
+                    return None;
+                }
+                let day = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((day, path))
+            })
+            .collect();
+        segments.sort();
+
+        Ok(segments)
+    }
+}
+
+/// Reads the records of a segment, skipping lines that fail to parse such as
+/// a last line cut short by a crash.
+fn read_segment(path: &Path) -> io::Result<Vec<Record>> {
+    let mut records = Vec::new();
+
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        match serde_json::from_str(&line?) {
+            Ok(record) => records.push(record),
+            Err(err) => warn!("skipping {}:{}: {}", path.display(), number + 1, err),
+        }
+    }
+
+    Ok(records)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn day_of(unix_secs: u64) -> NaiveDate {
+    DateTime::from_timestamp(unix_secs as i64, 0)
+        .unwrap_or_default()
+        .date_naive()
+}
+
+/// Runs [`HistoryStore::query`] on the blocking thread pool, reading the
+/// segments would otherwise stall the runtime. Checkpoints wait for it.
+pub async fn query_history(
+    history: Arc<Mutex<HistoryStore>>,
+    from: SystemTime,
+    to: SystemTime,
+) -> io::Result<Vec<PeerHistory>> {
+    let history = history.lock_owned().await;
+
+    tokio::task::spawn_blocking(move || history.query(from, to))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Checkpoints the peer table of `state` into its history store every
+/// `interval`, the resolution of the stored traffic.
+pub async fn persist_history(state: Arc<AppState>, interval: Duration) {
+    let Some(history) = state.history.clone() else {
+        return;
+    };
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        let peers = state.peers.lock().await.clone();
+        if let Err(err) = history
+            .lock()
+            .await
+            .checkpoint(SystemTime::now(), peers.peers())
+        {
+            warn!("failed to write history: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{IpAddr, Ipv4Addr},
+        path::PathBuf,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use tokio::sync::Mutex;
+
+    use super::{HistoryStore, query_history};
+    use crate::{event::Peer, fixtures};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("palantir-{}-{}", name, std::process::id()));
+        _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn peer(last: u8, ingress_bytes: u64, egress_bytes: u64, last_message: SystemTime) -> Peer {
+        Peer {
+            ingress_bytes,
+            egress_bytes,
+            last_message: Some(last_message),
+            ..fixtures::peer(IpAddr::V4(Ipv4Addr::new(1, 1, 1, last)), "AU")
+        }
+    }
+
+    #[test]
+    fn reloads_latest_peers() {
+        let dir = dir("history-reload");
+        let mut store = HistoryStore::open(&dir, None).unwrap();
+
+        let start = at(1_700_000_000);
+        store
+            .checkpoint(start, &[peer(1, 10, 20, start), peer(2, 1, 1, start)])
+            .unwrap();
+        let later = start + DAY;
+        store.checkpoint(later, &[peer(1, 15, 20, later)]).unwrap();
+
+        let peers = HistoryStore::open(&dir, None)
+            .unwrap()
+            .load_peers()
+            .unwrap();
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].addr, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 2)));
+        assert_eq!(peers[1].ingress_bytes, 15);
+        assert_eq!(peers[1].last_message, Some(later));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn queries_traffic_within_window() {
+        let dir = dir("history-query");
+        let mut store = HistoryStore::open(&dir, None).unwrap();
+
+        let start = at(1_700_000_000);
+        store.checkpoint(start, &[peer(1, 10, 20, start)]).unwrap();
+        store
+            .checkpoint(start + DAY, &[peer(1, 15, 20, start + DAY)])
+            .unwrap();
+        // nothing new, nothing written
+        assert_eq!(
+            store
+                .checkpoint(start + DAY * 2, &[peer(1, 15, 20, start + DAY)])
+                .unwrap(),
+            0
+        );
+
+        let history = store.query(start - DAY, start + DAY * 3).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].ingress_bytes, 15);
+        assert_eq!(history[0].egress_bytes, 20);
+        assert_eq!(history[0].info.as_ref().unwrap().country_code, "AU");
+
+        // only the second checkpoint, which covers the day after `start`
+        let history = store.query(start + DAY / 2, start + DAY * 3).unwrap();
+        assert_eq!(history[0].ingress_bytes, 5);
+        assert_eq!(history[0].egress_bytes, 0);
+        assert_eq!(history[0].first_seen, start);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn queries_shared_stores() {
+        let dir = dir("history-shared");
+        let mut store = HistoryStore::open(&dir, None).unwrap();
+
+        let start = at(1_700_000_000);
+        store.checkpoint(start, &[peer(1, 10, 20, start)]).unwrap();
+
+        let store = Arc::new(Mutex::new(store));
+        let history = query_history(store.clone(), start - DAY, start + DAY)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].ingress_bytes, 10);
+
+        // the store is free again for the next checkpoint
+        let later = start + DAY;
+        let written = store
+            .lock()
+            .await
+            .checkpoint(later, &[peer(1, 15, 20, later)])
+            .unwrap();
+        assert_eq!(written, 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prunes_segments_past_retention() {
+        let dir = dir("history-prune");
+        let mut store = HistoryStore::open(&dir, Some(DAY * 2)).unwrap();
+
+        let start = at(1_700_000_000);
+        for day in 0..5 {
+            let now = start + DAY * day;
+            store
+                .checkpoint(now, &[peer(1, 10 * (day as u64 + 1), 0, now)])
+                .unwrap();
+        }
+
+        assert_eq!(store.segments().unwrap().len(), 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
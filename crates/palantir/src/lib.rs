@@ -0,0 +1,62 @@
+//! Userspace side of palantir: turns `RawEvent`s from an [`EventSource`] into
+//! peer updates and serves them over HTTP.
+//!
+//! The monitor can be embedded into an existing axum application by running
+//! a [`Pipeline`] and mounting [`router`]:
+//!
+//! ```no_run
+//! # async fn example(local_peer: palantir::Peer, app: axum::Router) {
+//! use std::sync::Arc;
+//!
+//! use palantir::{AppState, Pipeline, Resolver, source::SyntheticSource};
+//!
+//! let state = Arc::new(AppState::new(local_peer.clone()));
+//!
+//! let reader = maxminddb::Reader::open_readfile("GeoLite2-City.mmdb").unwrap();
+//! let pipeline = Pipeline::new(Resolver::new(reader), state.peers.clone(), state.tx.clone())
+//!     .with_metrics(state.metrics.clone())
+//!     .with_processes(state.processes.clone())
+//!     .with_rollups(state.rollups.clone());
+//! tokio::spawn(pipeline.run(SyntheticSource::new(local_peer.addr, 100.0)));
+//!
+//! let app = app.nest("/palantir", palantir::router(state));
+//! # }
+//! ```
+//!
+//! [`EventSource`]: source::EventSource
+
+#![feature(ip)]
+
+pub mod batch;
+pub mod capture;
+pub mod cidr;
+pub mod config;
+pub mod event;
+pub mod flow;
+pub mod history;
+pub mod lru;
+pub mod metrics;
+pub mod pipeline;
+pub mod process;
+pub mod query;
+pub mod resolver;
+pub mod rollup;
+pub mod server;
+pub mod source;
+pub mod subscription;
+pub mod workload;
+pub mod ws;
+
+#[cfg(test)]
+mod fixtures;
+
+pub use self::{
+    config::Config,
+    event::{Event, Packet, Peer},
+    pipeline::{PeerTable, Pipeline},
+    process::ProcessTable,
+    resolver::{Asn, IpInfo, LocationDetails, LocationProvider, Resolver},
+    rollup::Rollups,
+    server::{AppState, router},
+    workload::{Workload, WorkloadResolver},
+};
@@ -0,0 +1,133 @@
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+/// A map that remembers the order its entries were last used in, so the
+/// least recently used ones can be evicted.
+#[derive(Debug, Clone)]
+pub struct LruMap<K, V> {
+    entries: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+    tick: u64,
+}
+
+impl<K, V> Default for LruMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+}
+
+impl<K, V> LruMap<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Looks up `key` without marking it as used.
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Looks up `key` and marks it as the most recently used entry.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let (value, tick) = self.entries.get_mut(key)?;
+
+        self.tick += 1;
+        self.order.remove(tick);
+        self.order.insert(self.tick, key.clone());
+        *tick = self.tick;
+
+        Some(value)
+    }
+
+    /// Inserts `value` as the most recently used entry, replacing the
+    /// previous value of `key`.
+    pub fn insert(&mut self, key: K, value: V) -> &mut V {
+        self.remove(&key);
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+
+        &mut self
+            .entries
+            .entry(key)
+            .insert_entry((value, self.tick))
+            .into_mut()
+            .0
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, tick) = self.entries.remove(key)?;
+        self.order.remove(&tick);
+        Some(value)
+    }
+
+    /// Removes the least recently used entry.
+    pub fn pop_lru(&mut self) -> Option<(K, V)> {
+        let (_, key) = self.order.pop_first()?;
+        let (value, _) = self.entries.remove(&key)?;
+        Some((key, value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|(value, _)| value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, (value, _))| (key, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LruMap;
+
+    #[test]
+    fn pops_least_recently_used() {
+        let mut map = LruMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+        map.insert("c", 3);
+
+        *map.get_mut(&"a").unwrap() += 10;
+        map.peek(&"b");
+
+        assert_eq!(map.pop_lru(), Some(("b", 2)));
+        assert_eq!(map.pop_lru(), Some(("c", 3)));
+        assert_eq!(map.pop_lru(), Some(("a", 11)));
+        assert_eq!(map.pop_lru(), None);
+    }
+
+    #[test]
+    fn insert_replaces_and_refreshes() {
+        let mut map = LruMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+        map.insert("a", 3);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.pop_lru(), Some(("b", 2)));
+        assert_eq!(map.remove(&"a"), Some(3));
+        assert!(map.is_empty());
+    }
+}
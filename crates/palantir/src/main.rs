@@ -1,296 +1,280 @@
-#![feature(ip)]
-
-mod event;
-mod resolver;
-
 use std::{
-    collections::{HashMap, hash_map::Entry},
-    convert::Infallible,
     env,
-    mem::zeroed,
-    net::IpAddr,
-    os::fd::AsRawFd,
+    fs::File,
+    io::{BufReader, BufWriter},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    process::ExitCode,
     sync::Arc,
-    time::{Duration, SystemTime},
+    time::Duration,
 };
 
-use async_stream::stream;
-use axum::{
-    Router,
-    extract::State,
-    response::{Sse, sse},
-    routing::get,
-};
-use aya::{
-    Ebpf,
-    maps::RingBuf,
-    programs::{SchedClassifier, TcAttachType},
-};
-use futures_util::Stream;
-use libc::{CLOCK_BOOTTIME, CLOCK_REALTIME, clock_gettime, timespec};
-use palantir_ebpf_common::{self, Direction, RawEvent};
-use tokio::{
-    io::{Interest, unix::AsyncFd},
-    net::TcpListener,
-    sync::{Mutex, broadcast},
+use clap::{Parser, Subcommand};
+use palantir::{
+    AppState, Config, Pipeline, ProcessTable, Resolver, Rollups,
+    capture::CaptureReader,
+    history::persist_history,
+    pipeline::{expire_peers, report_capture},
+    source::{EbpfSource, PcapSource, Recorder, ReplaySource, SyntheticSource, resolve_interfaces},
 };
+use tokio::net::TcpListener;
 use tower_http::{
     cors::{self, CorsLayer},
     trace::TraceLayer,
 };
-use tracing::{trace, warn};
+use tracing::{error, info};
 use tracing_subscriber::EnvFilter;
 
-use crate::{
-    event::{Event, Packet, Peer},
-    resolver::{IpInfo, Resolver},
-};
+/// Watches network traffic with eBPF and serves it to the palantir globe.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// TOML configuration file, values given as flags or environment variables take precedence.
+    #[arg(short, long, env = "PALANTIR_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address the HTTP server listens on.
+    #[arg(long, env = "LISTEN_ADDR")]
+    listen: Option<SocketAddr>,
+
+    /// Interfaces the eBPF programs are attached to, glob patterns like `bond*` are expanded.
+    #[arg(short, long, env = "IFACE", value_delimiter = ',')]
+    iface: Vec<String>,
+
+    /// Public address of this host.
+    #[arg(long, env = "SERVER_ADDR")]
+    server_addr: Option<IpAddr>,
+
+    /// Latitude of this host.
+    #[arg(long, env = "SERVER_LAT", allow_negative_numbers = true)]
+    server_lat: Option<f64>,
+
+    /// Longitude of this host.
+    #[arg(long, env = "SERVER_LON", allow_negative_numbers = true)]
+    server_lon: Option<f64>,
+
+    /// ISO 3166-1 alpha-2 country code of this host.
+    #[arg(long, env = "SERVER_COUNTRY_CODE")]
+    server_country_code: Option<String>,
+
+    /// GeoLite2/GeoIP2 City database used instead of the embedded one.
+    #[arg(long, env = "CITY_DB")]
+    city_db: Option<PathBuf>,
+
+    /// GeoLite2/GeoIP2 ASN database, peers are looked up by autonomous system if given.
+    #[arg(long, env = "ASN_DB")]
+    asn_db: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Capture live traffic (the default).
+    Live {
+        /// Record every captured event to this file.
+        #[arg(long, env = "CAPTURE_FILE")]
+        capture_file: Option<PathBuf>,
+    },
+    /// Replay a file recorded with `--capture-file`.
+    Replay {
+        path: PathBuf,
+        /// Playback speed relative to real time.
+        #[arg(long, default_value_t = 1.0, value_parser = positive)]
+        speed: f64,
+    },
+    /// Replay an Ethernet pcap file, packets from the server address count as egress.
+    Pcap {
+        path: PathBuf,
+        /// Playback speed relative to real time.
+        #[arg(long, default_value_t = 1.0, value_parser = positive)]
+        speed: f64,
+    },
+    /// Generate synthetic traffic.
+    Synthetic {
+        /// Events generated per second.
+        #[arg(long, default_value_t = 100.0, value_parser = positive)]
+        rate: f64,
+    },
+}
+
+/// Speeds and rates are divided by, they must be finite and above zero.
+fn positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        Ok(_) => Err(format!("{s} is not a finite number greater than 0")),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+impl Cli {
+    fn config(&self) -> Result<Config, palantir::config::ConfigError> {
+        let mut config = match &self.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+
+        if let Some(listen) = self.listen {
+            config.listen = listen;
+        }
+        if !self.iface.is_empty() {
+            config.interfaces = self.iface.clone();
+        }
+        if let Some(addr) = self.server_addr {
+            config.server.addr = Some(addr);
+        }
+        if let Some(lat) = self.server_lat {
+            config.server.lat = Some(lat);
+        }
+        if let Some(lon) = self.server_lon {
+            config.server.lon = Some(lon);
+        }
+        if let Some(country_code) = &self.server_country_code {
+            config.server.country_code = Some(country_code.clone());
+        }
+        if let Some(city_db) = &self.city_db {
+            config.geoip.city = Some(city_db.clone());
+        }
+        if let Some(asn_db) = &self.asn_db {
+            config.geoip.asn = Some(asn_db.clone());
+        }
+
+        config.validate()?;
 
-#[derive(Clone)]
-struct AppState {
-    tx: broadcast::Sender<Event>,
-    peers: Arc<Mutex<HashMap<IpAddr, Peer>>>,
+        Ok(config)
+    }
 }
 
 #[tokio::main]
-async fn main() {
-    let server_peer = Peer {
-        addr: env::var("SERVER_ADDR")
-            .expect("SERVER_ADDR is not defined")
-            .parse()
-            .expect("SERVER_ADDR is not a valid IpAddr"),
-        info: IpInfo {
-            lat: env::var("SERVER_LAT")
-                .expect("SERVER_LAT is not defined")
-                .parse()
-                .expect("SERVER_LAT is not a valid f64"),
-            lon: env::var("SERVER_LON")
-                .expect("SERVER_LON is not defined")
-                .parse()
-                .expect("SERVER_LON is not a valid f64"),
-            country_code: env::var("SERVER_COUNTRY_CODE")
-                .expect("SERVER_COUNTRY_CODE is not defined"),
-            details: resolver::LocationDetails::Manual,
-        },
-        ingress_bytes: 0,
-        egress_bytes: 0,
-        last_message: None,
-    };
-
-    let iface = env::var("IFACE").expect("IFACE is not defined");
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
 
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let boot_time = unsafe {
-        let mut ts: timespec = zeroed();
-        clock_gettime(CLOCK_BOOTTIME, &mut ts);
-        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
-    };
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let real_time = unsafe {
-        let mut ts: timespec = zeroed();
-        clock_gettime(CLOCK_REALTIME, &mut ts);
-        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
-    };
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let config = cli.config()?;
 
-    let boot_time = SystemTime::UNIX_EPOCH + (real_time - boot_time);
+    let server_peer = config.local_peer()?;
+    let local_addr = server_peer.addr;
 
-    let (tx, _) = broadcast::channel(64);
+    let mut state = AppState::new(server_peer)
+        .with_channel_capacity(config.events.channel_capacity)
+        .with_top_peers(config.metrics.top_peers)
+        .with_filter(config.filter.clone())
+        .with_processes(ProcessTable::new(config.retention.max_processes))
+        .with_rollups(Rollups::new(config.retention.max_series));
 
-    let state = Arc::new(AppState {
-        tx: tx.clone(),
-        peers: Arc::new(Mutex::new(HashMap::from_iter([(
-            server_peer.addr,
-            server_peer,
-        )]))),
-    });
+    if let Some(mut history) = config.history.store()? {
+        let restored = history.load_peers()?;
+        info!("restored {} peers from history", restored.len());
 
-    tokio::spawn({
-        let tx = tx.clone();
-        let state = state.clone();
-
-        let city_reader =
-            maxminddb::Reader::from_source(include_bytes!("../../../assets/GeoLite2-City.mmdb"))
-                .expect("failed to initialize reader");
-        let resolver = Resolver::new(city_reader);
-
-        async move {
-            let mut cache = HashMap::<IpAddr, IpInfo>::new();
-            let mut ebpf = init_ebpf(&iface);
-
-            let mut events = RingBuf::try_from(ebpf.map_mut("EVENTS").unwrap()).unwrap();
-
-            let poll = AsyncFd::new(events.as_raw_fd()).unwrap();
-            loop {
-                let mut guard = poll.readable().await.unwrap();
-                while let Some(item) = events.next() {
-                    let raw_event = unsafe { *(item.as_ptr() as *const RawEvent) };
-                    let peer_addr = raw_event.peer_addr();
-                    let local_addr = raw_event.local_addr();
-
-                    if peer_addr.is_multicast() || !peer_addr.is_global() {
-                        continue;
-                    }
-
-                    trace!("{:?}", raw_event);
-
-                    let peer_info = match cache.entry(peer_addr) {
-                        Entry::Occupied(entry) => entry.get().clone(),
-                        Entry::Vacant(entry) => match resolver.resolve(peer_addr) {
-                            Some(info) => entry.insert(info).clone(),
-                            None => {
-                                warn!("failed to get ip info for {}, skipping", peer_addr);
-                                continue;
-                            }
-                        },
-                    };
-
-                    {
-                        let mut peers = state.peers.lock().await;
-
-                        let mut is_new = false;
-
-                        let peer = peers.entry(peer_addr).or_insert_with(|| {
-                            is_new = true;
-                            Peer {
-                                addr: peer_addr,
-                                ingress_bytes: 0,
-                                egress_bytes: 0,
-                                last_message: None,
-                                info: peer_info,
-                            }
-                        });
-
-                        let bytes = raw_event.bytes as u64;
-                        match raw_event.direction {
-                            Direction::Ingress => peer.egress_bytes += bytes,
-                            Direction::Egress => peer.ingress_bytes += bytes,
-                        }
-
-                        peer.last_message = Some(raw_event.timestamp(boot_time));
-
-                        if is_new {
-                            let _ = tx.send(Event::Peer(peer.clone()));
-                        }
-                    }
-
-                    {
-                        let mut peers = state.peers.lock().await;
-
-                        match peers.entry(local_addr) {
-                            Entry::Occupied(mut entry) => {
-                                let peer = entry.get_mut();
-
-                                let bytes = raw_event.bytes as u64;
-                                match raw_event.direction {
-                                    Direction::Ingress => peer.ingress_bytes += bytes,
-                                    Direction::Egress => peer.egress_bytes += bytes,
-                                }
-
-                                peer.last_message = Some(raw_event.timestamp(boot_time));
-                            }
-                            Entry::Vacant(_) => {}
-                        }
-                    }
-
-                    let packet = Packet {
-                        src_addr: raw_event.src_addr,
-                        dst_addr: raw_event.dst_addr,
-                        proto: raw_event.proto,
-                        bytes: raw_event.bytes,
-                        timestamp: raw_event.timestamp(boot_time),
-                    };
-
-                    _ = tx.send(Event::Packet(packet));
-                }
-                guard.clear_ready();
-            }
+        let mut peers = state.peers.lock().await;
+        for peer in restored {
+            peers.restore(peer);
         }
-    });
+        peers.evict_lru(config.retention.max_peers);
+        drop(peers);
 
-    let listener = TcpListener::bind("0.0.0.0:3000")
-        .await
-        .expect("couldnt bind to 0.0.0.0:3000");
+        state = state.with_history(history);
+    }
 
-    let router = Router::new()
-        .route("/events", get(events))
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::new().allow_origin(cors::Any))
-        .with_state(state);
+    let state = Arc::new(state);
 
-    axum::serve(listener, router).await.unwrap();
-}
+    let city_reader = maxminddb::Reader::from_source(config.city_db()?)?;
+    let mut resolver = Resolver::new(city_reader);
+    if let Some(asn_db) = config.asn_db()? {
+        resolver = resolver.with_asn(maxminddb::Reader::from_source(asn_db)?);
+    }
 
-fn init_ebpf(iface: &str) -> Ebpf {
-    let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
-        env!("OUT_DIR"),
-        "/palantir"
-    )))
-    .expect("failed to load ebpf program");
+    let mut pipeline = Pipeline::new(resolver, state.peers.clone(), state.tx.clone())
+        .with_filter(state.filter.subscribe())
+        .with_events(config.events.clone())
+        .with_retention(config.retention.clone())
+        .with_metrics(state.metrics.clone())
+        .with_processes(state.processes.clone())
+        .with_rollups(state.rollups.clone());
 
-    match aya_log::EbpfLogger::init(&mut ebpf) {
-        Err(e) => {
-            warn!("failed to initialize eBPF logger: {}", e);
-        }
-        Ok(logger) => {
-            let mut logger = AsyncFd::with_interest(logger, Interest::READABLE).unwrap();
-            tokio::spawn(async move {
-                loop {
-                    let mut guard = logger.readable_mut().await.unwrap();
-                    guard.get_inner_mut().flush();
-                    guard.clear_ready();
-                }
-            });
-        }
+    if let Some(workloads) = config.workloads.resolver()? {
+        pipeline = pipeline.with_workloads(workloads);
     }
 
-    {
-        let probe: &mut SchedClassifier = ebpf
-            .program_mut("tc_ingress")
-            .expect("failed to get program tc_ingress")
-            .try_into()
-            .unwrap();
-        _ = probe.load().inspect_err(|err| warn!("{}", err));
-        _ = probe
-            .attach(iface, TcAttachType::Ingress)
-            .inspect_err(|err| warn!("{}", err));
+    if let Some(ttl) = config.retention.peer_ttl() {
+        tokio::spawn(expire_peers(state.clone(), ttl));
     }
 
-    {
-        let probe: &mut SchedClassifier = ebpf
-            .program_mut("tc_egress")
-            .expect("failed to get program tc_egress")
-            .try_into()
-            .unwrap();
-        _ = probe.load().inspect_err(|err| warn!("{}", err));
-        _ = probe
-            .attach(iface, TcAttachType::Egress)
-            .inspect_err(|err| warn!("{}", err));
-    }
+    tokio::spawn(persist_history(state.clone(), config.history.interval()));
 
-    ebpf
-}
+    // clap only reads the environment for a subcommand given on the command line
+    let command = cli.command.unwrap_or_else(|| Command::Live {
+        capture_file: env::var_os("CAPTURE_FILE").map(PathBuf::from),
+    });
 
-async fn events(
-    State(state): State<Arc<AppState>>,
-) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
-    let mut rx = state.tx.subscribe();
-    let peers: Vec<_> = {
-        let peers = state.peers.lock().await;
-        peers.values().cloned().collect()
-    };
-
-    let stream = stream! {
-        for peer in peers {
-            yield Ok(sse::Event::default().data(serde_json::to_string(&Event::Peer(peer)).unwrap()))
-        }
+    match command {
+        Command::Live { capture_file } => {
+            if config.interfaces.is_empty() {
+                return Err("interfaces is not defined".into());
+            }
 
-        while let Ok(event) = rx.recv().await {
-            yield Ok(sse::Event::default().data(serde_json::to_string(&event).unwrap()))
+            let interfaces = resolve_interfaces(&config.interfaces)?;
+            let source = EbpfSource::new(interfaces, config.capture.ingress, &config.sampling)
+                .with_metrics(state.metrics.clone())
+                .with_filter(state.filter.subscribe());
+            tokio::spawn(report_capture(state.clone(), Duration::from_secs(1)));
+
+            match capture_file {
+                Some(path) => {
+                    let file = File::create(&path)?;
+                    let source = Recorder::new(source, BufWriter::new(file))?;
+                    info!("recording events to {}", path.display());
+                    tokio::spawn(pipeline.run(source));
+                }
+                None => {
+                    tokio::spawn(pipeline.run(source));
+                }
+            }
+        }
+        Command::Replay { path, speed } => {
+            let reader = CaptureReader::new(BufReader::new(File::open(&path)?))?;
+            info!(
+                "replaying {} recorded at {:?} with speed {}",
+                path.display(),
+                reader.boot_time(),
+                speed
+            );
+            tokio::spawn(pipeline.run(ReplaySource::new(reader, speed)));
         }
-    };
+        Command::Pcap { path, speed } => {
+            let file = File::open(&path)?;
+            let source = PcapSource::new(BufReader::new(file), vec![local_addr], speed)?;
+            info!("replaying {} with speed {}", path.display(), speed);
+            tokio::spawn(pipeline.run(source));
+        }
+        Command::Synthetic { rate } => {
+            info!("generating {} events per second", rate);
+            tokio::spawn(pipeline.run(SyntheticSource::new(local_addr, rate)));
+        }
+    }
+
+    let listener = TcpListener::bind(config.listen).await?;
+    info!("listening on {}", config.listen);
+
+    let router = palantir::router(state)
+        .layer(TraceLayer::new_for_http())
+        .layer(CorsLayer::new().allow_origin(cors::Any));
+
+    axum::serve(listener, router).await?;
 
-    Sse::new(stream)
+    Ok(())
 }
@@ -0,0 +1,390 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use net::ip::IpProto;
+use palantir_ebpf_common::Direction;
+use serde::Serialize;
+
+use crate::event::CaptureStats;
+
+/// Upper bounds of the latency buckets in seconds, from a microsecond to
+/// a tenth of a second.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.1,
+];
+
+/// Counters shared between the pipeline, its background tasks and the HTTP
+/// handlers. They only ever increase.
+#[derive(Debug, Default, Serialize)]
+pub struct Metrics {
+    /// Peers removed after being idle for longer than the TTL.
+    pub peers_expired: AtomicU64,
+    /// Peers removed to keep the table within `max_peers`.
+    pub peers_evicted: AtomicU64,
+    /// Addresses removed from the geo cache to keep it within its size.
+    pub geo_cache_evicted: AtomicU64,
+    /// Addresses whose location was found in the geo cache.
+    pub geo_cache_hits: AtomicU64,
+    /// Addresses looked up in the GeoIP databases.
+    pub geo_cache_misses: AtomicU64,
+    /// Addresses unknown to the GeoIP databases, their events are skipped.
+    pub geo_lookup_failures: AtomicU64,
+    /// Times a subscriber fell behind by more than the channel capacity.
+    pub subscribers_lagged: AtomicU64,
+    /// Events skipped by lagging subscribers.
+    pub events_dropped: AtomicU64,
+    /// Counters of the eBPF programs, as last read from the kernel.
+    pub capture: Mutex<CaptureStats>,
+    /// Traffic per country of the remote peer, protocol and direction.
+    #[serde(skip)]
+    pub traffic: TrafficCounters,
+    /// Time the pipeline takes to handle one event.
+    #[serde(skip)]
+    pub handle_latency: Histogram,
+}
+
+impl Metrics {
+    /// Writes every metric to `out`, prefixed with `palantir_`.
+    pub fn encode(&self, out: &mut Exposition) {
+        for (name, help, counter) in [
+            (
+                "peers_expired",
+                "Peers removed after being idle",
+                &self.peers_expired,
+            ),
+            (
+                "peers_evicted",
+                "Peers removed to bound the peer table",
+                &self.peers_evicted,
+            ),
+            (
+                "geo_cache_evicted",
+                "Addresses removed from the geo cache",
+                &self.geo_cache_evicted,
+            ),
+            (
+                "geo_cache_hits",
+                "Addresses found in the geo cache",
+                &self.geo_cache_hits,
+            ),
+            (
+                "geo_cache_misses",
+                "Addresses looked up in the GeoIP databases",
+                &self.geo_cache_misses,
+            ),
+            (
+                "geo_lookup_failures",
+                "Addresses unknown to the GeoIP databases",
+                &self.geo_lookup_failures,
+            ),
+            (
+                "subscribers_lagged",
+                "Times a subscriber fell behind the event channel",
+                &self.subscribers_lagged,
+            ),
+            (
+                "events_dropped",
+                "Events skipped by lagging subscribers",
+                &self.events_dropped,
+            ),
+        ] {
+            let name = format!("palantir_{name}_total");
+            out.family(&name, "counter", help);
+            out.sample(&name, &[], counter.load(Ordering::Relaxed));
+        }
+
+        let capture = *self.capture.lock().unwrap();
+
+        out.family(
+            "palantir_ebpf_events_emitted_total",
+            "counter",
+            "New flows announced on the eBPF ring buffer",
+        );
+        out.sample(
+            "palantir_ebpf_events_emitted_total",
+            &[],
+            capture.events_emitted,
+        );
+
+        out.family(
+            "palantir_ring_buffer_dropped_total",
+            "counter",
+            "New flows not announced because the eBPF ring buffer was full",
+        );
+        out.sample(
+            "palantir_ring_buffer_dropped_total",
+            &[],
+            capture.ring_buffer_dropped,
+        );
+
+        out.family(
+            "palantir_filtered_packets_total",
+            "counter",
+            "Packets ignored by the capture filter of the eBPF classifiers",
+        );
+        out.sample("palantir_filtered_packets_total", &[], capture.filtered);
+
+        out.family(
+            "palantir_sampled_out_packets_total",
+            "counter",
+            "Packets skipped by sampling in the eBPF classifiers",
+        );
+        out.sample(
+            "palantir_sampled_out_packets_total",
+            &[],
+            capture.sampled_out,
+        );
+
+        let failures = capture.parse_failures;
+        out.family(
+            "palantir_parse_failures_total",
+            "counter",
+            "Packets the eBPF classifiers failed to parse",
+        );
+        for (reason, value) in [
+            ("truncated", failures.truncated),
+            ("unknown_ether_type", failures.unknown_ether_type),
+            ("ip_version_mismatch", failures.ip_version_mismatch),
+            (
+                "extension_header_overflow",
+                failures.extension_header_overflow,
+            ),
+        ] {
+            out.sample(
+                "palantir_parse_failures_total",
+                &[("reason", reason)],
+                value,
+            );
+        }
+
+        let traffic = self.traffic.counters.lock().unwrap();
+
+        let families: [(&str, &str, TrafficValue); 2] = [
+            (
+                "palantir_bytes_total",
+                "On-wire bytes by remote country, protocol and direction of the local host",
+                |counter| counter.bytes,
+            ),
+            (
+                "palantir_packets_total",
+                "Packets by remote country, protocol and direction of the local host",
+                |counter| counter.packets,
+            ),
+        ];
+
+        for (name, help, value) in families {
+            out.family(name, "counter", help);
+
+            for ((country_code, proto, direction), counter) in traffic.iter() {
+                let proto = proto.to_string();
+                let labels = [
+                    ("country", country_code.as_str()),
+                    ("proto", proto.as_str()),
+                    ("direction", direction_label(*direction)),
+                ];
+                out.sample(name, &labels, value(counter));
+            }
+        }
+        drop(traffic);
+
+        out.histogram(
+            "palantir_pipeline_handle_seconds",
+            "Time the pipeline takes to handle one event",
+            &self.handle_latency,
+        );
+    }
+}
+
+/// Label value of a direction, like its JSON representation.
+pub fn direction_label(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Ingress => "ingress",
+        Direction::Egress => "egress",
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Traffic {
+    packets: u64,
+    bytes: u64,
+}
+
+type TrafficValue = fn(&Traffic) -> u64;
+
+/// Packet and byte counters keyed by country, protocol and direction. There
+/// are only a few hundred countries and protocols, so the number of series
+/// stays bounded without evicting any.
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+    counters: Mutex<BTreeMap<(String, IpProto, Direction), Traffic>>,
+}
+
+impl TrafficCounters {
+    pub fn record(
+        &self,
+        country_code: &str,
+        proto: IpProto,
+        direction: Direction,
+        packets: u64,
+        bytes: u64,
+    ) {
+        let mut counters = self.counters.lock().unwrap();
+        let counter = counters
+            .entry((country_code.to_string(), proto, direction))
+            .or_default();
+
+        counter.packets += packets;
+        counter.bytes += bytes;
+    }
+}
+
+/// Durations counted into the fixed [`LATENCY_BUCKETS`].
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// Observations per bucket, not cumulative; larger ones are only in
+    /// `count`.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_ns: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&bound| secs <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ns
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Metrics in the Prometheus text exposition format, served by `/metrics`.
+#[derive(Debug, Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a metric family, `kind` is `counter`, `gauge` or `histogram`.
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        _ = writeln!(self.text, "# HELP {name} {help}");
+        _ = writeln!(self.text, "# TYPE {name} {kind}");
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.text.push_str(name);
+
+        if !labels.is_empty() {
+            self.text.push('{');
+            for (i, (label, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.text.push(',');
+                }
+                _ = write!(self.text, "{label}=\"");
+                for c in value.chars() {
+                    match c {
+                        '\\' => self.text.push_str("\\\\"),
+                        '"' => self.text.push_str("\\\""),
+                        '\n' => self.text.push_str("\\n"),
+                        c => self.text.push(c),
+                    }
+                }
+                self.text.push('"');
+            }
+            self.text.push('}');
+        }
+
+        _ = writeln!(self.text, " {value}");
+    }
+
+    pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) {
+        self.family(name, "histogram", help);
+
+        let bucket = format!("{name}_bucket");
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            self.sample(&bucket, &[("le", &bound.to_string())], cumulative);
+        }
+
+        let count = histogram.count.load(Ordering::Relaxed);
+        let sum = Duration::from_nanos(histogram.sum_ns.load(Ordering::Relaxed));
+        self.sample(&bucket, &[("le", "+Inf")], count);
+        self.sample(&format!("{name}_sum"), &[], sum.as_secs_f64());
+        self.sample(&format!("{name}_count"), &[], count);
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use net::ip::IpProto;
+    use palantir_ebpf_common::Direction;
+
+    use super::{Exposition, Metrics};
+    use crate::event::{CaptureStats, ParseFailures};
+
+    #[test]
+    fn encodes_text_format() {
+        let metrics = Metrics::default();
+        metrics
+            .traffic
+            .record("AT", IpProto::TCP, Direction::Ingress, 2, 3000);
+        metrics
+            .traffic
+            .record("AT", IpProto::TCP, Direction::Ingress, 1, 100);
+        metrics.handle_latency.observe(Duration::from_micros(3));
+        metrics.handle_latency.observe(Duration::from_secs(1));
+        *metrics.capture.lock().unwrap() = CaptureStats {
+            events_emitted: 10,
+            ring_buffer_dropped: 1,
+            filtered: 0,
+            sampled_out: 90,
+            parse_failures: ParseFailures {
+                truncated: 2,
+                ..ParseFailures::default()
+            },
+        };
+
+        let mut out = Exposition::new();
+        out.sample("palantir_peer", &[("peer", "a\"b")], 1);
+        metrics.encode(&mut out);
+        let text = out.finish();
+
+        assert!(text.contains("palantir_peer{peer=\"a\\\"b\"} 1\n"));
+        assert!(text.contains("palantir_ring_buffer_dropped_total 1\n"));
+        assert!(text.contains("palantir_sampled_out_packets_total 90\n"));
+        assert!(text.contains("palantir_parse_failures_total{reason=\"truncated\"} 2\n"));
+        assert!(text.contains("# TYPE palantir_bytes_total counter\n"));
+        assert!(text.contains(
+            "palantir_bytes_total{country=\"AT\",proto=\"TCP\",direction=\"ingress\"} 3100\n"
+        ));
+        assert!(text.contains(
+            "palantir_packets_total{country=\"AT\",proto=\"TCP\",direction=\"ingress\"} 3\n"
+        ));
+        assert!(text.contains("palantir_pipeline_handle_seconds_bucket{le=\"0.000001\"} 0\n"));
+        assert!(text.contains("palantir_pipeline_handle_seconds_bucket{le=\"0.000005\"} 1\n"));
+        assert!(text.contains("palantir_pipeline_handle_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("palantir_pipeline_handle_seconds_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("palantir_pipeline_handle_seconds_count 2\n"));
+    }
+}
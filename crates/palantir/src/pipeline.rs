@@ -0,0 +1,1065 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    iter,
+    net::IpAddr,
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+
+use palantir_ebpf_common::{Direction, RawEvent};
+use tokio::sync::{Mutex, broadcast, watch};
+use tracing::{trace, warn};
+
+use crate::{
+    batch::{PacketBatcher, flush_batches},
+    config::{EventsConfig, FilterConfig, RetentionConfig},
+    event::{Event, Icmp, Packet, Peer, PeerRemoved, Process, RemovalReason},
+    lru::LruMap,
+    metrics::Metrics,
+    process::ProcessTable,
+    resolver::{IpInfo, LocationProvider},
+    rollup::Rollups,
+    server::AppState,
+    source::EventSource,
+    workload::WorkloadResolver,
+};
+
+/// Byte counters of every peer seen so far, including the local peer.
+///
+/// Counters are kept from the perspective of the peer: bytes the local host
+/// receives from a peer count as that peer's egress. They count on-wire bytes
+/// like the interface statistics of `ip -s link`.
+///
+/// Remote peers are kept in least recently active order, so the table can be
+/// bounded with [`PeerTable::evict_lru`].
+#[derive(Debug, Clone)]
+pub struct PeerTable {
+    local: Peer,
+    peers: LruMap<IpAddr, Peer>,
+}
+
+impl PeerTable {
+    pub fn new(local_peer: Peer) -> Self {
+        Self {
+            local: local_peer,
+            peers: LruMap::new(),
+        }
+    }
+
+    pub fn get(&self, addr: &IpAddr) -> Option<&Peer> {
+        match *addr == self.local.addr {
+            true => Some(&self.local),
+            false => self.peers.peek(addr),
+        }
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &Peer> {
+        iter::once(&self.local).chain(self.remote_peers())
+    }
+
+    pub fn local_peer(&self) -> &Peer {
+        &self.local
+    }
+
+    pub fn remote_peers(&self) -> impl Iterator<Item = &Peer> {
+        self.peers.values()
+    }
+
+    /// Number of peers including the local peer.
+    pub fn len(&self) -> usize {
+        self.peers.len() + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Accounts `raw_event` to its remote peer, creating it with `info` if it
+    /// has not been seen before, and to the local peer if it is the local
+    /// address of the event. Returns the remote peer if it was newly created.
+    pub fn record(
+        &mut self,
+        raw_event: &RawEvent,
+        interface: Option<&str>,
+        workload: Option<&str>,
+        info: IpInfo,
+        timestamp: SystemTime,
+    ) -> Option<&Peer> {
+        let peer_addr = raw_event.peer_addr();
+        let local_addr = raw_event.local_addr();
+        let bytes = raw_event.wire_bytes;
+
+        let (ingress_bytes, egress_bytes) = match raw_event.direction {
+            Direction::Ingress => (bytes, 0),
+            Direction::Egress => (0, bytes),
+        };
+
+        if local_addr == self.local.addr {
+            let local = &mut self.local;
+            account(
+                local,
+                interface,
+                workload,
+                ingress_bytes,
+                egress_bytes,
+                timestamp,
+            );
+        }
+
+        if peer_addr == self.local.addr {
+            let local = &mut self.local;
+            account(
+                local,
+                interface,
+                workload,
+                egress_bytes,
+                ingress_bytes,
+                timestamp,
+            );
+            return None;
+        }
+
+        let is_new = self.peers.peek(&peer_addr).is_none();
+        if is_new {
+            self.peers.insert(
+                peer_addr,
+                Peer {
+                    addr: peer_addr,
+                    info,
+                    ingress_bytes: 0,
+                    egress_bytes: 0,
+                    last_message: None,
+                    interfaces: BTreeMap::new(),
+                    workloads: BTreeMap::new(),
+                },
+            );
+        }
+
+        let peer = self.peers.get_mut(&peer_addr)?;
+        account(
+            peer,
+            interface,
+            workload,
+            egress_bytes,
+            ingress_bytes,
+            timestamp,
+        );
+
+        is_new.then_some(peer)
+    }
+
+    /// Puts back a peer loaded from the history. The local peer only takes
+    /// the counters, its location is configured.
+    pub fn restore(&mut self, peer: Peer) {
+        if peer.addr != self.local.addr {
+            self.peers.insert(peer.addr, peer);
+            return;
+        }
+
+        self.local.ingress_bytes = peer.ingress_bytes;
+        self.local.egress_bytes = peer.egress_bytes;
+        self.local.last_message = peer.last_message;
+        self.local.interfaces = peer.interfaces;
+        self.local.workloads = peer.workloads;
+    }
+
+    /// Removes every peer whose last message is older than `ttl` at `now`.
+    /// The local peer is never removed.
+    pub fn evict_idle(&mut self, now: SystemTime, ttl: Duration) -> Vec<Peer> {
+        let expired: Vec<_> = self
+            .peers
+            .values()
+            .filter(|peer| {
+                peer.last_message
+                    .and_then(|last| now.duration_since(last).ok())
+                    .is_some_and(|idle| idle > ttl)
+            })
+            .map(|peer| peer.addr)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|addr| self.peers.remove(&addr))
+            .collect()
+    }
+
+    /// Removes the least recently active peers until at most `max_peers`
+    /// remote peers are left.
+    pub fn evict_lru(&mut self, max_peers: usize) -> Vec<Peer> {
+        let mut evicted = Vec::new();
+
+        while self.peers.len() > max_peers {
+            match self.peers.pop_lru() {
+                Some((_, peer)) => evicted.push(peer),
+                None => break,
+            }
+        }
+
+        evicted
+    }
+}
+
+fn account(
+    peer: &mut Peer,
+    interface: Option<&str>,
+    workload: Option<&str>,
+    ingress_bytes: u64,
+    egress_bytes: u64,
+    timestamp: SystemTime,
+) {
+    peer.ingress_bytes += ingress_bytes;
+    peer.egress_bytes += egress_bytes;
+    peer.last_message = Some(timestamp);
+
+    if let Some(interface) = interface {
+        let traffic = peer.interfaces.entry(interface.to_string()).or_default();
+        traffic.ingress_bytes += ingress_bytes;
+        traffic.egress_bytes += egress_bytes;
+    }
+
+    if let Some(workload) = workload {
+        let traffic = peer.workloads.entry(workload.to_string()).or_default();
+        traffic.ingress_bytes += ingress_bytes;
+        traffic.egress_bytes += egress_bytes;
+    }
+}
+
+/// Periodically evicts peers idle for longer than `ttl` from the peer table
+/// of `state` and announces their removal.
+pub async fn expire_peers(state: Arc<AppState>, ttl: Duration) {
+    let mut interval = tokio::time::interval(ttl.min(Duration::from_secs(60)));
+
+    loop {
+        interval.tick().await;
+
+        let evicted = state.peers.lock().await.evict_idle(SystemTime::now(), ttl);
+        if !evicted.is_empty() {
+            trace!("evicted {} idle peers", evicted.len());
+        }
+
+        state
+            .metrics
+            .peers_expired
+            .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+
+        for peer in evicted {
+            _ = state.tx.send(Event::PeerRemoved(PeerRemoved {
+                addr: peer.addr,
+                reason: RemovalReason::Idle,
+            }));
+        }
+    }
+}
+
+/// Periodically announces the capture counters of `state` if the eBPF
+/// programs missed packets since the previous announcement.
+pub async fn report_capture(state: Arc<AppState>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    let mut missed = 0;
+
+    loop {
+        interval.tick().await;
+
+        let capture = *state.metrics.capture.lock().unwrap();
+        if capture.missed() > missed {
+            missed = capture.missed();
+            warn!("the eBPF programs missed {} packets or flows", missed);
+            _ = state.tx.send(Event::Capture(capture));
+        }
+    }
+}
+
+/// Turns `RawEvent`s into peer updates and `Event`s broadcast to subscribers.
+pub struct Pipeline<L>
+where
+    L: LocationProvider,
+{
+    locations: L,
+    cache: LruMap<IpAddr, IpInfo>,
+    retention: RetentionConfig,
+    metrics: Arc<Metrics>,
+    interfaces: HashMap<u32, String>,
+    filter: watch::Receiver<FilterConfig>,
+    peers: Arc<Mutex<PeerTable>>,
+    processes: Option<Arc<Mutex<ProcessTable>>>,
+    rollups: Option<Arc<Mutex<Rollups>>>,
+    workloads: Option<WorkloadResolver>,
+    events: EventsConfig,
+    batcher: Option<Arc<Mutex<PacketBatcher>>>,
+    /// Packet events seen so far, for sampling.
+    packet_events: u64,
+    tx: broadcast::Sender<Event>,
+}
+
+impl<L> Pipeline<L>
+where
+    L: LocationProvider,
+{
+    /// Locates peers with `locations`, usually a [`Resolver`](crate::Resolver).
+    pub fn new(locations: L, peers: Arc<Mutex<PeerTable>>, tx: broadcast::Sender<Event>) -> Self {
+        Self {
+            locations,
+            cache: LruMap::new(),
+            retention: RetentionConfig::default(),
+            metrics: Arc::default(),
+            interfaces: HashMap::new(),
+            filter: watch::channel(FilterConfig::default()).1,
+            peers,
+            processes: None,
+            rollups: None,
+            workloads: None,
+            events: EventsConfig::default(),
+            batcher: None,
+            packet_events: 0,
+            tx,
+        }
+    }
+
+    /// Drops events excluded by the current `filter` before they reach the
+    /// peer table, see [`AppState::with_filter`].
+    pub fn with_filter(mut self, filter: watch::Receiver<FilterConfig>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Bounds the peer table and the geo cache, the idle TTL is enforced by
+    /// [`expire_peers`].
+    pub fn with_retention(mut self, retention: RetentionConfig) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Counts evictions in `metrics` instead of a private instance.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Also accounts events with a known process to `processes`.
+    pub fn with_processes(mut self, processes: Arc<Mutex<ProcessTable>>) -> Self {
+        self.processes = Some(processes);
+        self
+    }
+
+    /// Also accounts events to the time series of `rollups`.
+    pub fn with_rollups(mut self, rollups: Arc<Mutex<Rollups>>) -> Self {
+        self.rollups = Some(rollups);
+        self
+    }
+
+    /// Resolves the cgroups of events with a known process to workloads.
+    pub fn with_workloads(mut self, workloads: WorkloadResolver) -> Self {
+        self.workloads = Some(workloads);
+        self
+    }
+
+    /// Batches or samples packet events as configured by `events`.
+    pub fn with_events(mut self, events: EventsConfig) -> Self {
+        self.batcher = events
+            .batch_interval()
+            .map(|_| Arc::new(Mutex::new(PacketBatcher::new())));
+        self.events = events;
+        self
+    }
+
+    /// Consumes events from `source` until it is exhausted or fails.
+    pub async fn run<S>(mut self, mut source: S)
+    where
+        S: EventSource,
+    {
+        self.interfaces = source
+            .interfaces()
+            .into_iter()
+            .map(|interface| (interface.index, interface.name))
+            .collect();
+
+        let flush =
+            self.batcher
+                .clone()
+                .zip(self.events.batch_interval())
+                .map(|(batcher, interval)| {
+                    tokio::spawn(flush_batches(batcher, self.tx.clone(), interval))
+                });
+
+        loop {
+            match source.next().await {
+                Ok(Some(raw_event)) => {
+                    let start = Instant::now();
+                    self.handle(source.boot_time(), raw_event).await;
+                    self.metrics.handle_latency.observe(start.elapsed());
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    warn!("failed to read event, stopping pipeline: {}", err);
+                    break;
+                }
+            }
+        }
+
+        if let Some(flush) = flush {
+            flush.abort();
+        }
+        if let Some(batcher) = &self.batcher {
+            for batch in batcher.lock().await.drain() {
+                _ = self.tx.send(Event::PacketBatch(batch));
+            }
+        }
+    }
+
+    pub async fn handle(&mut self, boot_time: SystemTime, raw_event: RawEvent) {
+        let peer_addr = raw_event.peer_addr();
+
+        if peer_addr.is_multicast()
+            || !peer_addr.is_global()
+            || self.filter.borrow().excludes(&raw_event)
+        {
+            return;
+        }
+
+        trace!("{:?}", raw_event);
+
+        let cached = self.cache.get_mut(&peer_addr).cloned();
+        let counter = match cached {
+            Some(_) => &self.metrics.geo_cache_hits,
+            None => &self.metrics.geo_cache_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        let peer_info = match cached {
+            Some(info) => info,
+            None => match self.locations.lookup(peer_addr) {
+                Some(info) => {
+                    self.cache.insert(peer_addr, info.clone());
+
+                    if self.cache.len() > self.retention.geo_cache_size {
+                        self.cache.pop_lru();
+                        self.metrics
+                            .geo_cache_evicted
+                            .fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    info
+                }
+                None => {
+                    self.metrics
+                        .geo_lookup_failures
+                        .fetch_add(1, Ordering::Relaxed);
+                    warn!("failed to get ip info for {}, skipping", peer_addr);
+                    return;
+                }
+            },
+        };
+
+        let timestamp = raw_event.timestamp(boot_time);
+        let interface = self.interfaces.get(&raw_event.ifindex);
+        let workload = match (&mut self.workloads, &raw_event.process) {
+            (Some(workloads), Some(process)) => workloads.resolve(process.cgroup_id).cloned(),
+            _ => None,
+        };
+        let workload_key = workload.as_ref().map(ToString::to_string);
+        let country_code = peer_info.country_code.clone();
+
+        self.metrics.traffic.record(
+            &peer_info.country_code,
+            raw_event.proto,
+            raw_event.direction,
+            raw_event.packets,
+            raw_event.wire_bytes,
+        );
+
+        if let Some(processes) = &self.processes {
+            processes
+                .lock()
+                .await
+                .record(&raw_event, &peer_info.country_code, timestamp);
+        }
+
+        if let Some(rollups) = &self.rollups {
+            rollups
+                .lock()
+                .await
+                .record(&raw_event, &peer_info.country_code, timestamp);
+        }
+
+        {
+            let mut peers = self.peers.lock().await;
+
+            if let Some(peer) = peers.record(
+                &raw_event,
+                interface.map(String::as_str),
+                workload_key.as_deref(),
+                peer_info,
+                timestamp,
+            ) {
+                _ = self.tx.send(Event::Peer(peer.clone()));
+
+                let evicted = peers.evict_lru(self.retention.max_peers);
+                self.metrics
+                    .peers_evicted
+                    .fetch_add(evicted.len() as u64, Ordering::Relaxed);
+
+                for peer in evicted {
+                    _ = self.tx.send(Event::PeerRemoved(PeerRemoved {
+                        addr: peer.addr,
+                        reason: RemovalReason::Evicted,
+                    }));
+                }
+            }
+        }
+
+        let packet = Packet {
+            src_addr: raw_event.src_addr,
+            dst_addr: raw_event.dst_addr,
+            src_port: raw_event.ports().map(|(src_port, _)| src_port),
+            dst_port: raw_event.ports().map(|(_, dst_port)| dst_port),
+            direction: raw_event.direction,
+            country_code,
+            proto: raw_event.proto,
+            packets: raw_event.packets,
+            bytes: raw_event.bytes,
+            wire_bytes: raw_event.wire_bytes,
+            timestamp,
+            interface: interface.cloned(),
+            icmp: raw_event
+                .icmp()
+                .map(|(icmp_type, code)| Icmp { icmp_type, code }),
+            process: raw_event.process.as_ref().map(Process::from),
+            workload,
+            capture_sample_rate: raw_event.sample_rate,
+            sample_rate: 1,
+        };
+
+        if let Some(batcher) = &self.batcher {
+            batcher.lock().await.add(&packet);
+            return;
+        }
+
+        let packet = Packet {
+            sample_rate: self.events.sample_rate,
+            ..packet
+        };
+
+        let sampled = self
+            .packet_events
+            .is_multiple_of(self.events.sample_rate as u64);
+        self.packet_events += 1;
+
+        if sampled {
+            _ = self.tx.send(Event::Packet(packet));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        fs, io, iter,
+        net::{IpAddr, Ipv4Addr},
+        os::unix::fs::MetadataExt,
+        sync::{Arc, atomic::Ordering},
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use palantir_ebpf_common::{Direction, ProcessInfo, RawEvent};
+    use tokio::sync::{Mutex, broadcast};
+
+    use super::{PeerTable, Pipeline, expire_peers};
+    use crate::{
+        config::{EventsConfig, RetentionConfig},
+        event::{Event, PeerRemoved, RemovalReason},
+        fixtures::{self, LOCAL},
+        metrics::Metrics,
+        process::ProcessTable,
+        resolver::{IpInfo, LocationProvider},
+        server::AppState,
+        source::{EventSource, Interface},
+        workload::{FileProvider, WorkloadResolver},
+    };
+
+    const REMOTE: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8));
+    const UNKNOWN: IpAddr = IpAddr::V4(Ipv4Addr::new(9, 9, 9, 9));
+
+    /// Places `REMOTE` in Australia and `OTHER` in the US, nothing else.
+    struct Locations;
+
+    impl LocationProvider for Locations {
+        fn lookup(&self, addr: IpAddr) -> Option<IpInfo> {
+            match addr {
+                REMOTE => Some(fixtures::info("AU")),
+                OTHER => Some(fixtures::info("US")),
+                _ => None,
+            }
+        }
+    }
+
+    /// Yields the events it was built with, captured on `eth0`.
+    struct Events(VecDeque<RawEvent>);
+
+    impl EventSource for Events {
+        fn boot_time(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH
+        }
+
+        fn interfaces(&self) -> Vec<Interface> {
+            vec![Interface {
+                index: 2,
+                name: "eth0".to_string(),
+            }]
+        }
+
+        async fn next(&mut self) -> io::Result<Option<RawEvent>> {
+            Ok(self.0.pop_front())
+        }
+    }
+
+    /// A pipeline over an empty peer table, counting into `metrics`.
+    fn pipeline(
+        metrics: &Arc<Metrics>,
+    ) -> (
+        Pipeline<Locations>,
+        Arc<Mutex<PeerTable>>,
+        broadcast::Receiver<Event>,
+    ) {
+        let peers = Arc::new(Mutex::new(table()));
+        let (tx, rx) = broadcast::channel(64);
+        let pipeline = Pipeline::new(Locations, peers.clone(), tx).with_metrics(metrics.clone());
+
+        (pipeline, peers, rx)
+    }
+
+    /// Runs `pipeline` over `events` and returns everything it broadcast.
+    async fn run(
+        pipeline: Pipeline<Locations>,
+        rx: &mut broadcast::Receiver<Event>,
+        events: impl IntoIterator<Item = RawEvent>,
+    ) -> Vec<Event> {
+        pipeline.run(Events(events.into_iter().collect())).await;
+        iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    /// An ingress event from `src_addr`.
+    fn from(src_addr: IpAddr, wire_bytes: u64) -> RawEvent {
+        fixtures::event(src_addr, LOCAL, Direction::Ingress, wire_bytes)
+    }
+
+    fn process(tgid: u32, comm: &str, cgroup_id: u64) -> ProcessInfo {
+        let mut info = ProcessInfo {
+            pid: tgid,
+            tgid,
+            uid: 1000,
+            cgroup_id,
+            ..Default::default()
+        };
+        info.comm[..comm.len()].copy_from_slice(comm.as_bytes());
+        info
+    }
+
+    fn info() -> IpInfo {
+        fixtures::info("AU")
+    }
+
+    fn table() -> PeerTable {
+        PeerTable::new(fixtures::peer(LOCAL, "AU"))
+    }
+
+    fn event(direction: Direction, wire_bytes: u64) -> RawEvent {
+        let (src_addr, dst_addr) = match direction {
+            Direction::Ingress => (REMOTE, LOCAL),
+            Direction::Egress => (LOCAL, REMOTE),
+        };
+
+        fixtures::event(src_addr, dst_addr, direction, wire_bytes)
+    }
+
+    #[test]
+    fn first_event_creates_peer() {
+        let mut peers = table();
+        let now = SystemTime::now();
+
+        let peer = peers.record(&event(Direction::Ingress, 100), None, None, info(), now);
+        assert_eq!(peer.map(|peer| peer.addr), Some(REMOTE));
+
+        let peer = peers.record(&event(Direction::Ingress, 100), None, None, info(), now);
+        assert!(peer.is_none());
+
+        assert_eq!(peers.len(), 2);
+    }
+
+    #[test]
+    fn accounts_bytes_from_both_perspectives() {
+        let mut peers = table();
+        let now = SystemTime::now();
+
+        peers.record(&event(Direction::Ingress, 100), None, None, info(), now);
+        peers.record(&event(Direction::Egress, 40), None, None, info(), now);
+        peers.record(&event(Direction::Egress, 2), None, None, info(), now);
+
+        let remote = peers.get(&REMOTE).unwrap();
+        assert_eq!(remote.egress_bytes, 100);
+        assert_eq!(remote.ingress_bytes, 42);
+
+        let local = peers.get(&LOCAL).unwrap();
+        assert_eq!(local.ingress_bytes, 100);
+        assert_eq!(local.egress_bytes, 42);
+    }
+
+    #[test]
+    fn accounts_bytes_per_interface() {
+        let mut peers = table();
+        let now = SystemTime::now();
+
+        peers.record(
+            &event(Direction::Ingress, 100),
+            Some("eth0"),
+            None,
+            info(),
+            now,
+        );
+        peers.record(
+            &event(Direction::Egress, 40),
+            Some("wg0"),
+            None,
+            info(),
+            now,
+        );
+        peers.record(&event(Direction::Egress, 2), None, None, info(), now);
+
+        let remote = peers.get(&REMOTE).unwrap();
+        assert_eq!(remote.egress_bytes, 100);
+        assert_eq!(remote.ingress_bytes, 42);
+        assert_eq!(remote.interfaces["eth0"].egress_bytes, 100);
+        assert_eq!(remote.interfaces["wg0"].ingress_bytes, 40);
+        assert_eq!(remote.interfaces.len(), 2);
+
+        let local = peers.get(&LOCAL).unwrap();
+        assert_eq!(local.interfaces["eth0"].ingress_bytes, 100);
+        assert_eq!(local.interfaces["wg0"].egress_bytes, 40);
+    }
+
+    #[test]
+    fn accounts_bytes_per_workload() {
+        let mut peers = table();
+        let now = SystemTime::now();
+
+        peers.record(
+            &event(Direction::Ingress, 100),
+            None,
+            Some("shop/checkout"),
+            info(),
+            now,
+        );
+        peers.record(
+            &event(Direction::Egress, 40),
+            None,
+            Some("nginx"),
+            info(),
+            now,
+        );
+        peers.record(&event(Direction::Egress, 2), None, None, info(), now);
+
+        let remote = peers.get(&REMOTE).unwrap();
+        assert_eq!(remote.workloads["shop/checkout"].egress_bytes, 100);
+        assert_eq!(remote.workloads["nginx"].ingress_bytes, 40);
+        assert_eq!(remote.workloads.len(), 2);
+
+        let local = peers.get(&LOCAL).unwrap();
+        assert_eq!(local.workloads["shop/checkout"].ingress_bytes, 100);
+        assert_eq!(local.workloads["nginx"].egress_bytes, 40);
+    }
+
+    #[test]
+    fn evicts_idle_peers_but_not_local() {
+        let mut peers = table();
+        let last = SystemTime::UNIX_EPOCH + Duration::from_secs(10);
+
+        peers.record(&event(Direction::Ingress, 1), None, None, info(), last);
+
+        let ttl = Duration::from_secs(5);
+        assert!(peers.evict_idle(last + ttl, ttl).is_empty());
+
+        let evicted = peers.evict_idle(last + ttl * 2, ttl);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].addr, REMOTE);
+        assert!(peers.get(&LOCAL).is_some());
+    }
+
+    #[test]
+    fn evicts_least_recently_active_peers() {
+        let mut peers = table();
+        let now = SystemTime::now();
+
+        for last in 1..=3 {
+            let mut event = event(Direction::Ingress, 1);
+            event.src_addr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, last));
+            peers.record(&event, None, None, info(), now);
+        }
+
+        // 1.1.1.1 is active again, 1.1.1.2 is now the least recently active
+        peers.record(&event(Direction::Ingress, 1), None, None, info(), now);
+
+        let evicted = peers.evict_lru(2);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].addr, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 2)));
+        assert_eq!(peers.len(), 3);
+        assert!(peers.get(&LOCAL).is_some());
+    }
+
+    #[test]
+    fn tracks_last_message() {
+        let mut peers = table();
+        let first = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let second = SystemTime::UNIX_EPOCH + Duration::from_secs(2);
+
+        peers.record(&event(Direction::Ingress, 1), None, None, info(), first);
+        peers.record(&event(Direction::Egress, 1), None, None, info(), second);
+
+        assert_eq!(peers.get(&REMOTE).unwrap().last_message, Some(second));
+        assert_eq!(peers.get(&LOCAL).unwrap().last_message, Some(second));
+    }
+
+    #[tokio::test]
+    async fn announces_peers_and_packets() {
+        let metrics = Arc::default();
+        let processes = Arc::new(Mutex::new(ProcessTable::new(16)));
+        let (pipeline, peers, mut rx) = pipeline(&metrics);
+        let pipeline = pipeline.with_processes(processes.clone());
+
+        let curl = RawEvent {
+            ifindex: 2,
+            process: Some(process(42, "curl", 0)),
+            ..event(Direction::Egress, 40)
+        };
+        let events = run(
+            pipeline,
+            &mut rx,
+            [from(REMOTE, 100), curl, from(OTHER, 60)],
+        )
+        .await;
+
+        let [
+            Event::Peer(remote),
+            Event::Packet(first),
+            Event::Packet(second),
+            Event::Peer(other),
+            Event::Packet(third),
+        ] = &events[..]
+        else {
+            panic!("unexpected events {events:?}");
+        };
+        assert_eq!(remote.addr, REMOTE);
+        assert_eq!(other.addr, OTHER);
+        assert_eq!(first.country_code, "AU");
+        assert_eq!(first.interface, None);
+        assert_eq!(second.interface.as_deref(), Some("eth0"));
+        assert_eq!(
+            second.process.as_ref().map(|p| p.comm.as_str()),
+            Some("curl")
+        );
+        assert_eq!(third.country_code, "US");
+
+        let peers = peers.lock().await;
+        let remote = peers.get(&REMOTE).unwrap();
+        assert_eq!(remote.egress_bytes, 100);
+        assert_eq!(remote.ingress_bytes, 40);
+        assert_eq!(remote.interfaces["eth0"].ingress_bytes, 40);
+        assert_eq!(peers.get(&LOCAL).unwrap().ingress_bytes, 160);
+        assert_eq!(peers.len(), 3);
+
+        let processes = processes.lock().await;
+        let curl = processes.processes().next().unwrap();
+        assert_eq!(processes.len(), 1);
+        assert_eq!(curl.process.pid, 42);
+        assert_eq!(curl.egress_bytes, 40);
+        assert_eq!(curl.countries["AU"].egress_bytes, 40);
+    }
+
+    #[tokio::test]
+    async fn skips_local_and_unlocated_peers() {
+        let metrics = Arc::default();
+        let (pipeline, peers, mut rx) = pipeline(&metrics);
+
+        let private = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let events = run(pipeline, &mut rx, [from(private, 100), from(UNKNOWN, 100)]).await;
+
+        assert!(events.is_empty(), "unexpected events {events:?}");
+        assert_eq!(peers.lock().await.len(), 1);
+        assert_eq!(metrics.geo_cache_misses.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.geo_lookup_failures.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn caches_locations() {
+        let metrics = Arc::default();
+        let (pipeline, _, mut rx) = pipeline(&metrics);
+        let pipeline = pipeline.with_retention(RetentionConfig {
+            geo_cache_size: 1,
+            ..Default::default()
+        });
+
+        // OTHER evicts REMOTE, which evicts OTHER in turn
+        let events = [REMOTE, REMOTE, OTHER, REMOTE].map(|addr| from(addr, 100));
+        run(pipeline, &mut rx, events).await;
+
+        assert_eq!(metrics.geo_cache_hits.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.geo_cache_misses.load(Ordering::Relaxed), 3);
+        assert_eq!(metrics.geo_cache_evicted.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn announces_evicted_peers() {
+        let metrics = Arc::default();
+        let (pipeline, peers, mut rx) = pipeline(&metrics);
+        let pipeline = pipeline.with_retention(RetentionConfig {
+            max_peers: 1,
+            ..Default::default()
+        });
+
+        let events = run(pipeline, &mut rx, [from(REMOTE, 100), from(OTHER, 100)]).await;
+
+        let [
+            Event::Peer(_),
+            Event::Packet(_),
+            Event::Peer(other),
+            Event::PeerRemoved(PeerRemoved { addr, reason }),
+            Event::Packet(_),
+        ] = &events[..]
+        else {
+            panic!("unexpected events {events:?}");
+        };
+        assert_eq!(other.addr, OTHER);
+        assert_eq!((*addr, *reason), (REMOTE, RemovalReason::Evicted));
+
+        let peers = peers.lock().await;
+        assert!(peers.get(&REMOTE).is_none());
+        assert!(peers.get(&OTHER).is_some());
+        assert_eq!(metrics.peers_evicted.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn announces_expired_peers() {
+        let state = Arc::new(AppState::new(fixtures::peer(LOCAL, "AU")));
+        let mut rx = state.tx.subscribe();
+
+        let last = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        state
+            .peers
+            .lock()
+            .await
+            .record(&from(REMOTE, 100), None, None, info(), last);
+
+        let expire = tokio::spawn(expire_peers(state.clone(), Duration::from_secs(1)));
+        let event = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        expire.abort();
+
+        let Ok(Ok(Event::PeerRemoved(PeerRemoved { addr, reason }))) = event else {
+            panic!("unexpected event {event:?}");
+        };
+        assert_eq!((addr, reason), (REMOTE, RemovalReason::Idle));
+        assert!(state.peers.lock().await.get(&REMOTE).is_none());
+        assert_eq!(state.metrics.peers_expired.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn attributes_workloads() {
+        let root = std::env::temp_dir().join(format!("palantir-pipeline-{}", std::process::id()));
+        let service = root.join("system.slice/nginx.service");
+        fs::create_dir_all(&service).unwrap();
+        let cgroup_id = fs::metadata(&service).unwrap().ino();
+
+        let provider = FileProvider::from_toml(
+            r#"
+            [[workload]]
+            name = "nginx"
+            namespace = "web"
+            cgroup = "/system.slice/nginx.service"
+            "#,
+        )
+        .unwrap();
+        let mut workloads = WorkloadResolver::new(&root).with_provider(provider);
+
+        // the first lookup only starts the walk of the cgroup tree
+        workloads.resolve(cgroup_id);
+        while workloads.scanning() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let metrics = Arc::default();
+        let (pipeline, peers, mut rx) = pipeline(&metrics);
+        let pipeline = pipeline.with_workloads(workloads);
+
+        let nginx = RawEvent {
+            process: Some(process(42, "nginx", cgroup_id)),
+            ..from(REMOTE, 100)
+        };
+        let events = run(pipeline, &mut rx, [nginx]).await;
+        fs::remove_dir_all(&root).unwrap();
+
+        let [Event::Peer(_), Event::Packet(packet)] = &events[..] else {
+            panic!("unexpected events {events:?}");
+        };
+        let workload = packet.workload.as_ref().unwrap();
+        assert_eq!(workload.to_string(), "web/nginx");
+
+        let peers = peers.lock().await;
+        assert_eq!(
+            peers.get(&REMOTE).unwrap().workloads["web/nginx"].egress_bytes,
+            100
+        );
+    }
+
+    #[tokio::test]
+    async fn batches_packet_events() {
+        let metrics = Arc::default();
+        let (pipeline, _, mut rx) = pipeline(&metrics);
+        let pipeline = pipeline.with_events(EventsConfig {
+            batch_interval_ms: Some(60_000),
+            sample_rate: 2,
+            ..Default::default()
+        });
+
+        let events = run(
+            pipeline,
+            &mut rx,
+            [100, 200, 300].map(|bytes| from(REMOTE, bytes)),
+        )
+        .await;
+
+        // sampling only applies to packets sent on their own
+        let [Event::Peer(_), Event::PacketBatch(batch)] = &events[..] else {
+            panic!("unexpected events {events:?}");
+        };
+        assert_eq!(batch.peer_addr, REMOTE);
+        assert_eq!(batch.events, 3);
+        assert_eq!(batch.ingress_bytes, 600);
+    }
+
+    #[tokio::test]
+    async fn samples_packet_events() {
+        let metrics = Arc::default();
+        let (pipeline, peers, mut rx) = pipeline(&metrics);
+        let pipeline = pipeline.with_events(EventsConfig {
+            sample_rate: 2,
+            ..Default::default()
+        });
+
+        let sampled = RawEvent {
+            sample_rate: 10,
+            ..from(REMOTE, 100)
+        };
+        let events = run(pipeline, &mut rx, iter::repeat_n(sampled, 4)).await;
+
+        let [Event::Peer(_), Event::Packet(first), Event::Packet(second)] = &events[..] else {
+            panic!("unexpected events {events:?}");
+        };
+        for packet in [first, second] {
+            assert_eq!(packet.sample_rate, 2);
+            assert_eq!(packet.capture_sample_rate, 10);
+        }
+
+        // every event is accounted, whether it was sent or not
+        assert_eq!(peers.lock().await.get(&REMOTE).unwrap().egress_bytes, 400);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{collections::BTreeMap, time::SystemTime};
+
+use palantir_ebpf_common::{Direction, RawEvent};
+
+use crate::{
+    event::{Process, ProcessTraffic},
+    lru::LruMap,
+};
+
+/// Byte counters of every process seen so far, per country of the peers it
+/// talked to.
+///
+/// Processes are identified by pid and name, so a reused pid shows up as a
+/// separate process. Threads of one process are counted together.
+///
+/// The least recently active processes are dropped beyond `max_processes`.
+#[derive(Debug, Clone)]
+pub struct ProcessTable {
+    processes: LruMap<(u32, String), ProcessTraffic>,
+    max_processes: usize,
+}
+
+impl ProcessTable {
+    pub fn new(max_processes: usize) -> Self {
+        Self {
+            processes: LruMap::new(),
+            max_processes,
+        }
+    }
+
+    pub fn processes(&self) -> impl Iterator<Item = &ProcessTraffic> {
+        self.processes.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.processes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.processes.is_empty()
+    }
+
+    /// Accounts `raw_event` to its process, if it has one, and the country
+    /// of its remote peer.
+    pub fn record(&mut self, raw_event: &RawEvent, country_code: &str, timestamp: SystemTime) {
+        let Some(info) = &raw_event.process else {
+            return;
+        };
+        let process = Process::from(info);
+        let key = (process.pid, process.comm.clone());
+
+        if self.processes.get_mut(&key).is_none() {
+            self.processes.insert(
+                key.clone(),
+                ProcessTraffic {
+                    process,
+                    ingress_bytes: 0,
+                    egress_bytes: 0,
+                    last_message: None,
+                    countries: BTreeMap::new(),
+                },
+            );
+
+            while self.processes.len() > self.max_processes {
+                self.processes.pop_lru();
+            }
+        }
+
+        let Some(traffic) = self.processes.get_mut(&key) else {
+            return;
+        };
+
+        let bytes = raw_event.wire_bytes;
+        let country = traffic
+            .countries
+            .entry(country_code.to_string())
+            .or_default();
+
+        match raw_event.direction {
+            Direction::Ingress => {
+                traffic.ingress_bytes += bytes;
+                country.ingress_bytes += bytes;
+            }
+            Direction::Egress => {
+                traffic.egress_bytes += bytes;
+                country.egress_bytes += bytes;
+            }
+        }
+
+        traffic.last_message = Some(timestamp);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::SystemTime,
+    };
+
+    use palantir_ebpf_common::{Direction, ProcessInfo, RawEvent};
+
+    use super::ProcessTable;
+    use crate::fixtures;
+
+    fn process(tgid: u32, comm: &str) -> ProcessInfo {
+        let mut info = ProcessInfo {
+            pid: tgid + 1,
+            tgid,
+            uid: 1000,
+            ..Default::default()
+        };
+        info.comm[..comm.len()].copy_from_slice(comm.as_bytes());
+        info
+    }
+
+    fn event(process: Option<ProcessInfo>, direction: Direction, wire_bytes: u64) -> RawEvent {
+        let remote = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+
+        RawEvent {
+            process,
+            src_port: 50000,
+            dst_port: 443,
+            ..fixtures::event(fixtures::LOCAL, remote, direction, wire_bytes)
+        }
+    }
+
+    #[test]
+    fn accounts_bytes_per_process_and_country() {
+        let mut processes = ProcessTable::new(16);
+        let now = SystemTime::now();
+        let curl = Some(process(42, "curl"));
+
+        processes.record(&event(curl, Direction::Egress, 100), "AU", now);
+        processes.record(&event(curl, Direction::Ingress, 40), "AU", now);
+        processes.record(&event(curl, Direction::Egress, 2), "DE", now);
+        processes.record(&event(None, Direction::Egress, 1000), "DE", now);
+
+        assert_eq!(processes.len(), 1);
+
+        let traffic = processes.processes().next().unwrap();
+        assert_eq!(traffic.process.pid, 42);
+        assert_eq!(traffic.process.tid, 43);
+        assert_eq!(traffic.process.comm, "curl");
+        assert_eq!(traffic.egress_bytes, 102);
+        assert_eq!(traffic.ingress_bytes, 40);
+        assert_eq!(traffic.countries["AU"].egress_bytes, 100);
+        assert_eq!(traffic.countries["AU"].ingress_bytes, 40);
+        assert_eq!(traffic.countries["DE"].egress_bytes, 2);
+    }
+
+    #[test]
+    fn reused_pid_is_another_process() {
+        let mut processes = ProcessTable::new(16);
+        let now = SystemTime::now();
+
+        processes.record(
+            &event(Some(process(42, "curl")), Direction::Egress, 1),
+            "AU",
+            now,
+        );
+        processes.record(
+            &event(Some(process(42, "sshd")), Direction::Egress, 1),
+            "AU",
+            now,
+        );
+
+        assert_eq!(processes.len(), 2);
+    }
+
+    #[test]
+    fn drops_least_recently_active_processes() {
+        let mut processes = ProcessTable::new(2);
+        let now = SystemTime::now();
+
+        for (tgid, comm) in [(1, "curl"), (2, "sshd"), (1, "curl"), (3, "nginx")] {
+            processes.record(
+                &event(Some(process(tgid, comm)), Direction::Egress, 1),
+                "AU",
+                now,
+            );
+        }
+
+        let mut names: Vec<_> = processes
+            .processes()
+            .map(|traffic| traffic.process.comm.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["curl", "nginx"]);
+    }
+}
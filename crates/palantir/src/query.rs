@@ -0,0 +1,261 @@
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::event::Peer;
+
+/// Peers without a message for longer than this are inactive, like on the
+/// dashboard.
+const ACTIVE_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerSort {
+    /// Ingress and egress bytes together.
+    #[default]
+    Bytes,
+    IngressBytes,
+    EgressBytes,
+    LastMessage,
+    Addr,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Filters, order and page of `/peers`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PeerQuery {
+    pub sort: PeerSort,
+    pub order: Order,
+    pub offset: usize,
+    /// 100 by default.
+    pub limit: Option<usize>,
+    /// Comma separated country codes.
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    /// Only peers with, or without, a message within `active_secs`.
+    pub active: Option<bool>,
+    /// 60 by default.
+    pub active_secs: Option<u64>,
+}
+
+/// One page of the peers matching a [`PeerQuery`].
+#[derive(Debug, Serialize)]
+pub struct PeerPage<'a> {
+    /// Matching peers on all pages.
+    pub total: usize,
+    pub offset: usize,
+    pub peers: Vec<&'a Peer>,
+}
+
+impl PeerQuery {
+    pub fn matches(&self, peer: &Peer, now: SystemTime) -> bool {
+        if let Some(countries) = &self.country
+            && !countries
+                .split(',')
+                .any(|country_code| country_code.eq_ignore_ascii_case(&peer.info.country_code))
+        {
+            return false;
+        }
+
+        if let Some(asn) = self.asn
+            && peer.info.asn.as_ref().map(|asn| asn.number) != Some(asn)
+        {
+            return false;
+        }
+
+        if let Some(active) = self.active {
+            let window = Duration::from_secs(self.active_secs.unwrap_or(ACTIVE_SECS));
+            let is_active = peer
+                .last_message
+                .is_some_and(|last| now.duration_since(last).unwrap_or_default() <= window);
+
+            if is_active != active {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn select<'a>(
+        &self,
+        peers: impl Iterator<Item = &'a Peer>,
+        now: SystemTime,
+    ) -> PeerPage<'a> {
+        let mut peers: Vec<_> = peers.filter(|peer| self.matches(peer, now)).collect();
+
+        match self.sort {
+            PeerSort::Bytes => peers.sort_by_key(|peer| peer.ingress_bytes + peer.egress_bytes),
+            PeerSort::IngressBytes => peers.sort_by_key(|peer| peer.ingress_bytes),
+            PeerSort::EgressBytes => peers.sort_by_key(|peer| peer.egress_bytes),
+            PeerSort::LastMessage => peers.sort_by_key(|peer| peer.last_message),
+            PeerSort::Addr => peers.sort_by_key(|peer| peer.addr),
+        }
+        if self.order == Order::Desc {
+            peers.reverse();
+        }
+
+        let total = peers.len();
+        let peers = peers
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(100))
+            .collect();
+
+        PeerPage {
+            total,
+            offset: self.offset,
+            peers,
+        }
+    }
+}
+
+/// Traffic of all peers located in one country.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CountryTraffic {
+    pub country_code: String,
+    pub peers: usize,
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+    pub last_message: Option<SystemTime>,
+}
+
+/// Sums up `peers` per country, busiest countries first.
+pub fn countries<'a>(peers: impl Iterator<Item = &'a Peer>) -> Vec<CountryTraffic> {
+    let mut countries = BTreeMap::<&str, CountryTraffic>::new();
+
+    for peer in peers {
+        let country = countries
+            .entry(&peer.info.country_code)
+            .or_insert_with(|| CountryTraffic {
+                country_code: peer.info.country_code.clone(),
+                peers: 0,
+                ingress_bytes: 0,
+                egress_bytes: 0,
+                last_message: None,
+            });
+
+        country.peers += 1;
+        country.ingress_bytes += peer.ingress_bytes;
+        country.egress_bytes += peer.egress_bytes;
+        country.last_message = country.last_message.max(peer.last_message);
+    }
+
+    let mut countries: Vec<_> = countries.into_values().collect();
+    countries.sort_by_key(|country| Reverse(country.ingress_bytes + country.egress_bytes));
+    countries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, SystemTime},
+    };
+
+    use super::{Order, PeerQuery, PeerSort, countries};
+    use crate::{
+        event::Peer,
+        fixtures,
+        resolver::{Asn, IpInfo},
+    };
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn peer(last: u8, country_code: &str, asn: u32, bytes: u64, last_message: u64) -> Peer {
+        let addr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, last));
+
+        Peer {
+            info: IpInfo {
+                asn: Some(Asn {
+                    number: asn,
+                    organization: None,
+                }),
+                ..fixtures::info(country_code)
+            },
+            ingress_bytes: bytes,
+            egress_bytes: bytes,
+            last_message: Some(at(last_message)),
+            ..fixtures::peer(addr, country_code)
+        }
+    }
+
+    fn addrs(peers: &[&Peer]) -> Vec<u8> {
+        peers
+            .iter()
+            .map(|peer| match peer.addr {
+                IpAddr::V4(addr) => addr.octets()[3],
+                IpAddr::V6(_) => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn filters_sorts_and_pages() {
+        let peers = [
+            peer(1, "AU", 13335, 100, 1000),
+            peer(2, "NZ", 13335, 300, 500),
+            peer(3, "AU", 15169, 200, 990),
+            peer(4, "US", 13335, 400, 1000),
+        ];
+        let now = at(1000);
+
+        let query = PeerQuery::default();
+        assert_eq!(addrs(&query.select(peers.iter(), now).peers), [4, 2, 3, 1]);
+
+        let query = PeerQuery {
+            country: Some("au,NZ".to_string()),
+            sort: PeerSort::Addr,
+            order: Order::Asc,
+            ..PeerQuery::default()
+        };
+        assert_eq!(addrs(&query.select(peers.iter(), now).peers), [1, 2, 3]);
+
+        let query = PeerQuery {
+            asn: Some(13335),
+            active: Some(true),
+            ..PeerQuery::default()
+        };
+        assert_eq!(addrs(&query.select(peers.iter(), now).peers), [4, 1]);
+
+        let query = PeerQuery {
+            offset: 1,
+            limit: Some(2),
+            ..PeerQuery::default()
+        };
+        let page = query.select(peers.iter(), now);
+        assert_eq!(page.total, 4);
+        assert_eq!(addrs(&page.peers), [2, 3]);
+    }
+
+    #[test]
+    fn sums_up_countries() {
+        let peers = [
+            peer(1, "AU", 13335, 100, 1000),
+            peer(2, "NZ", 13335, 50, 500),
+            peer(3, "AU", 15169, 200, 990),
+        ];
+
+        let countries = countries(peers.iter());
+        assert_eq!(countries.len(), 2);
+        assert_eq!(countries[0].country_code, "AU");
+        assert_eq!(countries[0].peers, 2);
+        assert_eq!(countries[0].ingress_bytes, 300);
+        assert_eq!(countries[0].last_message, Some(at(1000)));
+        assert_eq!(countries[1].country_code, "NZ");
+    }
+}
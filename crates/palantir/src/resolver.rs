@@ -1,8 +1,8 @@
 use std::{net::IpAddr, str::FromStr};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IpInfo {
     pub lat: f64,
     pub lon: f64,
@@ -10,9 +10,20 @@ pub struct IpInfo {
 
     #[serde(flatten)]
     pub details: LocationDetails,
+
+    /// Autonomous system announcing the address, if an ASN database is
+    /// configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asn: Option<Asn>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Asn {
+    pub number: u32,
+    pub organization: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "source")]
 pub enum LocationDetails {
     City {
@@ -23,11 +34,18 @@ pub enum LocationDetails {
     RegisteredCountry,
 }
 
+/// Source of the location of remote peers, asked once per address before
+/// it is cached.
+pub trait LocationProvider {
+    fn lookup(&self, addr: IpAddr) -> Option<IpInfo>;
+}
+
 pub struct Resolver<R>
 where
     R: AsRef<[u8]>,
 {
     city_reader: maxminddb::Reader<R>,
+    asn_reader: Option<maxminddb::Reader<R>>,
 }
 
 impl<R> Resolver<R>
@@ -35,64 +53,105 @@ where
     R: AsRef<[u8]>,
 {
     pub fn new(city_reader: maxminddb::Reader<R>) -> Self {
-        Self { city_reader }
+        Self {
+            city_reader,
+            asn_reader: None,
+        }
+    }
+
+    /// Also looks up the autonomous system of addresses in a GeoLite2/GeoIP2
+    /// ASN database.
+    pub fn with_asn(mut self, asn_reader: maxminddb::Reader<R>) -> Self {
+        self.asn_reader = Some(asn_reader);
+        self
     }
 
     pub fn resolve(&self, addr: IpAddr) -> Option<IpInfo> {
+        let mut info = self.resolve_location(addr)?;
+        info.asn = self.resolve_asn(addr);
+        Some(info)
+    }
+
+    fn resolve_asn(&self, addr: IpAddr) -> Option<Asn> {
+        let asn_data = self
+            .asn_reader
+            .as_ref()?
+            .lookup::<maxminddb::geoip2::Asn>(addr)
+            .ok()??;
+
+        Some(Asn {
+            number: asn_data.autonomous_system_number?,
+            organization: asn_data
+                .autonomous_system_organization
+                .map(|o| o.to_string()),
+        })
+    }
+
+    fn resolve_location(&self, addr: IpAddr) -> Option<IpInfo> {
         let city_data = self
             .city_reader
             .lookup::<maxminddb::geoip2::City>(addr)
             .ok()??;
 
-        if let Some(location) = &city_data.location {
-            if let (Some(lat), Some(lon), Some(accuracy_radius)) = (
+        if let Some(location) = &city_data.location
+            && let (Some(lat), Some(lon), Some(accuracy_radius)) = (
                 location.latitude,
                 location.longitude,
                 location.accuracy_radius,
-            ) {
-                let country_code = city_data
-                    .country
-                    .as_ref()
-                    .and_then(|c| c.iso_code)
-                    .map(|c| c.to_string())
-                    .unwrap_or_default();
-
-                let city_name = city_data
-                    .city
-                    .as_ref()
-                    .and_then(|c| c.names.as_ref())
-                    .and_then(|n| n.get("en"))
-                    .map(|s| s.to_string())
-                    .unwrap_or_default();
+            )
+        {
+            let country_code = city_data
+                .country
+                .as_ref()
+                .and_then(|c| c.iso_code)
+                .map(|c| c.to_string())
+                .unwrap_or_default();
+
+            let city_name = city_data
+                .city
+                .as_ref()
+                .and_then(|c| c.names.as_ref())
+                .and_then(|n| n.get("en"))
+                .map(|s| s.to_string())
+                .unwrap_or_default();
+
+            return Some(IpInfo {
+                lat,
+                lon,
+                country_code: country_code.clone(),
+                details: LocationDetails::City {
+                    city_name,
+                    accuracy_radius,
+                },
+                asn: None,
+            });
+        }
 
+        if let Some(registered_country) = &city_data.registered_country
+            && let Some(iso_code) = registered_country.iso_code
+            && let Ok(country) = my_country::Country::from_str(iso_code)
+        {
+            let geo = country.geo();
+            if let (Some(lat), Some(lon)) = (geo.latitude, geo.longitude) {
                 return Some(IpInfo {
                     lat,
                     lon,
-                    country_code: country_code.clone(),
-                    details: LocationDetails::City {
-                        city_name,
-                        accuracy_radius,
-                    },
+                    country_code: iso_code.to_string(),
+                    details: LocationDetails::RegisteredCountry,
+                    asn: None,
                 });
             }
         }
 
-        if let Some(registered_country) = &city_data.registered_country {
-            if let Some(iso_code) = registered_country.iso_code {
-                if let Ok(country) = my_country::Country::from_str(iso_code) {
-                    let geo = country.geo();
-                    if let (Some(lat), Some(lon)) = (geo.latitude, geo.longitude) {
-                        return Some(IpInfo {
-                            lat,
-                            lon,
-                            country_code: iso_code.to_string(),
-                            details: LocationDetails::RegisteredCountry,
-                        });
-                    }
-                }
-            }
-        }
-
         None
     }
 }
+
+impl<R> LocationProvider for Resolver<R>
+where
+    R: AsRef<[u8]>,
+{
+    fn lookup(&self, addr: IpAddr) -> Option<IpInfo> {
+        self.resolve(addr)
+    }
+}
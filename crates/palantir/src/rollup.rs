@@ -0,0 +1,408 @@
+use std::{
+    collections::VecDeque,
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use palantir_ebpf_common::{Direction, RawEvent};
+use serde::{Deserialize, Serialize};
+
+use crate::lru::LruMap;
+
+/// Width of the buckets of a series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Second,
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub const ALL: [Self; 3] = [Self::Second, Self::Minute, Self::Hour];
+
+    pub fn secs(self) -> u64 {
+        match self {
+            Self::Second => 1,
+            Self::Minute => 60,
+            Self::Hour => 60 * 60,
+        }
+    }
+
+    /// Buckets kept per series: 5 minutes, 3 hours and 2 days.
+    pub fn capacity(self) -> usize {
+        match self {
+            Self::Second => 300,
+            Self::Minute => 180,
+            Self::Hour => 48,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeriesKind {
+    Peer,
+    Country,
+    Port,
+}
+
+/// What the traffic of a series is grouped by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SeriesKey {
+    Peer(IpAddr),
+    Country(String),
+    Port(u16),
+}
+
+impl SeriesKey {
+    pub fn kind(&self) -> SeriesKind {
+        match self {
+            Self::Peer(_) => SeriesKind::Peer,
+            Self::Country(_) => SeriesKind::Country,
+            Self::Port(_) => SeriesKind::Port,
+        }
+    }
+
+    /// The address, country code or port, as given to [`Rollups::query`].
+    pub fn value(&self) -> String {
+        match self {
+            Self::Peer(addr) => addr.to_string(),
+            Self::Country(country_code) => country_code.clone(),
+            Self::Port(port) => port.to_string(),
+        }
+    }
+}
+
+/// Traffic within one bucket, `start` is in unix seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    pub start: u64,
+    pub ingress_bytes: u64,
+    pub egress_bytes: u64,
+    pub packets: u64,
+}
+
+/// Rates of a series averaged over a window, with the buckets to draw it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rates {
+    pub kind: SeriesKind,
+    pub key: String,
+    pub resolution: Resolution,
+    pub ingress_bytes_per_sec: f64,
+    pub egress_bytes_per_sec: f64,
+    pub packets_per_sec: f64,
+    /// Every bucket of the window, oldest first, including empty ones.
+    pub buckets: Vec<Bucket>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Series {
+    buckets: [VecDeque<Bucket>; 3],
+}
+
+impl Series {
+    fn record(&mut self, secs: u64, ingress_bytes: u64, egress_bytes: u64, packets: u64) {
+        for (resolution, buckets) in Resolution::ALL.into_iter().zip(&mut self.buckets) {
+            let start = secs - secs % resolution.secs();
+
+            let bucket = match buckets.back() {
+                Some(last) if last.start == start => buckets.back_mut(),
+                // harvested flows can be older than the last notification
+                Some(last) if last.start > start => buckets
+                    .iter_mut()
+                    .rev()
+                    .find(|bucket| bucket.start == start),
+                _ => {
+                    buckets.push_back(Bucket {
+                        start,
+                        ..Bucket::default()
+                    });
+                    if buckets.len() > resolution.capacity() {
+                        buckets.pop_front();
+                    }
+                    buckets.back_mut()
+                }
+            };
+
+            if let Some(bucket) = bucket {
+                bucket.ingress_bytes += ingress_bytes;
+                bucket.egress_bytes += egress_bytes;
+                bucket.packets += packets;
+            }
+        }
+    }
+
+    fn buckets(&self, resolution: Resolution) -> &VecDeque<Bucket> {
+        &self.buckets[resolution as usize]
+    }
+}
+
+/// Traffic per peer, country and port in second, minute and hour buckets.
+///
+/// Unlike the peer table, traffic is counted from the perspective of the
+/// local host: bytes received from a peer are ingress. The port of a series
+/// is the lower of the two ports of an event, which is the service port for
+/// all but the most unusual connections.
+///
+/// The least recently active series are dropped beyond `max_series`.
+#[derive(Debug, Clone)]
+pub struct Rollups {
+    series: LruMap<SeriesKey, Series>,
+    max_series: usize,
+}
+
+impl Rollups {
+    pub fn new(max_series: usize) -> Self {
+        Self {
+            series: LruMap::new(),
+            max_series,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    /// Accounts `raw_event` to its remote peer, the country of the peer and
+    /// its port.
+    pub fn record(&mut self, raw_event: &RawEvent, country_code: &str, timestamp: SystemTime) {
+        let secs = unix_secs(timestamp);
+        let (ingress_bytes, egress_bytes) = match raw_event.direction {
+            Direction::Ingress => (raw_event.wire_bytes, 0),
+            Direction::Egress => (0, raw_event.wire_bytes),
+        };
+
+        let port = raw_event
+            .ports()
+            .map(|(src_port, dst_port)| src_port.min(dst_port))
+            .filter(|port| *port != 0);
+
+        let keys = [
+            Some(SeriesKey::Peer(raw_event.peer_addr())),
+            Some(SeriesKey::Country(country_code.to_string())),
+            port.map(SeriesKey::Port),
+        ];
+
+        for key in keys.into_iter().flatten() {
+            let series = match self.series.get_mut(&key) {
+                Some(series) => series,
+                None => self.series.insert(key, Series::default()),
+            };
+            series.record(secs, ingress_bytes, egress_bytes, raw_event.packets);
+        }
+
+        while self.series.len() > self.max_series {
+            self.series.pop_lru();
+        }
+    }
+
+    /// Rates of the series of `kind`, or only the one of `key`, over the
+    /// `window` ending at `now`. Series without traffic within the window
+    /// are left out, the busiest come first.
+    pub fn query(
+        &self,
+        kind: SeriesKind,
+        key: Option<&str>,
+        resolution: Resolution,
+        window: Duration,
+        now: SystemTime,
+    ) -> Vec<Rates> {
+        let width = resolution.secs();
+        let count = window
+            .as_secs()
+            .div_ceil(width)
+            .clamp(1, resolution.capacity() as u64);
+        let end = unix_secs(now) / width * width;
+        let first = end.saturating_sub((count - 1) * width);
+
+        let mut rates: Vec<_> = self
+            .series
+            .iter()
+            .filter(|(series_key, _)| series_key.kind() == kind)
+            .filter(|(series_key, _)| key.is_none_or(|key| series_key.value() == key))
+            .filter_map(|(series_key, series)| {
+                let mut buckets: Vec<_> = (0..count)
+                    .map(|i| Bucket {
+                        start: first + i * width,
+                        ..Bucket::default()
+                    })
+                    .collect();
+
+                let mut active = false;
+                for bucket in series.buckets(resolution) {
+                    if (first..=end).contains(&bucket.start) {
+                        buckets[((bucket.start - first) / width) as usize] = *bucket;
+                        active = true;
+                    }
+                }
+
+                if !active {
+                    return None;
+                }
+
+                let secs = (count * width) as f64;
+                let total = |field: fn(&Bucket) -> u64| {
+                    buckets.iter().map(field).sum::<u64>() as f64 / secs
+                };
+
+                Some(Rates {
+                    kind,
+                    key: series_key.value(),
+                    resolution,
+                    ingress_bytes_per_sec: total(|bucket| bucket.ingress_bytes),
+                    egress_bytes_per_sec: total(|bucket| bucket.egress_bytes),
+                    packets_per_sec: total(|bucket| bucket.packets),
+                    buckets,
+                })
+            })
+            .collect();
+
+        rates.sort_by(|a, b| {
+            let a = a.ingress_bytes_per_sec + a.egress_bytes_per_sec;
+            let b = b.ingress_bytes_per_sec + b.egress_bytes_per_sec;
+            b.total_cmp(&a)
+        });
+
+        rates
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, SystemTime},
+    };
+
+    use palantir_ebpf_common::{Direction, RawEvent};
+
+    use super::{Resolution, Rollups, SeriesKind};
+    use crate::fixtures::{self, LOCAL};
+
+    fn event(remote: IpAddr, direction: Direction, wire_bytes: u64) -> RawEvent {
+        let (src_addr, dst_addr, src_port, dst_port) = match direction {
+            Direction::Ingress => (remote, LOCAL, 443, 50000),
+            Direction::Egress => (LOCAL, remote, 50000, 443),
+        };
+
+        RawEvent {
+            src_port,
+            dst_port,
+            ..fixtures::event(src_addr, dst_addr, direction, wire_bytes)
+        }
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn rolls_up_per_resolution() {
+        let remote = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let mut rollups = Rollups::new(100);
+
+        rollups.record(&event(remote, Direction::Ingress, 100), "AU", at(3600));
+        rollups.record(&event(remote, Direction::Egress, 50), "AU", at(3601));
+        rollups.record(&event(remote, Direction::Ingress, 10), "AU", at(3661));
+        // late event of an earlier second
+        rollups.record(&event(remote, Direction::Ingress, 1), "AU", at(3600));
+
+        let seconds = rollups.query(
+            SeriesKind::Peer,
+            None,
+            Resolution::Second,
+            Duration::from_secs(62),
+            at(3661),
+        );
+        assert_eq!(seconds.len(), 1);
+        assert_eq!(seconds[0].key, "1.1.1.1");
+        assert_eq!(seconds[0].buckets.len(), 62);
+        assert_eq!(seconds[0].buckets[0].start, 3600);
+        assert_eq!(seconds[0].buckets[0].ingress_bytes, 101);
+        assert_eq!(seconds[0].buckets[1].egress_bytes, 50);
+        assert_eq!(seconds[0].buckets[61].ingress_bytes, 10);
+        assert_eq!(seconds[0].egress_bytes_per_sec, 50.0 / 62.0);
+
+        let minutes = rollups.query(
+            SeriesKind::Country,
+            Some("AU"),
+            Resolution::Minute,
+            Duration::from_secs(120),
+            at(3661),
+        );
+        assert_eq!(minutes[0].buckets.len(), 2);
+        assert_eq!(minutes[0].buckets[0].ingress_bytes, 101);
+        assert_eq!(minutes[0].buckets[0].packets, 3);
+        assert_eq!(minutes[0].buckets[1].ingress_bytes, 10);
+
+        let ports = rollups.query(
+            SeriesKind::Port,
+            None,
+            Resolution::Hour,
+            Duration::from_secs(3600),
+            at(3661),
+        );
+        assert_eq!(ports[0].key, "443");
+        assert_eq!(ports[0].buckets[0].ingress_bytes, 111);
+        assert_eq!(ports[0].buckets[0].egress_bytes, 50);
+    }
+
+    #[test]
+    fn clamps_window_to_capacity() {
+        let remote = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let mut rollups = Rollups::new(100);
+
+        rollups.record(&event(remote, Direction::Ingress, 100), "AU", at(0));
+        rollups.record(&event(remote, Direction::Ingress, 100), "AU", at(1000));
+
+        let window = Duration::from_secs(2000);
+        let seconds = rollups.query(SeriesKind::Peer, None, Resolution::Second, window, at(1000));
+        assert_eq!(seconds[0].buckets.len(), Resolution::Second.capacity());
+        assert_eq!(
+            seconds[0]
+                .buckets
+                .iter()
+                .map(|b| b.ingress_bytes)
+                .sum::<u64>(),
+            100
+        );
+
+        let idle = rollups.query(SeriesKind::Peer, None, Resolution::Second, window, at(5000));
+        assert!(idle.is_empty());
+    }
+
+    #[test]
+    fn keeps_most_recently_active_series() {
+        let mut rollups = Rollups::new(4);
+
+        for i in 1..=3 {
+            let remote = IpAddr::V4(Ipv4Addr::new(1, 1, 1, i));
+            rollups.record(&event(remote, Direction::Ingress, 100), "AU", at(0));
+        }
+
+        // a peer, its country and port, and the peer before it
+        assert_eq!(rollups.len(), 4);
+
+        let peers = rollups.query(
+            SeriesKind::Peer,
+            None,
+            Resolution::Second,
+            Duration::from_secs(1),
+            at(0),
+        );
+        let mut keys: Vec<_> = peers.iter().map(|rates| rates.key.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, ["1.1.1.2", "1.1.1.3"]);
+    }
+}
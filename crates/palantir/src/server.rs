@@ -0,0 +1,516 @@
+use std::{
+    convert::Infallible,
+    net::IpAddr,
+    sync::{Arc, atomic::Ordering},
+    time::{Duration, SystemTime},
+};
+
+use async_stream::stream;
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response, Sse, sse},
+    routing::get,
+};
+use futures_util::{Stream, StreamExt};
+use palantir_ebpf_common::Direction;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    Mutex,
+    broadcast::{self, error::RecvError},
+    watch,
+};
+
+use crate::{
+    config::{FilterConfig, RetentionConfig},
+    event::{Event, Lagged, Peer},
+    history::{HistoryStore, InvalidWindow, query_history},
+    metrics::{Exposition, Metrics, direction_label},
+    pipeline::PeerTable,
+    process::ProcessTable,
+    query::{self, PeerQuery, PeerSort},
+    rollup::{Resolution, Rollups, SeriesKind},
+    subscription::{EventFilter, EventQuery},
+    ws,
+};
+
+/// Events buffered for every subscriber by default.
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// Busiest peers exported on `/metrics` by default.
+pub const TOP_PEERS: usize = 10;
+
+/// State shared between the pipeline feeding events and the HTTP handlers
+/// serving them.
+pub struct AppState {
+    pub tx: broadcast::Sender<Event>,
+    pub peers: Arc<Mutex<PeerTable>>,
+    pub processes: Arc<Mutex<ProcessTable>>,
+    pub rollups: Arc<Mutex<Rollups>>,
+    pub metrics: Arc<Metrics>,
+    pub history: Option<Arc<Mutex<HistoryStore>>>,
+    pub top_peers: usize,
+    /// Traffic to capture, replaced by `PUT /filter`.
+    pub filter: watch::Sender<FilterConfig>,
+}
+
+impl AppState {
+    pub fn new(local_peer: Peer) -> Self {
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        Self {
+            tx,
+            peers: Arc::new(Mutex::new(PeerTable::new(local_peer))),
+            processes: Arc::new(Mutex::new(ProcessTable::new(
+                RetentionConfig::default().max_processes,
+            ))),
+            rollups: Arc::new(Mutex::new(Rollups::new(
+                RetentionConfig::default().max_series,
+            ))),
+            metrics: Arc::default(),
+            history: None,
+            top_peers: TOP_PEERS,
+            filter: watch::Sender::new(FilterConfig::default()),
+        }
+    }
+
+    /// Buffers `capacity` events for every subscriber. Must be called before
+    /// the sender is handed to the pipeline.
+    pub fn with_channel_capacity(mut self, capacity: usize) -> Self {
+        (self.tx, _) = broadcast::channel(capacity);
+        self
+    }
+
+    /// Serves `/rates` from `rollups` instead of a default sized instance.
+    pub fn with_rollups(mut self, rollups: Rollups) -> Self {
+        self.rollups = Arc::new(Mutex::new(rollups));
+        self
+    }
+
+    pub fn with_processes(mut self, processes: ProcessTable) -> Self {
+        self.processes = Arc::new(Mutex::new(processes));
+        self
+    }
+
+    /// Exports the traffic of the `top_peers` busiest peers on `/metrics`.
+    pub fn with_top_peers(mut self, top_peers: usize) -> Self {
+        self.top_peers = top_peers;
+        self
+    }
+
+    /// Starts out capturing the traffic `filter` lets through. Sources and
+    /// the pipeline subscribe to [`AppState::filter`] for later changes.
+    pub fn with_filter(self, filter: FilterConfig) -> Self {
+        self.filter.send_replace(filter);
+        self
+    }
+
+    /// Serves `/history` from `history`, see [`crate::history::persist_history`].
+    pub fn with_history(mut self, history: HistoryStore) -> Self {
+        self.history = Some(Arc::new(Mutex::new(history)));
+        self
+    }
+}
+
+/// Builds the routes serving `state`. The returned router carries no layers,
+/// so it can be nested into or merged with an existing application.
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/events", get(events))
+        .route("/ws", get(ws::upgrade))
+        .route("/peers", get(peers))
+        .route("/peers/{addr}", get(peer))
+        .route("/countries", get(countries))
+        .route("/processes", get(processes))
+        .route("/stats", get(stats))
+        .route("/history", get(history))
+        .route("/rates", get(rates))
+        .route("/metrics", get(metrics))
+        .route("/filter", get(filter).put(replace_filter))
+        .with_state(state)
+}
+
+fn json<T>(value: &T) -> Response
+where
+    T: Serialize,
+{
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string(value).unwrap(),
+    )
+        .into_response()
+}
+
+#[derive(Serialize)]
+struct Stats<'a> {
+    /// Peers in the table, including the local peer.
+    peers: usize,
+    countries: usize,
+    processes: usize,
+    rate_series: usize,
+    subscribers: usize,
+    /// On-wire bytes received and sent by the local host.
+    ingress_bytes: u64,
+    egress_bytes: u64,
+    #[serde(flatten)]
+    metrics: &'a Metrics,
+}
+
+async fn stats(State(state): State<Arc<AppState>>) -> Response {
+    let (peers, countries, ingress_bytes, egress_bytes) = {
+        let peers = state.peers.lock().await;
+        let local = peers.local_peer();
+        let countries = query::countries(peers.remote_peers()).len();
+        (
+            peers.len(),
+            countries,
+            local.ingress_bytes,
+            local.egress_bytes,
+        )
+    };
+    let processes = state.processes.lock().await.len();
+    let rate_series = state.rollups.lock().await.len();
+
+    json(&Stats {
+        peers,
+        countries,
+        processes,
+        rate_series,
+        subscribers: state.tx.receiver_count(),
+        ingress_bytes,
+        egress_bytes,
+        metrics: &state.metrics,
+    })
+}
+
+/// Prometheus metrics. Only the busiest peers are exported with their
+/// address as a label, to bound the number of series.
+async fn metrics(State(state): State<Arc<AppState>>) -> Response {
+    let mut out = Exposition::new();
+
+    {
+        let peers = state.peers.lock().await;
+
+        out.family("palantir_peers", "gauge", "Peers in the table");
+        out.sample("palantir_peers", &[], peers.len());
+
+        let query = PeerQuery {
+            sort: PeerSort::Bytes,
+            limit: Some(state.top_peers),
+            ..PeerQuery::default()
+        };
+        let page = query.select(peers.remote_peers(), SystemTime::now());
+
+        out.family(
+            "palantir_peer_bytes",
+            "gauge",
+            "On-wire bytes of the busiest peers by direction of the local host",
+        );
+        for peer in page.peers {
+            let addr = peer.addr.to_string();
+
+            // the counters of a peer are kept from its own perspective
+            for (direction, bytes) in [
+                (Direction::Ingress, peer.egress_bytes),
+                (Direction::Egress, peer.ingress_bytes),
+            ] {
+                let labels = [
+                    ("peer", addr.as_str()),
+                    ("country", peer.info.country_code.as_str()),
+                    ("direction", direction_label(direction)),
+                ];
+                out.sample("palantir_peer_bytes", &labels, bytes);
+            }
+        }
+    }
+
+    out.family(
+        "palantir_subscribers",
+        "gauge",
+        "Subscribers of /events and /ws",
+    );
+    out.sample("palantir_subscribers", &[], state.tx.receiver_count());
+
+    state.metrics.encode(&mut out);
+
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        out.finish(),
+    )
+        .into_response()
+}
+
+async fn filter(State(state): State<Arc<AppState>>) -> Response {
+    json(&*state.filter.borrow())
+}
+
+/// Replaces the capture filter with the JSON body, the eBPF maps are updated
+/// by the source. Traffic accounted before stays in the peer table.
+async fn replace_filter(State(state): State<Arc<AppState>>, body: Bytes) -> Response {
+    let filter = match serde_json::from_slice::<FilterConfig>(&body) {
+        Ok(filter) => filter,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+
+    if let Err(err) = filter.validate() {
+        return (StatusCode::BAD_REQUEST, err.to_string()).into_response();
+    }
+
+    state.filter.send_replace(filter.clone());
+
+    json(&filter)
+}
+
+/// Peers matching the query, see [`PeerQuery`].
+async fn peers(State(state): State<Arc<AppState>>, Query(query): Query<PeerQuery>) -> Response {
+    let peers = state.peers.lock().await;
+
+    json(&query.select(peers.peers(), SystemTime::now()))
+}
+
+async fn peer(State(state): State<Arc<AppState>>, Path(addr): Path<IpAddr>) -> Response {
+    match state.peers.lock().await.get(&addr) {
+        Some(peer) => json(peer),
+        None => (StatusCode::NOT_FOUND, format!("{addr} is not a known peer")).into_response(),
+    }
+}
+
+/// Traffic per country of the remote peers, busiest countries first.
+async fn countries(State(state): State<Arc<AppState>>) -> Response {
+    let peers = state.peers.lock().await;
+
+    json(&query::countries(peers.remote_peers()))
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub(crate) struct HistoryQuery {
+    /// Unix seconds, a day before `to` by default.
+    pub from: Option<u64>,
+    /// Unix seconds, now by default.
+    pub to: Option<u64>,
+}
+
+impl HistoryQuery {
+    /// Fails for timestamps beyond what the system clock can represent.
+    pub(crate) fn window(&self) -> Result<(SystemTime, SystemTime), InvalidWindow> {
+        let unix = |param, secs: u64| {
+            SystemTime::UNIX_EPOCH
+                .checked_add(Duration::from_secs(secs))
+                .ok_or(InvalidWindow { param, secs })
+        };
+
+        let to = match self.to {
+            Some(to) => unix("to", to)?,
+            None => SystemTime::now(),
+        };
+        let from = match self.from {
+            Some(from) => unix("from", from)?,
+            None => to
+                .checked_sub(Duration::from_secs(24 * 60 * 60))
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+        };
+
+        Ok((from, to))
+    }
+}
+
+/// Traffic per peer within a window of the persisted history.
+async fn history(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let Some(history) = &state.history else {
+        return (StatusCode::NOT_FOUND, "history is not enabled").into_response();
+    };
+
+    let (from, to) = match query.window() {
+        Ok(window) => window,
+        Err(err) => return (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    };
+
+    match query_history(history.clone(), from, to).await {
+        Ok(peers) => json(&peers),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct RatesQuery {
+    kind: SeriesKind,
+    /// Address, country code or port of a single series.
+    key: Option<String>,
+    resolution: Option<Resolution>,
+    /// Seconds, a minute by default.
+    window: Option<u64>,
+    /// Series returned, the busiest first.
+    limit: Option<usize>,
+}
+
+/// Recent rates of the peer, country or port series, for sparklines.
+async fn rates(State(state): State<Arc<AppState>>, Query(query): Query<RatesQuery>) -> Response {
+    let mut rates = state.rollups.lock().await.query(
+        query.kind,
+        query.key.as_deref(),
+        query.resolution.unwrap_or(Resolution::Second),
+        Duration::from_secs(query.window.unwrap_or(60)),
+        SystemTime::now(),
+    );
+    rates.truncate(query.limit.unwrap_or(100));
+
+    json(&rates)
+}
+
+/// Traffic per process and country, busiest processes first.
+async fn processes(State(state): State<Arc<AppState>>) -> Response {
+    let mut processes: Vec<_> = state.processes.lock().await.processes().cloned().collect();
+    processes
+        .sort_by_key(|traffic| std::cmp::Reverse(traffic.ingress_bytes + traffic.egress_bytes));
+
+    json(&processes)
+}
+
+/// Streams the peer table followed by live events, both narrowed down by the
+/// query parameters, see [`EventQuery`].
+async fn events(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<EventQuery>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, Response> {
+    let filter = EventFilter::try_from(query)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()).into_response())?;
+
+    let stream = subscribe(state, filter)
+        .map(|event| Ok(sse::Event::default().data(serde_json::to_string(&event).unwrap())));
+
+    Ok(Sse::new(stream))
+}
+
+/// The events of one subscriber. A subscriber falling behind by more than the
+/// channel capacity gets a [`Lagged`] event and the peer table again, so its
+/// counters are correct despite the missed events.
+fn subscribe(state: Arc<AppState>, filter: EventFilter) -> impl Stream<Item = Event> {
+    let mut rx = state.tx.subscribe();
+
+    stream! {
+        for event in snapshot(&state, &filter).await {
+            yield event;
+        }
+
+        loop {
+            match rx.recv().await {
+                Ok(event) => {
+                    if filter.matches(&event) {
+                        yield event;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    state.metrics.subscribers_lagged.fetch_add(1, Ordering::Relaxed);
+                    state.metrics.events_dropped.fetch_add(skipped, Ordering::Relaxed);
+
+                    yield Event::Lagged(Lagged { skipped });
+
+                    for event in snapshot(&state, &filter).await {
+                        yield event;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// The matching peers, followed by the capture counters if the eBPF programs
+/// ever missed packets.
+pub(crate) async fn snapshot(state: &AppState, filter: &EventFilter) -> Vec<Event> {
+    let peers = state.peers.lock().await;
+    let capture = *state.metrics.capture.lock().unwrap();
+
+    peers
+        .peers()
+        .map(|peer| Event::Peer(peer.clone()))
+        .chain((capture.missed() > 0).then_some(Event::Capture(capture)))
+        .filter(|event| filter.matches(event))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::{Arc, atomic::Ordering},
+        time::{Duration, SystemTime},
+    };
+
+    use futures_util::StreamExt;
+
+    use super::{AppState, HistoryQuery, subscribe};
+    use crate::{
+        event::{Event, Lagged, PeerRemoved, RemovalReason},
+        fixtures::{self, LOCAL},
+        subscription::EventFilter,
+    };
+
+    fn removed(last: u8) -> Event {
+        Event::PeerRemoved(PeerRemoved {
+            addr: IpAddr::V4(Ipv4Addr::new(1, 1, 1, last)),
+            reason: RemovalReason::Idle,
+        })
+    }
+
+    fn addr(event: &Event) -> Option<IpAddr> {
+        match event {
+            Event::Peer(peer) => Some(peer.addr),
+            Event::PeerRemoved(removed) => Some(removed.addr),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn resyncs_lagging_subscribers() {
+        let state = AppState::new(fixtures::peer(LOCAL, "AT")).with_channel_capacity(2);
+        let state = Arc::new(state);
+
+        let mut events = Box::pin(subscribe(state.clone(), EventFilter::default()));
+        assert_eq!(addr(&events.next().await.unwrap()), Some(LOCAL));
+
+        for last in 1..=5 {
+            state.tx.send(removed(last)).unwrap();
+        }
+
+        let events: Vec<_> = events.take(4).collect().await;
+        assert!(matches!(events[0], Event::Lagged(Lagged { skipped: 3 })));
+        assert_eq!(addr(&events[1]), Some(LOCAL));
+        assert_eq!(addr(&events[2]), addr(&removed(4)));
+        assert_eq!(addr(&events[3]), addr(&removed(5)));
+
+        assert_eq!(state.metrics.subscribers_lagged.load(Ordering::Relaxed), 1);
+        assert_eq!(state.metrics.events_dropped.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn rejects_windows_beyond_the_clock() {
+        let (from, to) = HistoryQuery {
+            from: None,
+            to: Some(3600),
+        }
+        .window()
+        .unwrap();
+        assert_eq!(to, SystemTime::UNIX_EPOCH + Duration::from_secs(3600));
+        assert_eq!(to.duration_since(from).unwrap(), Duration::from_secs(86400));
+
+        let err = HistoryQuery {
+            from: Some(0),
+            to: Some(u64::MAX),
+        }
+        .window()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid to: 18446744073709551615 is out of range"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+mod ebpf;
+mod pcap;
+mod record;
+mod replay;
+mod synthetic;
+
+use std::{
+    io,
+    time::{Duration, Instant, SystemTime},
+};
+
+use palantir_ebpf_common::RawEvent;
+
+pub use self::{
+    ebpf::{EbpfSource, resolve_interfaces},
+    pcap::PcapSource,
+    record::Recorder,
+    replay::ReplaySource,
+    synthetic::SyntheticSource,
+};
+
+/// A network interface events are attributed to via `RawEvent::ifindex`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    pub index: u32,
+    pub name: String,
+}
+
+pub trait EventSource {
+    /// Wall clock time the `ts_offset_ns` of yielded events is relative to.
+    fn boot_time(&self) -> SystemTime;
+
+    /// Interfaces the `ifindex` of yielded events refers to.
+    fn interfaces(&self) -> Vec<Interface> {
+        Vec::new()
+    }
+
+    /// Waits for the next event, returning `Ok(None)` once the source is exhausted.
+    fn next(&mut self) -> impl Future<Output = io::Result<Option<RawEvent>>> + Send;
+}
+
+/// Paces recorded timestamps against the wall clock, rebasing them onto the
+/// moment the first event is played back and scaling them by `speed`.
+struct Pacer {
+    speed: f64,
+    /// When the first event was played back, and its recorded timestamp.
+    first: Option<(Instant, u64)>,
+}
+
+impl Pacer {
+    fn new(speed: f64) -> Self {
+        Self { speed, first: None }
+    }
+
+    async fn pace(&mut self, ts_ns: u64) -> u64 {
+        let (start, first_ns) = *self.first.get_or_insert_with(|| (Instant::now(), ts_ns));
+        let offset_ns = (ts_ns.saturating_sub(first_ns) as f64 / self.speed) as u64;
+
+        tokio::time::sleep_until((start + Duration::from_nanos(offset_ns)).into()).await;
+
+        offset_ns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::Pacer;
+
+    #[tokio::test]
+    async fn paces_from_the_first_event() {
+        let mut pacer = Pacer::new(1.0);
+
+        // a source is often opened well before it is read from
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        assert_eq!(pacer.pace(1_000).await, 0);
+        assert_eq!(pacer.pace(20_001_000).await, 20_000_000);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
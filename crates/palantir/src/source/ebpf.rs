@@ -0,0 +1,661 @@
+use std::{
+    collections::VecDeque,
+    ffi::CString,
+    fs::{self, File},
+    io,
+    mem::zeroed,
+    os::fd::AsRawFd,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use aya::{
+    Ebpf,
+    maps::{
+        Array, HashMap, MapData, MapError, PerCpuArray, PerCpuHashMap, RingBuf,
+        lpm_trie::{Key, LpmTrie},
+    },
+    programs::{
+        CgroupAttachMode, CgroupSkb, CgroupSkbAttachType, CgroupSock, SchedClassifier,
+        TcAttachType, Xdp, XdpFlags, tc,
+    },
+};
+use libc::{
+    ARPHRD_NONE, ARPHRD_PPP, ARPHRD_TUNNEL, ARPHRD_TUNNEL6, CLOCK_BOOTTIME, CLOCK_REALTIME,
+    clock_gettime, if_nametoindex, timespec,
+};
+use palantir_ebpf_common::{
+    AddrRuleKey, CaptureFilter, Counter, FlowKey, FlowStats, ProcessInfo, RULE_EXCLUDE,
+    RULE_INCLUDE, RawEvent, Sampling, SocketKey,
+};
+use tokio::{
+    io::{Interest, unix::AsyncFd},
+    sync::watch,
+    time::{Interval, MissedTickBehavior, interval},
+};
+use tracing::{debug, info, warn};
+
+use super::{EventSource, Interface};
+use crate::{
+    config::{FilterConfig, IngressHook, SamplingConfig},
+    event::{CaptureStats, ParseFailures},
+    flow::FlowTable,
+    metrics::Metrics,
+};
+
+const HARVEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The cgroup programs are attached to the root of the unified hierarchy to
+/// see the sockets of every process.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Device types whose packets start at the IP header, such as WireGuard and
+/// tun (`ARPHRD_NONE`), PPP and IP-in-IP tunnels.
+const L3_DEVICE_TYPES: [u16; 4] = [ARPHRD_NONE, ARPHRD_PPP, ARPHRD_TUNNEL, ARPHRD_TUNNEL6];
+
+/// Captures traffic with the TC classifiers, or the XDP program for ingress
+/// traffic if configured. The kernel accumulates packets
+/// per flow in the `FLOWS` map and only announces new flows on the `EVENTS`
+/// ring buffer; the map is harvested every [`HARVEST_INTERVAL`] and yields
+/// one event per flow with traffic since the previous harvest.
+///
+/// Processes are attributed by the cgroup socket programs, which record the
+/// owner of every connection in the `OWNERS` map. Flows the classifiers saw
+/// before their owner was known are looked up again by userspace.
+///
+/// Packets outside the capture filter are skipped by the classifiers, the
+/// filter maps are rewritten whenever the filter changes.
+pub struct EbpfSource {
+    _ebpf: Ebpf,
+    events: RingBuf<MapData>,
+    flows: PerCpuHashMap<MapData, FlowKey, FlowStats>,
+    owners: HashMap<MapData, SocketKey, ProcessInfo>,
+    counters: PerCpuArray<MapData, u64>,
+    rules: FilterMaps,
+    filter: Option<watch::Receiver<FilterConfig>>,
+    metrics: Arc<Metrics>,
+    poll: AsyncFd<i32>,
+    harvest: Interval,
+    table: FlowTable,
+    pending: VecDeque<RawEvent>,
+    interfaces: Vec<Interface>,
+    boot_time: SystemTime,
+}
+
+impl EbpfSource {
+    /// Loads and attaches the programs. Only one in `sampling.rate` packets
+    /// is counted, events carry the rate their counters were scaled by.
+    pub fn new(
+        interfaces: Vec<Interface>,
+        ingress: IngressHook,
+        sampling: &SamplingConfig,
+    ) -> Self {
+        let mut ebpf = load_ebpf();
+
+        let events = RingBuf::try_from(ebpf.take_map("EVENTS").unwrap()).unwrap();
+        let flows = PerCpuHashMap::try_from(ebpf.take_map("FLOWS").unwrap()).unwrap();
+        let owners = HashMap::try_from(ebpf.take_map("OWNERS").unwrap()).unwrap();
+        let counters = PerCpuArray::try_from(ebpf.take_map("COUNTERS").unwrap()).unwrap();
+        let rules = FilterMaps {
+            filter: Array::try_from(ebpf.take_map("FILTER").unwrap()).unwrap(),
+            addr_rules: LpmTrie::try_from(ebpf.take_map("ADDR_RULES").unwrap()).unwrap(),
+            port_rules: HashMap::try_from(ebpf.take_map("PORT_RULES").unwrap()).unwrap(),
+            proto_rules: Array::try_from(ebpf.take_map("PROTO_RULES").unwrap()).unwrap(),
+        };
+        let mut sampling_map: Array<MapData, Sampling> =
+            Array::try_from(ebpf.take_map("SAMPLING").unwrap()).unwrap();
+        // before attaching, so no packet is counted at another rate
+        match sampling_map.set(0, sampling.sampling(), 0) {
+            Ok(()) => info!("sampling one in {} packets", sampling.rate),
+            Err(err) => warn!("failed to configure sampling: {}", err),
+        }
+        let mut l3_interfaces: HashMap<MapData, u32, u8> =
+            HashMap::try_from(ebpf.take_map("L3_INTERFACES").unwrap()).unwrap();
+        for interface in interfaces.iter().filter(|i| !has_link_header(&i.name)) {
+            match l3_interfaces.insert(interface.index, 1, 0) {
+                Ok(()) => info!("{} has no link layer header", interface.name),
+                Err(err) => warn!("failed to configure {}: {}", interface.name, err),
+            }
+        }
+
+        attach_programs(&mut ebpf, &interfaces, ingress);
+
+        let poll = AsyncFd::new(events.as_raw_fd()).unwrap();
+
+        let mut harvest = interval(HARVEST_INTERVAL);
+        harvest.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Self {
+            _ebpf: ebpf,
+            events,
+            flows,
+            owners,
+            counters,
+            rules,
+            filter: None,
+            metrics: Arc::default(),
+            poll,
+            harvest,
+            table: FlowTable::new(),
+            pending: VecDeque::new(),
+            interfaces,
+            boot_time: boot_time(),
+        }
+    }
+
+    /// Reports the counters of the classifiers to `metrics` instead of a
+    /// private instance.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Captures only the traffic `filter` lets through, following its
+    /// changes until the sender is dropped.
+    pub fn with_filter(mut self, filter: watch::Receiver<FilterConfig>) -> Self {
+        self.filter = Some(filter);
+        self.apply_filter();
+        self
+    }
+
+    fn apply_filter(&mut self) {
+        let Some(filter) = &mut self.filter else {
+            return;
+        };
+        let filter = filter.borrow_and_update().clone();
+
+        match self.rules.apply(&filter) {
+            Ok(()) => info!("applied capture filter {:?}", filter),
+            Err(err) => warn!("failed to apply capture filter: {}", err),
+        }
+    }
+
+    fn harvest(&mut self) -> io::Result<()> {
+        let snapshot = self
+            .flows
+            .iter()
+            .map(|entry| {
+                let (key, values) = entry.map_err(io::Error::other)?;
+                let stats = values
+                    .iter()
+                    .copied()
+                    .fold(FlowStats::default(), FlowStats::merge);
+                Ok((key, stats))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        for mut raw_event in self.table.harvest(snapshot) {
+            raw_event.process = self.owner(&raw_event);
+            self.pending.push_back(raw_event);
+        }
+        debug!(
+            "harvested {} flows, {} with new traffic",
+            self.table.len(),
+            self.pending.len()
+        );
+
+        *self.metrics.capture.lock().unwrap() = self.read_counters()?;
+
+        Ok(())
+    }
+
+    fn read_counters(&self) -> io::Result<CaptureStats> {
+        let mut values = [0; Counter::ALL.len()];
+        for counter in Counter::ALL {
+            let per_cpu = self
+                .counters
+                .get(&(counter as u32), 0)
+                .map_err(io::Error::other)?;
+            values[counter as usize] = per_cpu.iter().sum();
+        }
+
+        let value = |counter: Counter| values[counter as usize];
+
+        Ok(CaptureStats {
+            events_emitted: value(Counter::EventsEmitted),
+            ring_buffer_dropped: value(Counter::RingBufferFull),
+            filtered: value(Counter::Filtered),
+            sampled_out: value(Counter::SampledOut),
+            parse_failures: ParseFailures {
+                truncated: value(Counter::Truncated),
+                unknown_ether_type: value(Counter::UnknownEtherType),
+                ip_version_mismatch: value(Counter::IpVersionMismatch),
+                extension_header_overflow: value(Counter::ExtensionHeaderOverflow),
+            },
+        })
+    }
+
+    fn owner(&self, raw_event: &RawEvent) -> Option<ProcessInfo> {
+        raw_event
+            .process
+            .or_else(|| self.owners.get(&SocketKey::from(raw_event), 0).ok())
+    }
+}
+
+impl EventSource for EbpfSource {
+    fn boot_time(&self) -> SystemTime {
+        self.boot_time
+    }
+
+    fn interfaces(&self) -> Vec<Interface> {
+        self.interfaces.clone()
+    }
+
+    async fn next(&mut self) -> io::Result<Option<RawEvent>> {
+        loop {
+            if let Some(raw_event) = self.pending.pop_front() {
+                return Ok(Some(raw_event));
+            }
+
+            while let Some(item) = self.events.next() {
+                let mut raw_event = unsafe { *(item.as_ptr() as *const RawEvent) };
+                if self.table.notify(&raw_event) {
+                    raw_event.process = self.owner(&raw_event);
+                    return Ok(Some(raw_event));
+                }
+            }
+
+            tokio::select! {
+                guard = self.poll.readable() => guard?.clear_ready(),
+                _ = self.harvest.tick() => self.harvest()?,
+                changed = filter_changed(&mut self.filter) => match changed {
+                    Ok(()) => self.apply_filter(),
+                    Err(_) => self.filter = None,
+                },
+            }
+        }
+    }
+}
+
+/// Resolves once the filter changed, never without a filter.
+async fn filter_changed(
+    filter: &mut Option<watch::Receiver<FilterConfig>>,
+) -> Result<(), watch::error::RecvError> {
+    match filter {
+        Some(filter) => filter.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The maps the classifiers consult before accounting a packet, see
+/// [`CaptureFilter`].
+struct FilterMaps {
+    filter: Array<MapData, CaptureFilter>,
+    addr_rules: LpmTrie<MapData, AddrRuleKey, u8>,
+    port_rules: HashMap<MapData, u16, u8>,
+    proto_rules: Array<MapData, u8>,
+}
+
+impl FilterMaps {
+    /// Rewrites the rules of `filter`. The classifiers capture everything
+    /// while the rules are replaced, the pipeline still drops that traffic.
+    fn apply(&mut self, filter: &FilterConfig) -> Result<(), MapError> {
+        self.filter.set(0, CaptureFilter::default(), 0)?;
+
+        let keys = self.addr_rules.keys().collect::<Result<Vec<_>, _>>()?;
+        for key in keys {
+            self.addr_rules.remove(&key)?;
+        }
+        // exclusions are written last to win over an equal inclusion
+        for (cidrs, rule) in [
+            (&filter.include_addrs, RULE_INCLUDE),
+            (&filter.exclude_addrs, RULE_EXCLUDE),
+        ] {
+            for cidr in cidrs {
+                let key = Key::new(
+                    AddrRuleKey::prefix_len(cidr.prefix_len()),
+                    AddrRuleKey::new(cidr.addr()),
+                );
+                self.addr_rules.insert(&key, rule, 0)?;
+            }
+        }
+
+        let ports = self.port_rules.keys().collect::<Result<Vec<_>, _>>()?;
+        for port in ports {
+            self.port_rules.remove(&port)?;
+        }
+        for (ports, rule) in [
+            (&filter.include_ports, RULE_INCLUDE),
+            (&filter.exclude_ports, RULE_EXCLUDE),
+        ] {
+            for port in ports {
+                self.port_rules.insert(port, rule, 0)?;
+            }
+        }
+
+        for proto in 0..=u8::MAX {
+            let rule = match filter.protocols.iter().any(|p| p.0 == proto) {
+                true => RULE_INCLUDE,
+                false => 0,
+            };
+            self.proto_rules.set(proto as u32, rule, 0)?;
+        }
+
+        let exclude_directions = filter
+            .direction
+            .map_or(0, |direction| !(1 << direction as u8) & 0b11);
+
+        self.filter.set(
+            0,
+            CaptureFilter {
+                include_addrs: !filter.include_addrs.is_empty() as u8,
+                include_ports: !filter.include_ports.is_empty() as u8,
+                include_protos: !filter.protocols.is_empty() as u8,
+                exclude_directions,
+            },
+            0,
+        )
+    }
+}
+
+/// Expands interface names and glob patterns (`*`, `?`) against the
+/// interfaces currently present on the host.
+pub fn resolve_interfaces(patterns: &[String]) -> io::Result<Vec<Interface>> {
+    let names: Vec<_> = fs::read_dir("/sys/class/net")?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+
+    match_interfaces(patterns, names, |name| {
+        let c_name = CString::new(name).map_err(io::Error::other)?;
+        match unsafe { if_nametoindex(c_name.as_ptr()) } {
+            0 => Err(io::Error::last_os_error()),
+            index => Ok(index),
+        }
+    })
+}
+
+/// Expands `patterns` against the interface `names`, every interface is
+/// looked up by `index_of` and listed once, in the order of the patterns.
+fn match_interfaces<F>(
+    patterns: &[String],
+    mut names: Vec<String>,
+    index_of: F,
+) -> io::Result<Vec<Interface>>
+where
+    F: Fn(&str) -> io::Result<u32>,
+{
+    names.sort();
+
+    let mut interfaces = Vec::new();
+
+    for pattern in patterns {
+        let mut matched = false;
+
+        for name in names.iter().filter(|name| glob_match(pattern, name)) {
+            matched = true;
+
+            if interfaces.iter().any(|i: &Interface| &i.name == name) {
+                continue;
+            }
+
+            interfaces.push(Interface {
+                index: index_of(name)?,
+                name: name.clone(),
+            });
+        }
+
+        if !matched {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no interface matches {pattern:?}"),
+            ));
+        }
+    }
+
+    Ok(interfaces)
+}
+
+/// Whether packets of the interface start with an Ethernet header, assumed
+/// if its device type cannot be read.
+fn has_link_header(name: &str) -> bool {
+    fs::read_to_string(format!("/sys/class/net/{name}/type"))
+        .ok()
+        .and_then(|device_type| device_type.trim().parse::<u16>().ok())
+        .is_none_or(|device_type| !L3_DEVICE_TYPES.contains(&device_type))
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[u8], name: &[u8]) -> bool {
+        match (pattern.split_first(), name.split_first()) {
+            (None, None) => true,
+            (Some((b'*', rest)), _) => {
+                matches(rest, name) || (!name.is_empty() && matches(pattern, &name[1..]))
+            }
+            (Some((b'?', rest)), Some((_, name))) => matches(rest, name),
+            (Some((p, rest)), Some((n, name))) if p == n => matches(rest, name),
+            _ => false,
+        }
+    }
+
+    matches(pattern.as_bytes(), name.as_bytes())
+}
+
+fn boot_time() -> SystemTime {
+    let boot_time = unsafe {
+        let mut ts: timespec = zeroed();
+        clock_gettime(CLOCK_BOOTTIME, &mut ts);
+        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+    };
+
+    let real_time = unsafe {
+        let mut ts: timespec = zeroed();
+        clock_gettime(CLOCK_REALTIME, &mut ts);
+        Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+    };
+
+    SystemTime::UNIX_EPOCH + (real_time - boot_time)
+}
+
+fn load_ebpf() -> Ebpf {
+    let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
+        env!("OUT_DIR"),
+        "/palantir"
+    )))
+    .expect("failed to load ebpf program");
+
+    // the programs count instead of logging, there may be nothing to log
+    match aya_log::EbpfLogger::init(&mut ebpf) {
+        Err(e) => {
+            debug!("failed to initialize eBPF logger: {}", e);
+        }
+        Ok(logger) => {
+            let mut logger = AsyncFd::with_interest(logger, Interest::READABLE).unwrap();
+            tokio::spawn(async move {
+                loop {
+                    let mut guard = logger.readable_mut().await.unwrap();
+                    guard.get_inner_mut().flush();
+                    guard.clear_ready();
+                }
+            });
+        }
+    }
+
+    ebpf
+}
+
+/// Attaches the programs, the maps they read must be written before.
+fn attach_programs(ebpf: &mut Ebpf, interfaces: &[Interface], ingress: IngressHook) {
+    let tc_ingress = match ingress {
+        IngressHook::Tc => interfaces.to_vec(),
+        IngressHook::Xdp => attach_xdp(ebpf, interfaces, XdpFlags::DRV_MODE),
+        IngressHook::XdpGeneric => attach_xdp(ebpf, interfaces, XdpFlags::SKB_MODE),
+    };
+
+    for (program, attach_type, interfaces) in [
+        ("tc_ingress", TcAttachType::Ingress, tc_ingress.as_slice()),
+        ("tc_egress", TcAttachType::Egress, interfaces),
+    ] {
+        let probe: &mut SchedClassifier = ebpf
+            .program_mut(program)
+            .unwrap_or_else(|| panic!("failed to get program {program}"))
+            .try_into()
+            .unwrap();
+        _ = probe.load().inspect_err(|err| warn!("{}", err));
+
+        for interface in interfaces {
+            // fails if the clsact qdisc already exists
+            _ = tc::qdisc_add_clsact(&interface.name);
+
+            match probe.attach(&interface.name, attach_type) {
+                Ok(_) => info!("attached {} to {}", program, interface.name),
+                Err(err) => warn!(
+                    "failed to attach {} to {}: {}",
+                    program, interface.name, err
+                ),
+            }
+        }
+    }
+
+    attach_cgroup(ebpf);
+}
+
+/// Attaches the XDP program, returning the interfaces it could not be
+/// attached to so their ingress traffic is captured by TC instead.
+fn attach_xdp(ebpf: &mut Ebpf, interfaces: &[Interface], flags: XdpFlags) -> Vec<Interface> {
+    let probe: &mut Xdp = ebpf
+        .program_mut("xdp_ingress")
+        .expect("failed to get program xdp_ingress")
+        .try_into()
+        .unwrap();
+    _ = probe.load().inspect_err(|err| warn!("{}", err));
+
+    interfaces
+        .iter()
+        .filter(|interface| match probe.attach(&interface.name, flags) {
+            Ok(_) => {
+                info!("attached xdp_ingress to {}", interface.name);
+                false
+            }
+            Err(err) => {
+                warn!(
+                    "failed to attach xdp_ingress to {}, falling back to tc_ingress: {}",
+                    interface.name, err
+                );
+                true
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+/// Without the cgroup programs flows are reported without a process.
+fn attach_cgroup(ebpf: &mut Ebpf) {
+    let cgroup = match File::open(CGROUP_ROOT) {
+        Ok(cgroup) => cgroup,
+        Err(err) => {
+            warn!("failed to open {}: {}", CGROUP_ROOT, err);
+            return;
+        }
+    };
+
+    for program in ["sock_create", "post_bind4", "post_bind6"] {
+        let probe: &mut CgroupSock = ebpf
+            .program_mut(program)
+            .unwrap_or_else(|| panic!("failed to get program {program}"))
+            .try_into()
+            .unwrap();
+        _ = probe.load().inspect_err(|err| warn!("{}", err));
+
+        match probe.attach(&cgroup, CgroupAttachMode::Single) {
+            Ok(_) => info!("attached {} to {}", program, CGROUP_ROOT),
+            Err(err) => warn!("failed to attach {} to {}: {}", program, CGROUP_ROOT, err),
+        }
+    }
+
+    for (program, attach_type) in [
+        ("cgroup_skb_ingress", CgroupSkbAttachType::Ingress),
+        ("cgroup_skb_egress", CgroupSkbAttachType::Egress),
+    ] {
+        let probe: &mut CgroupSkb = ebpf
+            .program_mut(program)
+            .unwrap_or_else(|| panic!("failed to get program {program}"))
+            .try_into()
+            .unwrap();
+        _ = probe.load().inspect_err(|err| warn!("{}", err));
+
+        match probe.attach(&cgroup, attach_type, CgroupAttachMode::Single) {
+            Ok(_) => info!("attached {} to {}", program, CGROUP_ROOT),
+            Err(err) => warn!("failed to attach {} to {}: {}", program, CGROUP_ROOT, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{glob_match, match_interfaces};
+
+    fn names() -> Vec<String> {
+        ["wg1", "lo", "eth1", "wg0", "eth0"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    /// `ethN` has index 10 + N, every other interface 1.
+    fn index_of(name: &str) -> io::Result<u32> {
+        Ok(name
+            .strip_prefix("eth")
+            .map_or(1, |n| 10 + n.parse::<u32>().unwrap()))
+    }
+
+    #[test]
+    fn literal_names_match_exactly() {
+        assert!(glob_match("eth0", "eth0"));
+        assert!(!glob_match("eth0", "eth01"));
+        assert!(!glob_match("eth0", "eth"));
+        assert!(!glob_match("eth0", "veth0"));
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        assert!(glob_match("wg*", "wg"));
+        assert!(glob_match("wg*", "wg0"));
+        assert!(glob_match("*", "bond0"));
+        assert!(glob_match("veth*@*", "veth1a2b@if3"));
+        assert!(glob_match("eth?", "eth1"));
+        assert!(!glob_match("eth?", "eth"));
+        assert!(!glob_match("wg*", "eth0"));
+    }
+
+    #[test]
+    fn pattern_without_match() {
+        let names = ["lo", "eth0", "docker0"];
+        assert!(!names.iter().any(|name| glob_match("wlan*", name)));
+        assert!(!glob_match("", "lo"));
+    }
+
+    #[test]
+    fn resolves_patterns_in_order_without_duplicates() {
+        let interfaces =
+            match_interfaces(&patterns(&["eth1", "eth*", "lo"]), names(), index_of).unwrap();
+
+        let resolved: Vec<_> = interfaces
+            .iter()
+            .map(|interface| (interface.name.as_str(), interface.index))
+            .collect();
+        assert_eq!(resolved, [("eth1", 11), ("eth0", 10), ("lo", 1)]);
+    }
+
+    #[test]
+    fn rejects_patterns_matching_nothing() {
+        let err = match_interfaces(&patterns(&["eth0", "wlan*"]), names(), index_of).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("wlan*"));
+    }
+
+    #[test]
+    fn fails_if_an_interface_vanished() {
+        let err = match_interfaces(&patterns(&["wg*"]), names(), |_| {
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        })
+        .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    io::{self, ErrorKind, Read},
+    net::IpAddr,
+    time::SystemTime,
+};
+
+use net::{eth::EthHdr, parse::parse};
+use palantir_ebpf_common::{Direction, RawEvent};
+use tracing::trace;
+
+use super::{EventSource, Pacer};
+
+const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Frames are never captured longer than this, whatever the snapshot length
+/// of the file claims.
+const MAX_FRAME_LEN: usize = 256 * 1024;
+
+/// Reads Ethernet frames from a classic libpcap file. Direction is derived
+/// from whether the source address is one of `local_addrs`.
+pub struct PcapSource<R>
+where
+    R: Read,
+{
+    reader: R,
+    big_endian: bool,
+    nanos: bool,
+    snaplen: usize,
+    local_addrs: Vec<IpAddr>,
+    pacer: Pacer,
+    boot_time: SystemTime,
+}
+
+impl<R> PcapSource<R>
+where
+    R: Read,
+{
+    pub fn new(mut reader: R, local_addrs: Vec<IpAddr>, speed: f64) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+
+        let magic = [header[0], header[1], header[2], header[3]];
+        let (big_endian, nanos) = if u32::from_le_bytes(magic) == MAGIC_MICROS {
+            (false, false)
+        } else if u32::from_be_bytes(magic) == MAGIC_MICROS {
+            (true, false)
+        } else if u32::from_le_bytes(magic) == MAGIC_NANOS {
+            (false, true)
+        } else if u32::from_be_bytes(magic) == MAGIC_NANOS {
+            (true, true)
+        } else {
+            return Err(io::Error::new(ErrorKind::InvalidData, "not a pcap file"));
+        };
+
+        let mut source = Self {
+            reader,
+            big_endian,
+            nanos,
+            snaplen: MAX_FRAME_LEN,
+            local_addrs,
+            pacer: Pacer::new(speed),
+            boot_time: SystemTime::now(),
+        };
+
+        source.snaplen = match source.u32(&header[16..20]) as usize {
+            0 => MAX_FRAME_LEN,
+            snaplen => snaplen.min(MAX_FRAME_LEN),
+        };
+
+        let link_type = source.u32(&header[20..24]);
+        if link_type != LINKTYPE_ETHERNET {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported pcap link type {link_type}"),
+            ));
+        }
+
+        Ok(source)
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Returns the timestamp, the original length of the frame and the
+    /// captured, possibly truncated frame.
+    fn read_frame(&mut self) -> io::Result<Option<(u64, u64, Vec<u8>)>> {
+        let mut header = [0u8; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        let secs = self.u32(&header[0..4]) as u64;
+        let frac = self.u32(&header[4..8]) as u64;
+        let incl_len = self.u32(&header[8..12]) as usize;
+        let orig_len = self.u32(&header[12..16]) as u64;
+
+        if incl_len > self.snaplen {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("pcap frame of {incl_len} bytes exceeds the snapshot length"),
+            ));
+        }
+
+        let ts_ns = secs * 1_000_000_000 + if self.nanos { frac } else { frac * 1_000 };
+
+        let mut frame = vec![0u8; incl_len];
+        self.reader.read_exact(&mut frame)?;
+
+        Ok(Some((ts_ns, orig_len, frame)))
+    }
+}
+
+impl<R> EventSource for PcapSource<R>
+where
+    R: Read + Send,
+{
+    fn boot_time(&self) -> SystemTime {
+        self.boot_time
+    }
+
+    async fn next(&mut self) -> io::Result<Option<RawEvent>> {
+        loop {
+            let Some((ts_ns, orig_len, frame)) = self.read_frame()? else {
+                return Ok(None);
+            };
+
+            let Some(mut raw_event) = parse_frame(&frame, orig_len, &self.local_addrs) else {
+                trace!("skipping unsupported frame of {} bytes", frame.len());
+                continue;
+            };
+
+            raw_event.ts_offset_ns = self.pacer.pace(ts_ns).await;
+
+            return Ok(Some(raw_event));
+        }
+    }
+}
+
+fn parse_frame(frame: &[u8], orig_len: u64, local_addrs: &[IpAddr]) -> Option<RawEvent> {
+    let packet = parse(frame).ok()?;
+
+    let direction = match local_addrs.contains(&packet.src_addr) {
+        true => Direction::Egress,
+        false => Direction::Ingress,
+    };
+
+    Some(RawEvent {
+        process: None,
+        ifindex: 0,
+        src_addr: packet.src_addr,
+        dst_addr: packet.dst_addr,
+        src_port: packet.src_port,
+        dst_port: packet.dst_port,
+        ts_offset_ns: 0,
+        proto: packet.proto,
+        fragment: packet.fragment.is_fragment(),
+        last_fragment: packet.fragment.is_last(),
+        direction,
+        packets: 1,
+        bytes: orig_len.saturating_sub(size_of::<EthHdr>() as u64),
+        wire_bytes: orig_len,
+        sample_rate: 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::ErrorKind,
+        net::{IpAddr, Ipv4Addr},
+    };
+
+    use net::ip::IpProto;
+    use palantir_ebpf_common::Direction;
+
+    use super::{LINKTYPE_ETHERNET, MAGIC_MICROS, MAGIC_NANOS, PcapSource};
+    use crate::source::EventSource;
+
+    const LOCAL: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const SPEED: f64 = 1000.0;
+
+    /// 192.0.2.1:50000 -> 1.1.1.1:443, TCP SYN.
+    const FRAME: &[u8] = &[
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x02, 0x08,
+        0x00, // eth
+        0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00, // ipv4
+        0xc0, 0x00, 0x02, 0x01, 0x01, 0x01, 0x01, 0x01, //
+        0xc3, 0x50, 0x01, 0xbb, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // tcp
+        0x50, 0x02, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// A pcap file of `frames` given as seconds, fraction and original
+    /// length, every one captured as `FRAME`.
+    fn pcap(big_endian: bool, nanos: bool, frames: &[(u32, u32, u32)]) -> Vec<u8> {
+        let u32 = |value: u32| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        let u16 = |value: u16| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+
+        let mut file = Vec::new();
+        file.extend(u32(if nanos { MAGIC_NANOS } else { MAGIC_MICROS }));
+        file.extend(u16(2));
+        file.extend(u16(4));
+        file.extend([0; 8]);
+        file.extend(u32(65535));
+        file.extend(u32(LINKTYPE_ETHERNET));
+
+        for (secs, frac, orig_len) in frames {
+            file.extend(u32(*secs));
+            file.extend(u32(*frac));
+            file.extend(u32(FRAME.len() as u32));
+            file.extend(u32(*orig_len));
+            file.extend(FRAME);
+        }
+
+        file
+    }
+
+    #[tokio::test]
+    async fn reads_every_header_variant() {
+        for (big_endian, nanos) in [(false, false), (true, false), (false, true), (true, true)] {
+            let half = if nanos { 500_000_000 } else { 500_000 };
+            let file = pcap(big_endian, nanos, &[(10, 0, 1514), (11, half, 54)]);
+            let mut source = PcapSource::new(&file[..], vec![LOCAL], SPEED).unwrap();
+
+            let first = source.next().await.unwrap().unwrap();
+            assert_eq!(first.src_addr, LOCAL);
+            assert_eq!(first.dst_addr, IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)));
+            assert_eq!((first.src_port, first.dst_port), (50000, 443));
+            assert_eq!(first.proto, IpProto::TCP);
+            assert_eq!(first.direction, Direction::Egress);
+            assert_eq!(first.wire_bytes, 1514);
+            assert_eq!(first.bytes, 1500);
+            assert_eq!(first.ts_offset_ns, 0);
+
+            // 1.5 s apart, played back a thousand times faster
+            let second = source.next().await.unwrap().unwrap();
+            assert_eq!(second.ts_offset_ns, 1_500_000);
+            assert_eq!(second.wire_bytes, 54);
+
+            assert!(source.next().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_frames() {
+        let file = pcap(false, false, &[(10, 0, 54)]);
+        let mut source = PcapSource::new(&file[..file.len() - 1], vec![LOCAL], SPEED).unwrap();
+
+        let err = source.next().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn rejects_frames_beyond_the_snapshot_length() {
+        let mut file = pcap(false, false, &[(10, 0, 54)]);
+        file[24 + 8..24 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut source = PcapSource::new(&file[..], vec![LOCAL], SPEED).unwrap();
+
+        let err = source.next().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut file = pcap(false, false, &[]);
+        file[20..24].copy_from_slice(&101u32.to_le_bytes());
+        let err = PcapSource::new(&file[..], vec![LOCAL], SPEED)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err = PcapSource::new(&[0u8; 24][..], vec![LOCAL], SPEED)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant, SystemTime},
+};
+
+use palantir_ebpf_common::RawEvent;
+use tracing::warn;
+
+use super::{EventSource, Interface};
+use crate::capture::CaptureWriter;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Passes events of the inner source through unchanged while recording them
+/// to a capture file.
+pub struct Recorder<S, W>
+where
+    W: Write,
+{
+    source: S,
+    writer: Option<CaptureWriter<W>>,
+    last_flush: Instant,
+}
+
+impl<S, W> Recorder<S, W>
+where
+    S: EventSource,
+    W: Write,
+{
+    pub fn new(source: S, writer: W) -> io::Result<Self> {
+        let writer = CaptureWriter::new(writer, source.boot_time(), &source.interfaces())?;
+
+        Ok(Self {
+            source,
+            writer: Some(writer),
+            last_flush: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, raw_event: &RawEvent) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+
+        let mut result = writer.write(raw_event);
+        if result.is_ok() && self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            result = writer.flush();
+            self.last_flush = Instant::now();
+        }
+
+        if let Err(err) = result {
+            warn!("failed to record event, stopping capture: {}", err);
+            self.writer = None;
+        }
+    }
+}
+
+impl<S, W> EventSource for Recorder<S, W>
+where
+    S: EventSource + Send,
+    W: Write + Send,
+{
+    fn boot_time(&self) -> SystemTime {
+        self.source.boot_time()
+    }
+
+    fn interfaces(&self) -> Vec<Interface> {
+        self.source.interfaces()
+    }
+
+    async fn next(&mut self) -> io::Result<Option<RawEvent>> {
+        let raw_event = self.source.next().await?;
+
+        match &raw_event {
+            Some(raw_event) => self.record(raw_event),
+            None => {
+                if let Some(writer) = &mut self.writer {
+                    writer.flush()?;
+                }
+            }
+        }
+
+        Ok(raw_event)
+    }
+}
@@ -0,0 +1,109 @@
+use std::{io, time::SystemTime};
+
+use palantir_ebpf_common::RawEvent;
+
+use super::{EventSource, Interface, Pacer};
+use crate::capture::CaptureReader;
+
+/// Replays a capture file. Recorded timestamps are rebased onto the start of
+/// the replay so peers show up as active.
+pub struct ReplaySource<R>
+where
+    R: io::Read,
+{
+    reader: CaptureReader<R>,
+    pacer: Pacer,
+    boot_time: SystemTime,
+}
+
+impl<R> ReplaySource<R>
+where
+    R: io::Read,
+{
+    pub fn new(reader: CaptureReader<R>, speed: f64) -> Self {
+        Self {
+            reader,
+            pacer: Pacer::new(speed),
+            boot_time: SystemTime::now(),
+        }
+    }
+}
+
+impl<R> EventSource for ReplaySource<R>
+where
+    R: io::Read + Send,
+{
+    fn boot_time(&self) -> SystemTime {
+        self.boot_time
+    }
+
+    fn interfaces(&self) -> Vec<Interface> {
+        self.reader.interfaces().to_vec()
+    }
+
+    async fn next(&mut self) -> io::Result<Option<RawEvent>> {
+        let Some(mut raw_event) = self.reader.read()? else {
+            return Ok(None);
+        };
+
+        raw_event.ts_offset_ns = self.pacer.pace(raw_event.ts_offset_ns).await;
+
+        Ok(Some(raw_event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, SystemTime},
+    };
+
+    use palantir_ebpf_common::{Direction, RawEvent};
+
+    use super::ReplaySource;
+    use crate::{
+        capture::{CaptureReader, CaptureWriter},
+        fixtures::{self, LOCAL},
+        source::{EventSource, Interface},
+    };
+
+    #[tokio::test]
+    async fn replays_rebased_and_scaled() {
+        let interfaces = vec![Interface {
+            index: 2,
+            name: "eth0".to_string(),
+        }];
+        let remote = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+        let boot_time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut file = Vec::new();
+        {
+            let mut writer = CaptureWriter::new(&mut file, boot_time, &interfaces).unwrap();
+            for (ts_offset_ns, wire_bytes) in [(5_000_000_000, 100), (7_000_000_000, 200)] {
+                let event = RawEvent {
+                    ifindex: 2,
+                    ts_offset_ns,
+                    ..fixtures::event(remote, LOCAL, Direction::Ingress, wire_bytes)
+                };
+                writer.write(&event).unwrap();
+            }
+            writer.flush().unwrap();
+        }
+
+        let reader = CaptureReader::new(&file[..]).unwrap();
+        let mut source = ReplaySource::new(reader, 1000.0);
+        assert_eq!(source.interfaces(), interfaces);
+
+        let first = source.next().await.unwrap().unwrap();
+        assert_eq!(first.ts_offset_ns, 0);
+        assert_eq!((first.src_addr, first.wire_bytes), (remote, 100));
+
+        // 2 s apart, played back a thousand times faster
+        let second = source.next().await.unwrap().unwrap();
+        assert_eq!(second.ts_offset_ns, 2_000_000);
+        assert_eq!(second.wire_bytes, 200);
+
+        assert!(source.next().await.unwrap().is_none());
+    }
+}
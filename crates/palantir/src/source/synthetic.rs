@@ -0,0 +1,174 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use net::ip::IpProto;
+use palantir_ebpf_common::{Direction, ProcessInfo, RawEvent};
+use tokio::time::{Interval, MissedTickBehavior};
+
+use super::EventSource;
+
+const PEER_COUNT: usize = 64;
+
+/// Pretend owners of the generated traffic, by peer port.
+const PROCESSES: [(u16, u32, &str); 3] = [
+    (53, 612, "systemd-resolve"),
+    (80, 4711, "curl"),
+    (443, 2342, "firefox"),
+];
+
+/// Generates a steady stream of TCP and UDP traffic between `local_addr` and a
+/// fixed pool of random public IPv4 peers.
+pub struct SyntheticSource {
+    local_addr: IpAddr,
+    peers: Vec<IpAddr>,
+    rng: XorShift,
+    interval: Interval,
+    start: Instant,
+    boot_time: SystemTime,
+}
+
+impl SyntheticSource {
+    pub fn new(local_addr: IpAddr, events_per_sec: f64) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        let mut rng = XorShift(seed | 1);
+
+        let peers = (0..PEER_COUNT)
+            .map(|_| {
+                loop {
+                    let addr = Ipv4Addr::from_bits(rng.next() as u32);
+                    if addr.is_global() {
+                        break IpAddr::V4(addr);
+                    }
+                }
+            })
+            .collect();
+
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / events_per_sec));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Burst);
+
+        Self {
+            local_addr,
+            peers,
+            rng,
+            interval,
+            start: Instant::now(),
+            boot_time: SystemTime::now(),
+        }
+    }
+}
+
+impl EventSource for SyntheticSource {
+    fn boot_time(&self) -> SystemTime {
+        self.boot_time
+    }
+
+    async fn next(&mut self) -> io::Result<Option<RawEvent>> {
+        self.interval.tick().await;
+
+        let rand = self.rng.next();
+        let peer_addr = self.peers[rand as usize % self.peers.len()];
+        let local_port = (rand >> 16) as u16 | 0x8000;
+        let (proto, peer_port) = match (rand >> 32) % 4 {
+            0 => (IpProto::UDP, 53),
+            1 => (IpProto::UDP, 443),
+            2 => (IpProto::TCP, 80),
+            _ => (IpProto::TCP, 443),
+        };
+        let bytes = 64 + (rand >> 40) % 1436;
+
+        let (direction, src_addr, dst_addr, src_port, dst_port) = if rand >> 63 == 0 {
+            (
+                Direction::Egress,
+                self.local_addr,
+                peer_addr,
+                local_port,
+                peer_port,
+            )
+        } else {
+            (
+                Direction::Ingress,
+                peer_addr,
+                self.local_addr,
+                peer_port,
+                local_port,
+            )
+        };
+
+        Ok(Some(RawEvent {
+            process: process(peer_port),
+            ifindex: 0,
+            src_addr,
+            dst_addr,
+            src_port,
+            dst_port,
+            ts_offset_ns: self.start.elapsed().as_nanos() as u64,
+            proto,
+            fragment: false,
+            last_fragment: false,
+            direction,
+            packets: 1,
+            bytes,
+            wire_bytes: bytes + 14,
+            sample_rate: 1,
+        }))
+    }
+}
+
+fn process(peer_port: u16) -> Option<ProcessInfo> {
+    let (_, tgid, name) = PROCESSES.iter().find(|(port, ..)| *port == peer_port)?;
+
+    let mut comm = [0u8; 16];
+    comm[..name.len().min(15)].copy_from_slice(&name.as_bytes()[..name.len().min(15)]);
+
+    Some(ProcessInfo {
+        pid: *tgid,
+        tgid: *tgid,
+        uid: 1000,
+        _pad: 0,
+        cgroup_id: 0,
+        comm,
+    })
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use palantir_ebpf_common::Direction;
+
+    use super::SyntheticSource;
+    use crate::{fixtures::LOCAL, source::EventSource};
+
+    #[tokio::test]
+    async fn generates_traffic_with_public_peers() {
+        let mut source = SyntheticSource::new(LOCAL, 10_000.0);
+
+        for _ in 0..100 {
+            let event = source.next().await.unwrap().unwrap();
+
+            let (local_addr, peer_addr) = match event.direction {
+                Direction::Egress => (event.src_addr, event.dst_addr),
+                Direction::Ingress => (event.dst_addr, event.src_addr),
+            };
+            assert_eq!(local_addr, LOCAL);
+            assert!(peer_addr.is_global());
+            assert_eq!(event.wire_bytes, event.bytes + 14);
+            assert!((64..1500).contains(&event.bytes));
+        }
+    }
+}
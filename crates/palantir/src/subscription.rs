@@ -0,0 +1,368 @@
+use std::{fmt, net::IpAddr, ops::RangeInclusive, str::FromStr};
+
+use net::ip::IpProto;
+use palantir_ebpf_common::Direction;
+use serde::Deserialize;
+
+use crate::{cidr::Cidr, event::Event};
+
+/// Query parameters of `/events`, lists are comma separated. Everything is
+/// delivered if none are given.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EventQuery {
+    /// `peer`, `packet`, `packet_batch`, `peer_removed` and `capture`.
+    pub kind: Option<String>,
+    /// IANA keywords like `tcp` or protocol numbers.
+    pub proto: Option<String>,
+    /// Ports or ranges like `8000-8999`, matching either port of a packet or
+    /// the service ports of a batch.
+    pub port: Option<String>,
+    /// Networks matching either address of a packet or the peer address.
+    pub cidr: Option<String>,
+    /// Country codes of the remote peer.
+    pub country: Option<String>,
+    /// `ingress` or `egress`.
+    pub direction: Option<Direction>,
+    /// Minimum on-wire bytes of a packet event.
+    pub min_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Peer,
+    Packet,
+    PacketBatch,
+    PeerRemoved,
+    Lagged,
+    Capture,
+}
+
+impl EventKind {
+    pub fn of(event: &Event) -> Self {
+        match event {
+            Event::Peer(_) => Self::Peer,
+            Event::Packet(_) => Self::Packet,
+            Event::PacketBatch(_) => Self::PacketBatch,
+            Event::PeerRemoved(_) => Self::PeerRemoved,
+            Event::Lagged(_) => Self::Lagged,
+            Event::Capture(_) => Self::Capture,
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "peer" => Ok(Self::Peer),
+            "packet" => Ok(Self::Packet),
+            "packet_batch" => Ok(Self::PacketBatch),
+            "peer_removed" => Ok(Self::PeerRemoved),
+            "capture" => Ok(Self::Capture),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A query parameter of `/events` that could not be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFilter {
+    pub param: &'static str,
+    pub value: String,
+}
+
+impl fmt::Display for InvalidFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {}: {:?}", self.param, self.value)
+    }
+}
+
+impl std::error::Error for InvalidFilter {}
+
+/// The events a subscriber asked for, compiled from an [`EventQuery`].
+///
+/// Empty lists match everything. Protocols, ports, directions and sizes are
+/// properties of packets only, peer events pass them. Removed peers only
+/// carry an address, so they are checked against the networks alone. Lag
+/// notices are always delivered, capture counters only by kind.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    kinds: Vec<EventKind>,
+    protos: Vec<IpProto>,
+    ports: Vec<RangeInclusive<u16>>,
+    cidrs: Vec<Cidr>,
+    countries: Vec<String>,
+    direction: Option<Direction>,
+    min_bytes: u64,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        if let Event::Lagged(_) = event {
+            return true;
+        }
+
+        if !self.kinds.is_empty() && !self.kinds.contains(&EventKind::of(event)) {
+            return false;
+        }
+
+        match event {
+            Event::Peer(peer) => {
+                self.matches_addrs(&[peer.addr]) && self.matches_country(&peer.info.country_code)
+            }
+            Event::PeerRemoved(removed) => self.matches_addrs(&[removed.addr]),
+            Event::Lagged(_) | Event::Capture(_) => true,
+            Event::Packet(packet) => {
+                (self.protos.is_empty() || self.protos.contains(&packet.proto))
+                    && (self.ports.is_empty()
+                        || [packet.src_port, packet.dst_port]
+                            .into_iter()
+                            .flatten()
+                            .any(|port| self.ports.iter().any(|range| range.contains(&port))))
+                    && self.matches_addrs(&[packet.src_addr, packet.dst_addr])
+                    && self.matches_country(&packet.country_code)
+                    && self
+                        .direction
+                        .is_none_or(|direction| direction == packet.direction)
+                    && packet.wire_bytes >= self.min_bytes
+            }
+            Event::PacketBatch(batch) => {
+                (self.protos.is_empty()
+                    || self
+                        .protos
+                        .iter()
+                        .any(|proto| batch.protocols.contains_key(&proto.to_string())))
+                    && (self.ports.is_empty()
+                        || batch
+                            .ports
+                            .iter()
+                            .any(|port| self.ports.iter().any(|range| range.contains(port))))
+                    && self.matches_addrs(&[batch.local_addr, batch.peer_addr])
+                    && self.matches_country(&batch.country_code)
+                    && self.direction.is_none_or(|direction| match direction {
+                        Direction::Ingress => batch.ingress_bytes > 0,
+                        Direction::Egress => batch.egress_bytes > 0,
+                    })
+                    && batch.ingress_bytes + batch.egress_bytes >= self.min_bytes
+            }
+        }
+    }
+
+    fn matches_addrs(&self, addrs: &[IpAddr]) -> bool {
+        self.cidrs.is_empty()
+            || addrs
+                .iter()
+                .any(|addr| self.cidrs.iter().any(|cidr| cidr.contains(addr)))
+    }
+
+    fn matches_country(&self, country_code: &str) -> bool {
+        self.countries.is_empty()
+            || self
+                .countries
+                .iter()
+                .any(|country| country.eq_ignore_ascii_case(country_code))
+    }
+}
+
+impl TryFrom<EventQuery> for EventFilter {
+    type Error = InvalidFilter;
+
+    fn try_from(query: EventQuery) -> Result<Self, Self::Error> {
+        Ok(Self {
+            kinds: parse_list("kind", &query.kind, |s| s.parse().ok())?,
+            protos: parse_list("proto", &query.proto, |s| {
+                IpProto::from_name(s).or_else(|| s.parse::<u8>().ok().map(IpProto))
+            })?,
+            ports: parse_list("port", &query.port, |s| match s.split_once('-') {
+                Some((start, end)) => Some(start.parse().ok()?..=end.parse().ok()?),
+                None => s.parse().ok().map(|port| port..=port),
+            })?,
+            cidrs: parse_list("cidr", &query.cidr, |s| s.parse().ok())?,
+            countries: parse_list("country", &query.country, |s| Some(s.to_string()))?,
+            direction: query.direction,
+            min_bytes: query.min_bytes.unwrap_or(0),
+        })
+    }
+}
+
+fn parse_list<T>(
+    param: &'static str,
+    list: &Option<String>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<T>, InvalidFilter> {
+    let Some(list) = list else {
+        return Ok(Vec::new());
+    };
+
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            parse(item).ok_or_else(|| InvalidFilter {
+                param,
+                value: item.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::SystemTime,
+    };
+
+    use net::ip::IpProto;
+    use palantir_ebpf_common::Direction;
+
+    use super::{EventFilter, EventQuery, InvalidFilter};
+    use crate::{
+        batch::PacketBatcher,
+        event::{Event, Packet, PeerRemoved, RemovalReason},
+        fixtures::{self, LOCAL},
+    };
+    const REMOTE: IpAddr = IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1));
+
+    fn packet(proto: IpProto, dst_port: u16, wire_bytes: u64) -> Event {
+        Event::Packet(Packet {
+            proto,
+            src_addr: LOCAL,
+            dst_addr: REMOTE,
+            src_port: Some(50000),
+            dst_port: Some(dst_port),
+            direction: Direction::Egress,
+            country_code: "AU".to_string(),
+            packets: 1,
+            bytes: wire_bytes - 14,
+            wire_bytes,
+            timestamp: SystemTime::now(),
+            interface: None,
+            icmp: None,
+            process: None,
+            workload: None,
+            capture_sample_rate: 1,
+            sample_rate: 1,
+        })
+    }
+
+    fn peer(country_code: &str) -> Event {
+        Event::Peer(fixtures::peer(REMOTE, country_code))
+    }
+
+    fn filter(query: EventQuery) -> EventFilter {
+        EventFilter::try_from(query).unwrap()
+    }
+
+    #[test]
+    fn matches_packets() {
+        let https = packet(IpProto::TCP, 443, 1514);
+        let dns = packet(IpProto::UDP, 53, 80);
+
+        assert!(filter(EventQuery::default()).matches(&https));
+
+        let query = EventQuery {
+            proto: Some("tcp,17".to_string()),
+            port: Some("53, 400-500".to_string()),
+            ..EventQuery::default()
+        };
+        assert!(filter(query.clone()).matches(&https));
+        assert!(filter(query).matches(&dns));
+
+        let query = EventQuery {
+            cidr: Some("1.1.1.0/24".to_string()),
+            country: Some("au".to_string()),
+            direction: Some(Direction::Egress),
+            min_bytes: Some(100),
+            ..EventQuery::default()
+        };
+        assert!(filter(query.clone()).matches(&https));
+        assert!(!filter(query).matches(&dns));
+
+        let query = EventQuery {
+            direction: Some(Direction::Ingress),
+            ..EventQuery::default()
+        };
+        assert!(!filter(query).matches(&https));
+    }
+
+    #[test]
+    fn matches_peers_on_their_properties_only() {
+        let removed = Event::PeerRemoved(PeerRemoved {
+            addr: REMOTE,
+            reason: RemovalReason::Idle,
+        });
+
+        let query = EventQuery {
+            port: Some("443".to_string()),
+            country: Some("NZ".to_string()),
+            ..EventQuery::default()
+        };
+        assert!(!filter(query.clone()).matches(&peer("AU")));
+        assert!(filter(query.clone()).matches(&peer("NZ")));
+        assert!(filter(query).matches(&removed));
+
+        let query = EventQuery {
+            kind: Some("peer,peer_removed".to_string()),
+            cidr: Some("10.0.0.0/8".to_string()),
+            ..EventQuery::default()
+        };
+        assert!(!filter(query.clone()).matches(&peer("AU")));
+        assert!(!filter(query).matches(&packet(IpProto::TCP, 443, 1514)));
+    }
+
+    #[test]
+    fn matches_batches() {
+        let mut batcher = PacketBatcher::new();
+        for event in [
+            packet(IpProto::TCP, 443, 1514),
+            packet(IpProto::UDP, 53, 80),
+        ] {
+            let Event::Packet(packet) = event else {
+                unreachable!()
+            };
+            batcher.add(&packet);
+        }
+        let batch = Event::PacketBatch(batcher.drain().remove(0));
+
+        let query = EventQuery {
+            kind: Some("packet_batch".to_string()),
+            proto: Some("udp".to_string()),
+            port: Some("53".to_string()),
+            direction: Some(Direction::Egress),
+            min_bytes: Some(1594),
+            ..EventQuery::default()
+        };
+        assert!(filter(query).matches(&batch));
+
+        let query = EventQuery {
+            direction: Some(Direction::Ingress),
+            ..EventQuery::default()
+        };
+        assert!(!filter(query).matches(&batch));
+
+        let query = EventQuery {
+            kind: Some("packet".to_string()),
+            ..EventQuery::default()
+        };
+        assert!(!filter(query).matches(&batch));
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        let query = EventQuery {
+            port: Some("443,https".to_string()),
+            ..EventQuery::default()
+        };
+
+        assert_eq!(
+            EventFilter::try_from(query).unwrap_err(),
+            InvalidFilter {
+                param: "port",
+                value: "https".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,544 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// The cgroup tree is walked again for unknown cgroup ids at most this often.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Prefixes of the scopes container runtimes create with the systemd cgroup
+/// driver, e.g. `docker-<id>.scope`.
+const SCOPE_PREFIXES: [&str; 5] = [
+    "docker-",
+    "cri-containerd-",
+    "crio-",
+    "libpod-",
+    "containerd-",
+];
+
+/// A container, pod or service that traffic is attributed to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Workload {
+    pub name: String,
+    /// Kubernetes namespace or Compose project.
+    pub namespace: Option<String>,
+    pub container_id: Option<String>,
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.namespace {
+            Some(namespace) => write!(f, "{}/{}", namespace, self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// A cgroup v2 directory, identified by the id the eBPF program reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cgroup {
+    pub id: u64,
+    /// Path relative to the cgroup root, starting with `/`.
+    pub path: String,
+    pub container_id: Option<String>,
+    pub pod_uid: Option<String>,
+}
+
+impl Cgroup {
+    pub fn new(id: u64, path: String) -> Self {
+        let container_id = path.rsplit('/').next().and_then(container_id);
+        let pod_uid = path.split('/').find_map(pod_uid);
+
+        Self {
+            id,
+            path,
+            container_id,
+            pod_uid,
+        }
+    }
+}
+
+/// Finds the container id in the last component of a cgroup path, for both
+/// the systemd (`docker-<id>.scope`) and cgroupfs (`docker/<id>`) drivers.
+fn container_id(component: &str) -> Option<String> {
+    let id = component.strip_suffix(".scope").unwrap_or(component);
+    let id = SCOPE_PREFIXES
+        .iter()
+        .find_map(|prefix| id.strip_prefix(prefix))
+        .unwrap_or(id);
+
+    (id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())).then(|| id.to_string())
+}
+
+/// Finds the pod uid in `pod<uid>` or `kubepods-<qos>-pod<uid>.slice`, the
+/// systemd driver replaces the dashes of the uid with underscores.
+fn pod_uid(component: &str) -> Option<String> {
+    let component = component.strip_suffix(".slice").unwrap_or(component);
+    let (_, uid) = component.rsplit_once("pod")?;
+
+    (uid.len() == 36).then(|| uid.replace('_', "-"))
+}
+
+/// Source of container metadata, asked for every new cgroup a walk of the
+/// cgroup tree finds.
+pub trait MetadataProvider: Send + Sync {
+    fn lookup(&self, cgroup: &Cgroup) -> Option<Workload>;
+}
+
+/// Reads container names and Kubernetes labels from the state directory of
+/// the Docker daemon, usually `/var/lib/docker`.
+pub struct DockerProvider {
+    root: PathBuf,
+}
+
+impl DockerProvider {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerContainer {
+    name: String,
+    config: DockerConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerConfig {
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+impl MetadataProvider for DockerProvider {
+    fn lookup(&self, cgroup: &Cgroup) -> Option<Workload> {
+        let id = cgroup.container_id.as_ref()?;
+        let path = self.root.join("containers").join(id).join("config.v2.json");
+        let container: DockerContainer = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+        let label = |name: &str| container.config.labels.get(name).cloned();
+
+        let (name, namespace) = match label("io.kubernetes.pod.name") {
+            Some(pod) => (pod, label("io.kubernetes.pod.namespace")),
+            None => (
+                container.name.trim_start_matches('/').to_string(),
+                label("com.docker.compose.project"),
+            ),
+        };
+
+        Some(Workload {
+            name,
+            namespace,
+            container_id: Some(id.clone()),
+        })
+    }
+}
+
+/// A fixed mapping of cgroups to workloads read from a TOML file, for hosts
+/// without a supported runtime and for tests:
+///
+/// ```toml
+/// [[workload]]
+/// name = "checkout"
+/// namespace = "shop"
+/// pod_uid = "0f8ba5f3-5c1e-4a4b-9d0e-0c5e5b8f4a11"
+///
+/// [[workload]]
+/// name = "nginx"
+/// cgroup = "/system.slice/nginx.service"
+/// ```
+///
+/// An entry matches if any of `cgroup`, `container_id` or `pod_uid` match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileProvider {
+    #[serde(default, rename = "workload")]
+    entries: Vec<FileEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileEntry {
+    name: String,
+    namespace: Option<String>,
+    cgroup: Option<String>,
+    container_id: Option<String>,
+    pod_uid: Option<String>,
+}
+
+impl FileProvider {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        toml::from_str(contents).map_err(io::Error::other)
+    }
+}
+
+impl MetadataProvider for FileProvider {
+    fn lookup(&self, cgroup: &Cgroup) -> Option<Workload> {
+        let entry = self.entries.iter().find(|entry| {
+            entry.cgroup.as_ref() == Some(&cgroup.path)
+                || entry.container_id.is_some() && entry.container_id == cgroup.container_id
+                || entry.pod_uid.is_some() && entry.pod_uid == cgroup.pod_uid
+        })?;
+
+        Some(Workload {
+            name: entry.name.clone(),
+            namespace: entry.namespace.clone(),
+            container_id: cgroup.container_id.clone(),
+        })
+    }
+}
+
+/// Resolves the cgroup ids of flows to workloads. Ids are mapped to paths by
+/// walking cgroupfs, where the id of a cgroup is the inode of its directory,
+/// and the paths are handed to the providers in order.
+///
+/// Cgroups no provider knows are named after their container id, or their
+/// last path component such as `nginx.service`.
+///
+/// The walk and the provider lookups run on their own thread so neither
+/// stalls the pipeline, ids seen while it runs resolve once it is done.
+/// Cgroups found by an earlier walk are not looked up again.
+pub struct WorkloadResolver {
+    root: PathBuf,
+    providers: Vec<Arc<dyn MetadataProvider>>,
+    workloads: HashMap<u64, Workload>,
+    scanned: Option<Instant>,
+    scan: Option<JoinHandle<HashMap<u64, Workload>>>,
+}
+
+impl WorkloadResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            providers: Vec::new(),
+            workloads: HashMap::new(),
+            scanned: None,
+            scan: None,
+        }
+    }
+
+    /// Asks `provider` after the providers added before it.
+    pub fn with_provider<P>(mut self, provider: P) -> Self
+    where
+        P: MetadataProvider + 'static,
+    {
+        self.providers.push(Arc::new(provider));
+        self
+    }
+
+    pub fn resolve(&mut self, cgroup_id: u64) -> Option<&Workload> {
+        if self.scan.as_ref().is_some_and(JoinHandle::is_finished) {
+            match self.scan.take().map(JoinHandle::join) {
+                Some(Ok(workloads)) => self.workloads = workloads,
+                _ => warn!("failed to walk {}", self.root.display()),
+            }
+        }
+
+        let stale = self
+            .scanned
+            .is_none_or(|scanned| scanned.elapsed() >= RESCAN_INTERVAL);
+
+        // unknown ids are retried after the next scan
+        if !self.workloads.contains_key(&cgroup_id) && stale && self.scan.is_none() {
+            let root = self.root.clone();
+            let providers = self.providers.clone();
+            let known = self.workloads.clone();
+            self.scanned = Some(Instant::now());
+            self.scan = Some(thread::spawn(move || scan(&root, &providers, known)));
+        }
+
+        self.workloads.get(&cgroup_id)
+    }
+
+    /// Whether a walk of the cgroup tree is still running.
+    pub fn scanning(&self) -> bool {
+        self.scan.as_ref().is_some_and(|scan| !scan.is_finished())
+    }
+}
+
+/// Resolves the cgroups below `root` to workloads, taking those already
+/// resolved from `known`.
+fn scan(
+    root: &Path,
+    providers: &[Arc<dyn MetadataProvider>],
+    mut known: HashMap<u64, Workload>,
+) -> HashMap<u64, Workload> {
+    walk(root)
+        .into_iter()
+        .map(|(id, path)| {
+            let workload = known.remove(&id).unwrap_or_else(|| {
+                let cgroup = Cgroup::new(id, path);
+                providers
+                    .iter()
+                    .find_map(|provider| provider.lookup(&cgroup))
+                    .unwrap_or_else(|| fallback(&cgroup))
+            });
+
+            (id, workload)
+        })
+        .collect()
+}
+
+/// Maps the ids of the cgroups below `root` to their paths.
+fn walk(root: &Path) -> HashMap<u64, String> {
+    let mut paths = HashMap::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let metadata = match fs::metadata(&dir) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                warn!("failed to read {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+
+        let path = Path::new("/").join(dir.strip_prefix(root).unwrap_or(&dir));
+        paths.insert(metadata.ino(), path.to_string_lossy().into_owned());
+
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+
+        pending.extend(
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+                .map(|entry| entry.path()),
+        );
+    }
+
+    debug!("found {} cgroups in {}", paths.len(), root.display());
+    paths
+}
+
+fn fallback(cgroup: &Cgroup) -> Workload {
+    let name = match &cgroup.container_id {
+        Some(id) => id[..12].to_string(),
+        None => cgroup
+            .path
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    };
+
+    Workload {
+        name: if name.is_empty() {
+            "/".to_string()
+        } else {
+            name
+        },
+        namespace: None,
+        container_id: cgroup.container_id.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::MetadataExt,
+        path::{Path, PathBuf},
+        thread,
+        time::Duration,
+    };
+
+    use serde_json::json;
+
+    use super::{
+        Cgroup, DockerProvider, FileProvider, MetadataProvider, Workload, WorkloadResolver,
+    };
+
+    const CONTAINER_ID: &str = "4f1c3c2a9b8d7e6f5a4b3c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f";
+    const POD_UID: &str = "0f8ba5f3-5c1e-4a4b-9d0e-0c5e5b8f4a11";
+
+    /// A throwaway cgroup root, the ids are the inodes of its directories.
+    fn cgroup_root(name: &str, paths: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("palantir-{}-{}", name, std::process::id()));
+        _ = fs::remove_dir_all(&root);
+
+        for path in paths {
+            fs::create_dir_all(root.join(path.trim_start_matches('/'))).unwrap();
+        }
+
+        root
+    }
+
+    /// Stores the `config.v2.json` of a container in the Docker state
+    /// directory `root`.
+    fn docker_container(root: &Path, id: &str, name: &str, labels: &[(&str, &str)]) {
+        let dir = root.join("containers").join(id);
+        let labels: serde_json::Map<_, _> = labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), json!(value)))
+            .collect();
+        let config = json!({ "Name": name, "Config": { "Labels": labels } });
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.v2.json"), config.to_string()).unwrap();
+    }
+
+    /// Resolves `cgroup_id` once the walk it starts is done.
+    fn resolve(resolver: &mut WorkloadResolver, cgroup_id: u64) -> Option<String> {
+        if let Some(workload) = resolver.resolve(cgroup_id) {
+            return Some(workload.name.clone());
+        }
+
+        while resolver.scanning() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        resolver
+            .resolve(cgroup_id)
+            .map(|workload| workload.name.clone())
+    }
+
+    fn id(root: &Path, path: &str) -> u64 {
+        fs::metadata(root.join(path.trim_start_matches('/')))
+            .unwrap()
+            .ino()
+    }
+
+    #[test]
+    fn parses_container_ids_of_both_drivers() {
+        let systemd = Cgroup::new(
+            1,
+            format!(
+                "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod{}.slice/cri-containerd-{}.scope",
+                POD_UID.replace('-', "_"),
+                CONTAINER_ID
+            ),
+        );
+        assert_eq!(systemd.container_id.as_deref(), Some(CONTAINER_ID));
+        assert_eq!(systemd.pod_uid.as_deref(), Some(POD_UID));
+
+        let cgroupfs = Cgroup::new(2, format!("/docker/{CONTAINER_ID}"));
+        assert_eq!(cgroupfs.container_id.as_deref(), Some(CONTAINER_ID));
+        assert_eq!(cgroupfs.pod_uid, None);
+
+        let service = Cgroup::new(3, "/system.slice/nginx.service".to_string());
+        assert_eq!(service.container_id, None);
+        assert_eq!(service.pod_uid, None);
+    }
+
+    #[test]
+    fn file_provider_matches_any_selector() {
+        let provider = FileProvider::from_toml(&format!(
+            r#"
+            [[workload]]
+            name = "checkout"
+            namespace = "shop"
+            pod_uid = "{POD_UID}"
+
+            [[workload]]
+            name = "nginx"
+            cgroup = "/system.slice/nginx.service"
+            "#
+        ))
+        .unwrap();
+
+        let pod = Cgroup::new(
+            1,
+            format!("/kubepods/burstable/pod{POD_UID}/{CONTAINER_ID}"),
+        );
+        let workload = provider.lookup(&pod).unwrap();
+        assert_eq!(workload.to_string(), "shop/checkout");
+        assert_eq!(workload.container_id.as_deref(), Some(CONTAINER_ID));
+
+        let service = Cgroup::new(2, "/system.slice/nginx.service".to_string());
+        assert_eq!(provider.lookup(&service).unwrap().name, "nginx");
+
+        let other = Cgroup::new(3, "/system.slice/sshd.service".to_string());
+        assert!(provider.lookup(&other).is_none());
+    }
+
+    #[test]
+    fn docker_provider_reads_pods_and_compose_projects() {
+        let compose_id = format!("{:064x}", 1);
+        let root = cgroup_root("docker", &[]);
+        docker_container(
+            &root,
+            CONTAINER_ID,
+            "/k8s_app_checkout-6d4c_shop",
+            &[
+                ("io.kubernetes.pod.name", "checkout-6d4c"),
+                ("io.kubernetes.pod.namespace", "shop"),
+            ],
+        );
+        docker_container(
+            &root,
+            &compose_id,
+            "/shop-web-1",
+            &[("com.docker.compose.project", "shop")],
+        );
+        let provider = DockerProvider::new(&root);
+
+        let pod = Cgroup::new(1, format!("/kubepods/pod{POD_UID}/{CONTAINER_ID}"));
+        assert_eq!(
+            provider.lookup(&pod),
+            Some(Workload {
+                name: "checkout-6d4c".to_string(),
+                namespace: Some("shop".to_string()),
+                container_id: Some(CONTAINER_ID.to_string()),
+            })
+        );
+
+        let compose = Cgroup::new(2, format!("/system.slice/docker-{compose_id}.scope"));
+        assert_eq!(
+            provider.lookup(&compose),
+            Some(Workload {
+                name: "shop-web-1".to_string(),
+                namespace: Some("shop".to_string()),
+                container_id: Some(compose_id.clone()),
+            })
+        );
+
+        let gone = Cgroup::new(3, format!("/docker/{:064x}", 2));
+        assert!(provider.lookup(&gone).is_none());
+
+        let service = Cgroup::new(4, "/system.slice/nginx.service".to_string());
+        assert!(provider.lookup(&service).is_none());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn resolves_cgroup_ids_through_cgroupfs() {
+        let container = format!("/system.slice/docker-{CONTAINER_ID}.scope");
+        let root = cgroup_root("resolver", &["/system.slice/nginx.service", &container]);
+
+        let provider = FileProvider::from_toml(
+            r#"
+            [[workload]]
+            name = "web"
+            cgroup = "/system.slice/nginx.service"
+            "#,
+        )
+        .unwrap();
+        let mut resolver = WorkloadResolver::new(&root).with_provider(provider);
+
+        let nginx = id(&root, "/system.slice/nginx.service");
+        assert_eq!(resolve(&mut resolver, nginx).unwrap(), "web");
+
+        // unknown to the provider, named after the container
+        let docker = id(&root, &container);
+        assert_eq!(resolve(&mut resolver, docker).unwrap(), CONTAINER_ID[..12]);
+
+        assert!(resolve(&mut resolver, u64::MAX).is_none());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
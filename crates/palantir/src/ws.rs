@@ -0,0 +1,278 @@
+use std::sync::{Arc, atomic::Ordering};
+
+use axum::{
+    extract::{
+        Query, State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    event::{Event, Lagged},
+    history::{PeerHistory, query_history},
+    server::{AppState, HistoryQuery, snapshot},
+    subscription::{EventFilter, EventQuery},
+};
+
+/// Messages a WebSocket client sends to steer its session, tagged by `type`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Control {
+    /// Replaces the filter of live events and sends the matching peers.
+    Subscribe {
+        #[serde(default)]
+        filter: EventQuery,
+    },
+    /// Stops live events until the next `subscribe`.
+    Unsubscribe,
+    /// Drops live events until `resume`.
+    Pause,
+    /// Continues live events after sending the matching peers again.
+    Resume,
+    /// Sends the matching peers.
+    Snapshot,
+    /// Sends the traffic per peer within a window of the persisted history,
+    /// like `/history`.
+    History { from: Option<u64>, to: Option<u64> },
+}
+
+/// Answers to control messages that are not events.
+#[derive(Debug, Serialize)]
+pub enum Reply {
+    #[serde(rename = "history")]
+    History(Vec<PeerHistory>),
+    #[serde(rename = "error")]
+    Error(String),
+}
+
+/// What one WebSocket client subscribed to. The same [`Event`] JSON as on
+/// `/events` is sent, interleaved with [`Reply`]s.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// `None` while unsubscribed.
+    filter: Option<EventFilter>,
+    paused: bool,
+}
+
+impl Session {
+    pub fn new(filter: EventFilter) -> Self {
+        Self {
+            filter: Some(filter),
+            paused: false,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    /// Whether a live event is sent to the client.
+    pub fn wants(&self, event: &Event) -> bool {
+        !self.paused
+            && self
+                .filter
+                .as_ref()
+                .is_some_and(|filter| filter.matches(event))
+    }
+
+    /// The matching peers, nothing while unsubscribed or paused.
+    pub async fn snapshot(&self, state: &AppState) -> Vec<String> {
+        match &self.filter {
+            Some(filter) if !self.paused => {
+                snapshot(state, filter).await.iter().map(to_json).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Handles a text message of the client, returning the messages to send
+    /// back.
+    pub async fn control(&mut self, state: &AppState, text: &str) -> Vec<String> {
+        let control = match serde_json::from_str::<Control>(text) {
+            Ok(control) => control,
+            Err(err) => return vec![to_json(&Reply::Error(err.to_string()))],
+        };
+
+        match control {
+            Control::Subscribe { filter } => match EventFilter::try_from(filter) {
+                Ok(filter) => {
+                    self.filter = Some(filter);
+                    self.snapshot(state).await
+                }
+                Err(err) => vec![to_json(&Reply::Error(err.to_string()))],
+            },
+            Control::Unsubscribe => {
+                self.filter = None;
+                Vec::new()
+            }
+            Control::Pause => {
+                self.paused = true;
+                Vec::new()
+            }
+            Control::Resume => {
+                self.paused = false;
+                self.snapshot(state).await
+            }
+            Control::Snapshot => self.snapshot(state).await,
+            Control::History { from, to } => {
+                let Some(history) = &state.history else {
+                    return vec![to_json(&Reply::Error("history is not enabled".to_string()))];
+                };
+
+                let (from, to) = match (HistoryQuery { from, to }.window()) {
+                    Ok(window) => window,
+                    Err(err) => return vec![to_json(&Reply::Error(err.to_string()))],
+                };
+                let reply = match query_history(history.clone(), from, to).await {
+                    Ok(peers) => Reply::History(peers),
+                    Err(err) => Reply::Error(err.to_string()),
+                };
+                vec![to_json(&reply)]
+            }
+        }
+    }
+}
+
+fn to_json<T>(value: &T) -> String
+where
+    T: Serialize,
+{
+    serde_json::to_string(value).unwrap()
+}
+
+/// Upgrades to a WebSocket session, the query parameters of `/events` set
+/// the initial filter.
+pub(crate) async fn upgrade(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<EventQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    match EventFilter::try_from(query) {
+        Ok(filter) => upgrade.on_upgrade(move |socket| run(state, socket, Session::new(filter))),
+        Err(err) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+    }
+}
+
+async fn run(state: Arc<AppState>, mut socket: WebSocket, mut session: Session) {
+    let mut rx = state.tx.subscribe();
+    let mut messages = session.snapshot(&state).await;
+
+    loop {
+        for message in messages.drain(..) {
+            if socket.send(Message::Text(message.into())).await.is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    messages = session.control(&state, text.as_str()).await;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+            event = rx.recv() => match event {
+                Ok(event) => {
+                    if session.wants(&event) {
+                        messages.push(to_json(&event));
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    state.metrics.subscribers_lagged.fetch_add(1, Ordering::Relaxed);
+                    state.metrics.events_dropped.fetch_add(skipped, Ordering::Relaxed);
+
+                    if session.is_subscribed() && !session.is_paused() {
+                        messages.push(to_json(&Event::Lagged(Lagged { skipped })));
+                        messages.extend(session.snapshot(&state).await);
+                    }
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::Session;
+    use crate::{
+        event::{Event, PeerRemoved, RemovalReason},
+        fixtures::{self, LOCAL},
+        server::AppState,
+        subscription::EventFilter,
+    };
+
+    fn state() -> AppState {
+        AppState::new(fixtures::peer(LOCAL, "AT"))
+    }
+
+    fn removed(addr: [u8; 4]) -> Event {
+        Event::PeerRemoved(PeerRemoved {
+            addr: IpAddr::from(addr),
+            reason: RemovalReason::Idle,
+        })
+    }
+
+    #[tokio::test]
+    async fn steers_live_events() {
+        let state = state();
+        let mut session = Session::new(EventFilter::default());
+        assert!(session.wants(&removed([1, 1, 1, 1])));
+
+        let replies = session
+            .control(
+                &state,
+                r#"{"type": "subscribe", "filter": {"cidr": "10.0.0.0/8"}}"#,
+            )
+            .await;
+        assert!(replies.is_empty(), "the local peer is not in 10.0.0.0/8");
+        assert!(!session.wants(&removed([1, 1, 1, 1])));
+        assert!(session.wants(&removed([10, 1, 1, 1])));
+
+        session.control(&state, r#"{"type": "pause"}"#).await;
+        assert!(!session.wants(&removed([10, 1, 1, 1])));
+
+        session.control(&state, r#"{"type": "resume"}"#).await;
+        assert!(session.wants(&removed([10, 1, 1, 1])));
+
+        session.control(&state, r#"{"type": "unsubscribe"}"#).await;
+        assert!(!session.wants(&removed([10, 1, 1, 1])));
+    }
+
+    #[tokio::test]
+    async fn answers_requests() {
+        let state = state();
+        let mut session = Session::new(EventFilter::default());
+
+        let replies = session.control(&state, r#"{"type": "snapshot"}"#).await;
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].starts_with(r#"{"peer":{"addr":"192.0.2.1""#));
+
+        let replies = session
+            .control(&state, r#"{"type": "history", "from": 0}"#)
+            .await;
+        assert_eq!(replies, [r#"{"error":"history is not enabled"}"#]);
+
+        let replies = session
+            .control(
+                &state,
+                r#"{"type": "subscribe", "filter": {"port": "http"}}"#,
+            )
+            .await;
+        assert_eq!(replies, [r#"{"error":"invalid port: \"http\""}"#]);
+
+        let replies = session.control(&state, r#"{"type": "rewind"}"#).await;
+        assert!(replies[0].starts_with(r#"{"error":"unknown variant `rewind`"#));
+    }
+}
@@ -1,10 +1,10 @@
 import { midpoint } from "$lib/utils/geo";
 import { Color, Path, Polyline, Vec3, type OGLRenderingContext } from "ogl";
 
-type TraceOptions = { from: Vec3; to: Vec3 };
+type TraceOptions = { from: Vec3; to: Vec3; color: string };
 
 export class Trace extends Polyline {
-	constructor(gl: OGLRenderingContext, { from = new Vec3(), to = new Vec3() }: Partial<TraceOptions> = {}) {
+	constructor(gl: OGLRenderingContext, { from = new Vec3(), to = new Vec3(), color = "#DE3163" }: Partial<TraceOptions> = {}) {
 		const mid = midpoint(from, to);
 		const c1 = midpoint(from, mid).scale(1.4);
 		const c2 = midpoint(mid, to).scale(1.4);
@@ -17,7 +17,7 @@ export class Trace extends Polyline {
 			points: path.getPoints(256),
 			uniforms: {
 				uThickness: { value: 5 },
-				uColor: { value: new Color("#DE3163") },
+				uColor: { value: new Color(color) },
 			},
 		});
 	}
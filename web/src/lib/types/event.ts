@@ -1,4 +1,12 @@
+export type Traffic = { ingress_bytes: number; egress_bytes: number };
+
 export class Peer {
+	/** Last ICMP message seen from this peer, only tracked client side. */
+	public lastIcmp: string | undefined = undefined;
+
+	/** Set once the server dropped the peer, it is faded out before removal. */
+	public removed = false;
+
 	constructor(
 		public addr: string,
 		public info:
@@ -25,6 +33,8 @@ export class Peer {
 		public ingress_bytes: number,
 		public egress_bytes: number,
 		public last_message: Date,
+		public interfaces: Record<string, Traffic>,
+		public workloads: Record<string, Traffic>,
 	) {}
 
 	get active() {
@@ -38,19 +48,67 @@ export class Peer {
 			obj.ingress_bytes,
 			obj.egress_bytes,
 			new Date(obj.last_message.secs_since_epoch * 1000 + obj.last_message.nanos_since_epoch / 1_000_000),
+			obj.interfaces ?? {},
+			obj.workloads ?? {},
 		);
 	}
 }
 
+export type Proto = { number: number; name: string | null };
+
+export type Icmp = { type: number; code: number };
+
+export type Workload = { name: string; namespace: string | null; container_id: string | null };
+
+/** Key of a workload in `Peer.workloads`. */
+export function workloadKey(workload: Workload): string {
+	return workload.namespace ? `${workload.namespace}/${workload.name}` : workload.name;
+}
+
+export type Process = { pid: number; tid: number; uid: number; cgroup_id: number; comm: string };
+
+/** Entry of the `/processes` endpoint. */
+export type ProcessTraffic = {
+	process: Process;
+	ingress_bytes: number;
+	egress_bytes: number;
+	countries: Record<string, Traffic>;
+};
+
+export type Bucket = { start: number; ingress_bytes: number; egress_bytes: number; packets: number };
+
+/** Entry of the `/rates` endpoint, ingress and egress are from the local host's point of view. */
+export type Rates = {
+	kind: "peer" | "country" | "port";
+	key: string;
+	resolution: "second" | "minute" | "hour";
+	ingress_bytes_per_sec: number;
+	egress_bytes_per_sec: number;
+	packets_per_sec: number;
+	buckets: Bucket[];
+};
+
 export class Packet {
 	constructor(
-		public proto: string,
+		public proto: Proto,
 		public src_addr: string,
 		public src_location: Location,
 		public dst_addr: string,
 		public dst_location: Location,
+		public src_port: number | null,
+		public dst_port: number | null,
+		public direction: "ingress" | "egress",
+		public country_code: string,
+		public packets: number,
 		public bytes: number,
+		public wire_bytes: number,
 		public timestamp: Date,
+		public iface: string | null,
+		public icmp: Icmp | null,
+		public process: Process | null,
+		public workload: Workload | null,
+		public capture_sample_rate: number,
+		public sample_rate: number,
 	) {}
 
 	static fromJSON(obj: any): Packet {
@@ -60,10 +118,64 @@ export class Packet {
 			obj.src_location,
 			obj.dst_addr,
 			obj.dst_location,
+			obj.src_port ?? null,
+			obj.dst_port ?? null,
+			obj.direction,
+			obj.country_code,
+			obj.packets ?? 1,
 			obj.bytes,
+			obj.wire_bytes ?? obj.bytes,
 			new Date(obj.timestamp.secs_since_epoch * 1000 + obj.timestamp.nanos_since_epoch / 1_000_000),
+			obj.interface ?? null,
+			obj.icmp ?? null,
+			obj.process ?? null,
+			obj.workload ?? null,
+			obj.capture_sample_rate ?? 1,
+			obj.sample_rate ?? 1,
 		);
 	}
 }
 
-export type Event = { peer: Peer; packet?: never } | { packet: Packet; peer?: never };
+/** Packets exchanged with one peer within the batching interval, counted from the local host's point of view. */
+export type PacketBatch = {
+	local_addr: string;
+	peer_addr: string;
+	country_code: string;
+	events: number;
+	packets: number;
+	ingress_bytes: number;
+	egress_bytes: number;
+	protocols: Record<string, number>;
+	ports: number[];
+	first_timestamp: { secs_since_epoch: number; nanos_since_epoch: number };
+	last_timestamp: { secs_since_epoch: number; nanos_since_epoch: number };
+	interfaces: Record<string, Traffic>;
+	workloads: Record<string, Traffic>;
+};
+
+export type PeerRemoved = { addr: string; reason: "idle" | "evicted" };
+
+/** Sent when this client fell behind, the peer table follows it. */
+export type Lagged = { skipped: number };
+
+/** Counters of the eBPF classifiers, sent when they missed packets. */
+export type CaptureStats = {
+	events_emitted: number;
+	ring_buffer_dropped: number;
+	filtered: number;
+	sampled_out: number;
+	parse_failures: {
+		truncated: number;
+		unknown_ether_type: number;
+		ip_version_mismatch: number;
+		extension_header_overflow: number;
+	};
+};
+
+export type Event =
+	| { peer: Peer; packet?: never; packet_batch?: never; peer_removed?: never; lagged?: never; capture?: never }
+	| { packet: Packet; peer?: never; packet_batch?: never; peer_removed?: never; lagged?: never; capture?: never }
+	| { packet_batch: PacketBatch; peer?: never; packet?: never; peer_removed?: never; lagged?: never; capture?: never }
+	| { peer_removed: PeerRemoved; peer?: never; packet?: never; packet_batch?: never; lagged?: never; capture?: never }
+	| { lagged: Lagged; peer?: never; packet?: never; packet_batch?: never; peer_removed?: never; capture?: never }
+	| { capture: CaptureStats; peer?: never; packet?: never; packet_batch?: never; peer_removed?: never; lagged?: never };
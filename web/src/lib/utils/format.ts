@@ -1,3 +1,5 @@
+import type { Icmp, Proto } from "$lib/types/event";
+
 /**
  * @see https://stackoverflow.com/questions/10420352/converting-file-size-in-bytes-to-human-readable-string
  */
@@ -27,6 +29,34 @@ export function formatFlag(countryCode: string): String {
 		.join("");
 }
 
+const ICMP_MESSAGES: Record<string, string> = {
+	"0": "Echo reply",
+	"3": "Destination unreachable",
+	"3/4": "Fragmentation needed",
+	"5": "Redirect",
+	"8": "Echo request",
+	"11": "Time exceeded",
+};
+
+const ICMPV6_MESSAGES: Record<string, string> = {
+	"1": "Destination unreachable",
+	"2": "Packet too big",
+	"3": "Time exceeded",
+	"128": "Echo request",
+	"129": "Echo reply",
+	"133": "Router solicitation",
+	"134": "Router advertisement",
+	"135": "Neighbor solicitation",
+	"136": "Neighbor advertisement",
+};
+
+export function formatIcmp(proto: Proto, icmp: Icmp): string {
+	const messages = proto.number === 58 ? ICMPV6_MESSAGES : ICMP_MESSAGES;
+	const message = messages[`${icmp.type}/${icmp.code}`] ?? messages[`${icmp.type}`];
+
+	return message ?? `${proto.name ?? proto.number} type ${icmp.type} code ${icmp.code}`;
+}
+
 export const relativeTimeFormatter = new Intl.RelativeTimeFormat("en", {
 	style: "narrow",
 	numeric: "auto",